@@ -0,0 +1,169 @@
+use std::path::Path;
+
+use rayon::prelude::*;
+
+const METERS_PER_DEGREE: f64 = 111320.0; // Along a meridian, or along the equator
+const VOID: i16 = -32768; // What SRTM tiles have where they have no data
+const LIGHT_AZIMUTH: f64 = 315.0; // Degrees clockwise from north that the light shines from, which is northwest as on paper maps
+const LIGHT_ALTITUDE: f64 = 45.0; // Degrees above the horizon
+
+// A grid of elevations in meters, evenly spaced in latitude and longitude, with rows running from
+// north to south
+#[derive(Debug, Clone)]
+pub struct Dem {
+	north: f64, // Latitude of the first row of samples
+	west: f64, // Longitude of the first column
+	step: (f64, f64), // Degrees between rows and between columns
+	width: usize,
+	height: usize,
+	data: Vec<f32>, // NaN where there's no data
+}
+
+impl Dem {
+	// Reads an SRTM .hgt tile, which has to keep its usual name since that's the only place its
+	// position is given, or an ESRI ASCII grid in latitude and longitude
+	pub fn from_file(path: &Path) -> Result<Self, String> {
+		let ext = path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase());
+		match ext.as_deref() {
+			Some("hgt") => {
+				let name = path.file_stem().and_then(|name| name.to_str()).unwrap_or("");
+				Self::parse_hgt(name, &std::fs::read(path).map_err(|e| e.to_string())?)
+			},
+			Some("asc") => Self::parse_asc(&std::fs::read_to_string(path).map_err(|e| e.to_string())?),
+			_ => Err("elevation data has to be an SRTM .hgt tile or an ESRI ASCII .asc grid".to_string()),
+		}
+	}
+
+	// Square grids of big-endian 16-bit samples covering one degree, edges included, named after
+	// their southwest corner like N45E006
+	fn parse_hgt(name: &str, data: &[u8]) -> Result<Self, String> {
+		let bad_name = || format!("{} isn't named for its corner, like N45E006", name);
+		let name = name.to_ascii_uppercase();
+		if name.len() != 7 || !name.is_ascii() { return Err(bad_name()); }
+		let lat = name[1..3].parse::<f64>().map_err(|_| bad_name())?;
+		let lon = name[4..7].parse::<f64>().map_err(|_| bad_name())?;
+		let lat = match &name[0..1] { "N" => lat, "S" => -lat, _ => return Err(bad_name()) };
+		let lon = match &name[3..4] { "E" => lon, "W" => -lon, _ => return Err(bad_name()) };
+		let size = ((data.len() / 2) as f64).sqrt().round() as usize;
+		if size < 2 || size * size * 2 != data.len() { return Err("SRTM tile isn't a square grid".to_string()); }
+		let data = data.chunks_exact(2).map(|sample| match i16::from_be_bytes([sample[0], sample[1]]) {
+			VOID => f32::NAN,
+			height => height as f32,
+		}).collect();
+		let step = 1.0 / (size - 1) as f64;
+		Ok(Self { north: lat + 1.0, west: lon, step: (step, step), width: size, height: size, data })
+	}
+
+	// A header of keys and values followed by rows of samples, with the position given by either
+	// the corner or the center of the southwest cell
+	fn parse_asc(text: &str) -> Result<Self, String> {
+		let mut words = text.split_whitespace().peekable();
+		let mut header = std::collections::HashMap::new();
+		while let Some(key) = words.next_if(|word| word.starts_with(|c: char| c.is_ascii_alphabetic())) {
+			let value = words.next().ok_or(format!("{} has no value", key))?;
+			header.insert(key.to_ascii_lowercase(), value.parse::<f64>().map_err(|e| format!("{}: {}", key, e))?);
+		}
+		let get = |key: &str| header.get(key).copied().ok_or(format!("grid has no {}", key));
+		let (width, height, cell) = (get("ncols")? as usize, get("nrows")? as usize, get("cellsize")?);
+		if width < 2 || height < 2 || cell.is_nan() || cell <= 0.0 { return Err("grid is too small".to_string()); }
+		// Corners are a half cell further out than the centers of the samples
+		let west = get("xllcenter").or_else(|_| get("xllcorner").map(|x| x + cell / 2.0))?;
+		let south = get("yllcenter").or_else(|_| get("yllcorner").map(|y| y + cell / 2.0))?;
+		let nodata = header.get("nodata_value").copied();
+		let data = words.map(|word| match word.parse::<f64>() {
+			Ok(height) if Some(height) == nodata => Ok(f32::NAN),
+			Ok(height) => Ok(height as f32),
+			Err(e) => Err(format!("{}: {}", word, e)),
+		}).collect::<Result<Vec<_>, _>>()?;
+		if data.len() != width * height { return Err(format!("grid should have {} samples but has {}", width * height, data.len())); }
+		Ok(Self { north: south + (height - 1) as f64 * cell, west, step: (cell, cell), width, height, data })
+	}
+
+	fn sample(&self, row: usize, col: usize) -> f32 {
+		self.data[row * self.width + col]
+	}
+
+	// Height in meters at a position, interpolated between the samples around it, or None outside
+	// the grid or next to a gap in it
+	pub fn elevation(&self, lat: f64, lon: f64) -> Option<f32> {
+		let row = (self.north - lat) / self.step.0;
+		let col = (lon - self.west) / self.step.1;
+		if row < 0.0 || col < 0.0 || row > (self.height - 1) as f64 || col > (self.width - 1) as f64 { return None; }
+		let (top, left) = ((row as usize).min(self.height - 2), (col as usize).min(self.width - 2));
+		let (dy, dx) = ((row - top as f64) as f32, (col - left as f64) as f32);
+		// Samples that don't count at all are left out, so a gap next to a sample doesn't hide it
+		let lerp = |a: f32, b: f32, t: f32| if t == 0.0 { a } else if t == 1.0 { b } else { a * (1.0 - t) + b * t };
+		let upper = lerp(self.sample(top, left), self.sample(top, left + 1), dx);
+		let lower = lerp(self.sample(top + 1, left), self.sample(top + 1, left + 1), dx);
+		Some(lerp(upper, lower, dy)).filter(|height| !height.is_nan())
+	}
+
+	// How brightly lit the ground is at a position, from 0 for slopes facing away from the light to
+	// 1 for flat ground and slopes facing it, or None where there's no data
+	pub fn shade(&self, lat: f64, lon: f64) -> Option<f32> {
+		let center = self.elevation(lat, lon)?;
+		// Differences a sample either side, or one side only at the edges and next to gaps
+		let slope = |before: Option<f32>, after: Option<f32>, step: f64, meters: f64| {
+			let steps = before.is_some() as u8 + after.is_some() as u8;
+			if steps == 0 { return 0.0; }
+			(after.unwrap_or(center) - before.unwrap_or(center)) as f64 / (steps as f64 * step * meters)
+		};
+		let (dlat, dlon) = self.step;
+		let east = slope(self.elevation(lat, lon - dlon), self.elevation(lat, lon + dlon), dlon, METERS_PER_DEGREE * lat.to_radians().cos());
+		let north = slope(self.elevation(lat - dlat, lon), self.elevation(lat + dlat, lon), dlat, METERS_PER_DEGREE);
+		let (azimuth, altitude) = (LIGHT_AZIMUTH.to_radians(), LIGHT_ALTITUDE.to_radians());
+		let light = (azimuth.sin() * altitude.cos(), azimuth.cos() * altitude.cos(), altitude.sin());
+		let lit = (-east * light.0 - north * light.1 + light.2) / (east * east + north * north + 1.0).sqrt();
+		Some((lit / altitude.sin()).clamp(0.0, 1.0) as f32)
+	}
+
+	// Gray RGBA pixels shading a grid of positions, a row for each latitude and a column for each
+	// longitude, white where there's no data so that it can be multiplied over a map
+	pub fn relief(&self, lats: &[f64], lons: &[f64]) -> Vec<u8> {
+		// A window's worth is hundreds of thousands of positions, so rows are shaded in parallel
+		lats.par_iter().flat_map_iter(|lat| lons.iter().flat_map(move |lon| {
+			let gray = (self.shade(*lat, *lon).unwrap_or(1.0) * 255.0).round() as u8;
+			[gray, gray, gray, 255]
+		})).collect()
+	}
+}
+
+#[test]
+fn test_parse() {
+	let asc = "ncols 3\nnrows 2\nxllcorner 10.0\nyllcorner 45.0\ncellsize 0.5\nNODATA_value -9999\n1 2 3\n4 -9999 6\n";
+	let dem = Dem::parse_asc(asc).unwrap();
+	assert_eq!((dem.width, dem.height, dem.north, dem.west), (3, 2, 45.75, 10.25));
+	assert_eq!(dem.elevation(45.75, 10.25), Some(1.0));
+	assert_eq!(dem.elevation(45.75, 10.5), Some(1.5));
+	assert_eq!(dem.elevation(45.25, 11.25), Some(6.0));
+	assert_eq!(dem.elevation(45.5, 10.5), None); // Next to the gap
+	assert_eq!(dem.elevation(46.0, 10.5), None); // Off the top
+	assert!(Dem::parse_asc("ncols 3\nnrows 2\nxllcorner 10\nyllcorner 45\ncellsize 0.5\n1 2 3\n").is_err());
+	let hgt = [0u8, 10, 0, 20, 0x80, 0, 0, 40];
+	let dem = Dem::parse_hgt("S01W002", &hgt).unwrap();
+	assert_eq!((dem.north, dem.west, dem.step), (0.0, -2.0, (1.0, 1.0)));
+	assert_eq!(dem.elevation(0.0, -1.5), Some(15.0));
+	assert_eq!(dem.elevation(-1.0, -2.0), None);
+	assert!(Dem::parse_hgt("N45E006", &hgt[..6]).is_err());
+	assert!(Dem::parse_hgt("tile", &hgt).is_err());
+}
+
+#[test]
+fn test_shade() {
+	let grid = |rows: [[i32; 3]; 3]| {
+		let samples = rows.iter().flatten().map(|height| height.to_string()).collect::<Vec<_>>().join(" ");
+		Dem::parse_asc(&format!("ncols 3 nrows 3 xllcenter 0 yllcenter 0 cellsize 0.001 {}", samples)).unwrap()
+	};
+	assert_eq!(grid([[5; 3]; 3]).shade(0.001, 0.001), Some(1.0));
+	// Sloping down towards the northwest is lit, and away from it shadowed
+	assert_eq!(grid([[0, 100, 200]; 3]).shade(0.001, 0.001), Some(1.0));
+	let shadowed = grid([[200, 100, 0]; 3]);
+	let dark = shadowed.shade(0.001, 0.001).unwrap();
+	assert!(dark < 0.5);
+	// With no data off the edge of the grid left white
+	let gray = (dark * 255.0).round() as u8;
+	assert_eq!(shadowed.relief(&[0.001, 1.0], &[0.001]), vec![gray, gray, gray, 255, 255, 255, 255, 255]);
+	// Row by row, whichever order they're shaded in
+	let white = [255; 4];
+	assert_eq!(shadowed.relief(&[0.001, 1.0], &[0.001, 1.0]), [[gray, gray, gray, 255], white, white, white].concat());
+}
@@ -0,0 +1,36 @@
+use skulpin::skia_safe::{Canvas, Color4f, Paint, Rect, Shader, TileMode};
+
+// Where the fade starts and ends, as fractions of the distance from the center of the frame to
+// its corners.  None if there's nothing to draw.
+fn vignette_stops(strength: f32) -> Option<[f32; 2]> {
+	let strength = strength.clamp(0.0, 1.0);
+	if strength == 0.0 { None }
+	else { Some([1.0 - strength, 1.0]) }
+}
+
+// Fade the edges of a finished image into the background color.  Strength runs from 0, which
+// leaves the image alone, to 1, where the fade starts right at the center.  The frame is the whole
+// image, which can be bigger than the canvas when it's one of several pieces, like exported tiles.
+// This should be drawn after everything else.
+pub fn draw_vignette(canvas: &mut Canvas, frame: Rect, background: Color4f, strength: f32) {
+	let stops = match vignette_stops(strength) {
+		Some(stops) => stops,
+		None => return,
+	};
+	let center = ((frame.left + frame.right) / 2.0, (frame.top + frame.bottom) / 2.0);
+	let (half_width, half_height) = ((frame.right - frame.left) / 2.0, (frame.bottom - frame.top) / 2.0);
+	let radius = (half_width * half_width + half_height * half_height).sqrt();
+	let colors = [background.with_a(0.0).to_color(), background.with_a(1.0).to_color()];
+	let mut paint = Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None);
+	paint.set_anti_alias(true);
+	paint.set_shader(Shader::radial_gradient(center, radius, &colors[..], &stops[..], TileMode::Clamp, None, None));
+	canvas.draw_rect(frame, &paint);
+}
+
+#[test]
+fn test_vignette_stops() {
+	assert_eq!(vignette_stops(0.0), None);
+	assert_eq!(vignette_stops(-1.0), None);
+	assert_eq!(vignette_stops(0.25), Some([0.75, 1.0]));
+	assert_eq!(vignette_stops(2.0), Some([0.0, 1.0]));
+}
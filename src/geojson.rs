@@ -0,0 +1,105 @@
+use std::io::{self, Write};
+
+use super::mapsforge::Coord;
+use super::osm::touches;
+use super::render::{BoundingBox, Geometry, Object};
+
+fn escape(text: &str) -> String {
+	let mut ret = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			'"' => ret.push_str("\\\""),
+			'\\' => ret.push_str("\\\\"),
+			'\n' => ret.push_str("\\n"),
+			c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+			c => ret.push(c),
+		}
+	}
+	ret
+}
+
+// GeoJSON puts longitude first
+fn position(point: Coord) -> String {
+	let (lat, lon) = point.to_latlon().degrees();
+	format!("[{:.6},{:.6}]", lon, lat)
+}
+
+fn line(points: &[Coord]) -> String {
+	format!("[{}]", points.iter().map(|point| position(*point)).collect::<Vec<_>>().join(","))
+}
+
+fn geometry(geo: &Geometry) -> String {
+	match geo {
+		Geometry::Point(point) => format!("{{\"type\":\"Point\",\"coordinates\":{}}}", position(*point)),
+		Geometry::Path(polies) => {
+			let lines = polies.iter().map(|poly| line(poly)).collect::<Vec<_>>().join(",");
+			if polies.len() == 1 { format!("{{\"type\":\"LineString\",\"coordinates\":{}}}", lines) }
+			else { format!("{{\"type\":\"MultiLineString\",\"coordinates\":[{}]}}", lines) }
+		},
+		Geometry::Area(polies) => {
+			// As in the map, the first ring is the outline and the rest are holes in it.  GeoJSON
+			// wants every ring to end where it starts, which ways tagged as areas might not.
+			let rings = polies.iter().map(|poly| {
+				let mut ring = poly.clone();
+				if ring.first() != ring.last() { ring.push(ring[0]); }
+				line(&ring)
+			}).collect::<Vec<_>>().join(",");
+			format!("{{\"type\":\"Polygon\",\"coordinates\":[{}]}}", rings)
+		},
+	}
+}
+
+// Write the objects that touch the area as a GeoJSON FeatureCollection, one feature per line,
+// returning how many were written.  Each feature has the object's name and material, and its tags
+// as an object of their own.  Like the OSM export, ways come out in pieces cut at tile edges.
+pub fn write_geojson<'a>(objects: impl IntoIterator<Item = &'a Object>, area: &BoundingBox, out: &mut impl Write) -> io::Result<usize> {
+	let mut count = 0;
+	writeln!(out, "{{\"type\":\"FeatureCollection\",\"features\":[")?;
+	for obj in objects {
+		if !touches(&obj.geo, area) { continue; }
+		let mut tags = obj.tags.iter().map(|(key, value)| (key.clone(), value.to_string())).collect::<Vec<_>>();
+		tags.sort();
+		let tags = tags.iter().map(|(key, value)| format!("\"{}\":\"{}\"", escape(key), escape(value))).collect::<Vec<_>>().join(",");
+		let name = obj.name.as_ref().map_or("null".to_string(), |name| format!("\"{}\"", escape(name)));
+		if count > 0 { writeln!(out, ",")?; }
+		write!(out, "{{\"type\":\"Feature\",\"geometry\":{},\"properties\":{{\"name\":{},\"material\":\"{}\",\"tags\":{{{}}}}}}}", geometry(&obj.geo), name, escape(obj.material.name()), tags)?;
+		count += 1;
+	}
+	if count > 0 { writeln!(out)?; }
+	writeln!(out, "]}}")?;
+	Ok(count)
+}
+
+#[test]
+fn test_write_geojson() {
+	use super::mapsforge::{LatLon, TagValue};
+	let c = |lat, lon| LatLon::from_degrees(lat, lon).to_coord();
+	let obj = |geo, tags: &[(&str, &str)], name: Option<&str>| Object {
+		geo,
+		name: name.map(|name| name.to_string()),
+		tags: std::sync::Arc::new(tags.iter().map(|(key, value)| (key.to_string(), TagValue::Literal(value.to_string()))).collect()),
+		material: Default::default(),
+		subtiles: 0,
+		label_pos: None,
+	};
+	let ring = vec![c(51.51, -0.11), c(51.51, -0.106), c(51.506, -0.106), c(51.506, -0.11), c(51.51, -0.11)];
+	let objects = vec![
+		obj(Geometry::Point(c(51.5, -0.1)), &[("amenity", "cafe")], Some("The \"Bean\"")),
+		obj(Geometry::Path(vec![vec![c(51.5, -0.1), c(51.501, -0.1)]]), &[("highway", "primary")], None),
+		obj(Geometry::Area(vec![ring]), &[("building", "yes")], None),
+		obj(Geometry::Area(vec![vec![c(51.5, -0.1), c(51.5, -0.099), c(51.499, -0.099)]]), &[("area", "yes")], None),
+		obj(Geometry::Point(c(48.85, 2.35)), &[("amenity", "cafe")], None), // Off in Paris
+	];
+	let area = BoundingBox::from_corners((c(51.52, -0.12), c(51.49, -0.09)));
+	let mut out = vec![];
+	assert_eq!(write_geojson(&objects, &area, &mut out).unwrap(), 4);
+	let json = String::from_utf8(out).unwrap();
+	assert!(json.starts_with("{\"type\":\"FeatureCollection\",\"features\":[\n"));
+	assert!(json.contains("{\"type\":\"Point\",\"coordinates\":[-0.100000,51.500000]}"));
+	assert!(json.contains("\"properties\":{\"name\":\"The \\\"Bean\\\"\",\"material\":\"\",\"tags\":{\"amenity\":\"cafe\"}}"));
+	assert!(json.contains("{\"type\":\"LineString\",\"coordinates\":[[-0.100000,51.500000],[-0.100000,51.501000]]}"));
+	assert!(json.contains("{\"type\":\"Polygon\",\"coordinates\":[[[-0.110000,51.510000],"));
+	assert!(json.contains("[[[-0.100000,51.500000],[-0.099000,51.500000],[-0.099000,51.499000],[-0.100000,51.500000]]]")); // Closed up
+	assert!(!json.contains("48.85"));
+	assert!(json.ends_with("}}}\n]}\n"));
+}
@@ -0,0 +1,64 @@
+use mapviewer::render::Object;
+
+// Picks out features to highlight.  "key=value" matches features with that tag, "key=" any
+// feature that has the tag at all, and anything else features whose name contains it.  Matching
+// ignores case.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+	Name(String),
+	Tag(String, Option<String>),
+}
+
+impl Query {
+	pub fn parse(query: &str) -> Self {
+		match query.split_once('=') {
+			Some((key, "")) => Query::Tag(key.trim().to_string(), None),
+			Some((key, value)) => Query::Tag(key.trim().to_string(), Some(value.trim().to_lowercase())),
+			None => Query::Name(query.trim().to_lowercase()),
+		}
+	}
+
+	pub fn matches(&self, obj: &Object) -> bool {
+		match self {
+			Query::Name(text) => obj.name.as_ref().is_some_and(|name| name.to_lowercase().contains(text.as_str())),
+			Query::Tag(key, value) => match (obj.tags.iter().find(|(tag, _)| tag.eq_ignore_ascii_case(key)).map(|(_, tag)| tag), value) {
+				(Some(_), None) => true,
+				(Some(tag), Some(value)) => tag.to_string().to_lowercase() == *value,
+				(None, _) => false,
+			},
+		}
+	}
+}
+
+impl std::fmt::Display for Query {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Query::Name(text) => write!(f, "{}", text),
+			Query::Tag(key, value) => write!(f, "{}={}", key, value.as_deref().unwrap_or("")),
+		}
+	}
+}
+
+#[test]
+fn test_query() {
+	use mapviewer::mapsforge::{Coord, TagValue};
+	use mapviewer::render::Geometry;
+	let obj = Object {
+		geo: Geometry::Point(Coord { x: 0, y: 0 }),
+		name: Some("Main Street".to_string()),
+		tags: std::sync::Arc::new(vec![("highway".to_string(), TagValue::Literal("residential".to_string())), ("lanes".to_string(), TagValue::Byte(2))].into_iter().collect()),
+		material: Default::default(),
+		subtiles: 0,
+		label_pos: None,
+	};
+	assert!(Query::parse("main st").matches(&obj));
+	assert!(!Query::parse("Starbucks").matches(&obj));
+	assert_eq!(Query::parse("highway=Residential"), Query::Tag("highway".to_string(), Some("residential".to_string())));
+	assert!(Query::parse("highway=Residential").matches(&obj));
+	assert!(Query::parse("lanes=2").matches(&obj));
+	assert!(Query::parse("highway=").matches(&obj));
+	assert!(Query::parse("Highway=residential").matches(&obj));
+	assert!(Query::parse("LANES=").matches(&obj));
+	assert!(!Query::parse("highway=primary").matches(&obj));
+	assert!(!Query::parse("building=").matches(&obj));
+}
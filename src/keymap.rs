@@ -0,0 +1,192 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use sdl2::keyboard::{Keycode, Mod};
+
+// Everything a key can do other than bookmarks and layers, which use the number keys with
+// modifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+	ZoomIn,
+	ZoomOut,
+	PanLeft,
+	PanRight,
+	PanUp,
+	PanDown,
+	ResetView,
+	Quit,
+	SwitchTheme,
+	Grayscale,
+	Subtiles,
+	Grid,
+	FainterOverlay,
+	StrongerOverlay,
+	Cancel,
+	CopyLink,
+	Elevation,
+	NoDataPattern,
+	ExportOsm,
+	Screenshot,
+	ExportGeojson,
+	Heatmap,
+	Legend,
+	Measure,
+	Fullscreen,
+	NightMode,
+	Help,
+}
+
+// Names for the key file, and the keys each action has unless the file says otherwise
+const ACTIONS: [(Action, &str, &[Keycode]); 27] = [
+	(Action::ZoomIn, "zoom_in", &[Keycode::Equals, Keycode::KpPlus]),
+	(Action::ZoomOut, "zoom_out", &[Keycode::Minus, Keycode::KpMinus]),
+	(Action::PanLeft, "pan_left", &[Keycode::Left, Keycode::H]),
+	(Action::PanRight, "pan_right", &[Keycode::Right, Keycode::L]),
+	(Action::PanUp, "pan_up", &[Keycode::Up, Keycode::K]),
+	(Action::PanDown, "pan_down", &[Keycode::Down, Keycode::J]),
+	(Action::ResetView, "reset_view", &[Keycode::Num0]),
+	(Action::Quit, "quit", &[Keycode::Q]),
+	(Action::SwitchTheme, "switch_theme", &[Keycode::A]),
+	(Action::Grayscale, "grayscale", &[Keycode::P]),
+	(Action::Subtiles, "subtiles", &[Keycode::S]),
+	(Action::Grid, "grid", &[Keycode::G]),
+	(Action::FainterOverlay, "fainter_overlay", &[Keycode::LeftBracket]),
+	(Action::StrongerOverlay, "stronger_overlay", &[Keycode::RightBracket]),
+	(Action::Cancel, "cancel", &[Keycode::Escape]),
+	(Action::CopyLink, "copy_link", &[Keycode::C]),
+	(Action::Elevation, "elevation", &[Keycode::E]),
+	(Action::NoDataPattern, "no_data_pattern", &[Keycode::N]),
+	(Action::ExportOsm, "export_osm", &[Keycode::X]),
+	(Action::Screenshot, "screenshot", &[Keycode::I]),
+	(Action::ExportGeojson, "export_geojson", &[Keycode::V]),
+	(Action::Heatmap, "heatmap", &[Keycode::D]),
+	(Action::Legend, "legend", &[Keycode::F4]),
+	(Action::Measure, "measure", &[Keycode::M]),
+	(Action::Fullscreen, "fullscreen", &[Keycode::F11, Keycode::F]),
+	(Action::NightMode, "night_mode", &[Keycode::B]),
+	(Action::Help, "help", &[Keycode::Question, Keycode::F1]),
+];
+
+// Usual keys that most keyboards only have with Shift, which are bound along with it
+const SHIFTED: [(Action, Keycode); 1] = [
+	(Action::Help, Keycode::Slash), // ?
+];
+
+// Left and right modifiers count the same, and lock keys don't count at all
+fn normalize(keymod: Mod) -> Mod {
+	let mut ret = Mod::empty();
+	if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) { ret |= Mod::LSHIFTMOD; }
+	if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) { ret |= Mod::LCTRLMOD; }
+	if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) { ret |= Mod::LALTMOD; }
+	ret
+}
+
+// A key by its SDL name, such as "H", "Left", or "Keypad +", after any of "Shift+", "Ctrl+", and
+// "Alt+"
+fn parse_key(text: &str) -> Option<(Keycode, Mod)> {
+	let mut keymod = Mod::empty();
+	let mut rest = text;
+	loop {
+		let lower = rest.to_ascii_lowercase();
+		let (prefix, flag) = match [("shift+", Mod::LSHIFTMOD), ("ctrl+", Mod::LCTRLMOD), ("alt+", Mod::LALTMOD)].iter().find(|(prefix, _)| lower.starts_with(prefix)) {
+			Some(found) => *found,
+			None => break,
+		};
+		// A lone + is the key itself
+		if rest.len() == prefix.len() { break; }
+		keymod |= flag;
+		rest = &rest[prefix.len()..];
+	}
+	Keycode::from_name(rest).map(|code| (code, keymod))
+}
+
+// The other way round from parse_key
+fn key_name((code, keymod): (Keycode, Mod)) -> String {
+	let prefixes = [(Mod::LSHIFTMOD, "Shift+"), (Mod::LCTRLMOD, "Ctrl+"), (Mod::LALTMOD, "Alt+")];
+	let prefix = prefixes.iter().filter(|(flag, _)| keymod.contains(*flag)).map(|(_, prefix)| *prefix).collect::<String>();
+	prefix + &code.name()
+}
+
+pub struct Keymap {
+	bindings: HashMap<(Keycode, Mod), Action>,
+}
+
+impl Default for Keymap {
+	fn default() -> Self {
+		let bindings = ACTIONS.iter().flat_map(|(action, _, keys)| keys.iter().map(move |key| ((*key, Mod::empty()), *action)))
+			.chain(SHIFTED.iter().map(|(action, key)| ((*key, Mod::LSHIFTMOD), *action)))
+			.collect();
+		Self { bindings }
+	}
+}
+
+impl Keymap {
+	// Each line of the file is an action name and a key, like "pan_left A".  Listing any keys for
+	// an action replaces its usual ones, and a key can only do one thing, so binding it takes it
+	// away from whatever else had it.  Blank lines and lines starting with # are skipped.
+	pub fn parse(text: &str) -> Result<Self, String> {
+		let mut ret = Self::default();
+		let mut rebound = HashSet::new();
+		for (i, line) in text.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') { continue; }
+			let (name, key) = line.split_once(char::is_whitespace).ok_or_else(|| format!("line {}: expected an action and a key", i + 1))?;
+			let action = ACTIONS.iter().find(|(_, action_name, _)| *action_name == name).map(|(action, _, _)| *action)
+				.ok_or_else(|| format!("line {}: unknown action \"{}\"", i + 1, name))?;
+			let key = key.trim();
+			let key = parse_key(key).ok_or_else(|| format!("line {}: unknown key \"{}\"", i + 1, key))?;
+			if rebound.insert(action) { ret.bindings.retain(|_, bound| *bound != action); }
+			ret.bindings.insert(key, action);
+		}
+		Ok(ret)
+	}
+
+	pub fn from_file(path: &Path) -> Result<Self, String> {
+		let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+		Self::parse(&text)
+	}
+
+	pub fn action(&self, key: (Keycode, Mod)) -> Option<Action> {
+		self.bindings.get(&(key.0, normalize(key.1))).copied()
+	}
+
+	// A line for each action that has any keys, in the usual order, like "zoom in: =, Keypad +"
+	pub fn help(&self) -> Vec<String> {
+		ACTIONS.iter().filter_map(|(action, name, _)| {
+			let mut keys = self.bindings.iter().filter(|(_, bound)| *bound == action).map(|(key, _)| key_name(*key)).collect::<Vec<_>>();
+			if keys.is_empty() { return None; }
+			keys.sort();
+			Some(format!("{}: {}", name.replace('_', " "), keys.join(", ")))
+		}).collect()
+	}
+}
+
+#[test]
+fn test_keymap() {
+	let keys = Keymap::parse("# WASD\npan_left A\npan_left Left\n\npan_up W\nswitch_theme Shift+A\nzoom_in Ctrl+Keypad +\n").unwrap();
+	assert_eq!(keys.action((Keycode::A, Mod::empty())), Some(Action::PanLeft));
+	assert_eq!(keys.action((Keycode::Left, Mod::empty())), Some(Action::PanLeft));
+	assert_eq!(keys.action((Keycode::H, Mod::empty())), None); // Replaced
+	assert_eq!(keys.action((Keycode::A, Mod::RSHIFTMOD | Mod::NUMMOD)), Some(Action::SwitchTheme));
+	assert_eq!(keys.action((Keycode::KpPlus, Mod::LCTRLMOD)), Some(Action::ZoomIn));
+	assert_eq!(keys.action((Keycode::KpPlus, Mod::empty())), None);
+	assert_eq!(keys.action((Keycode::Right, Mod::empty())), Some(Action::PanRight)); // Left alone
+	assert_eq!(keys.action((Keycode::J, Mod::LALTMOD)), None);
+	assert_eq!(Keymap::parse("pan_left\n").err(), Some("line 1: expected an action and a key".to_string()));
+	assert_eq!(Keymap::parse("\npan_sideways A\n").err(), Some("line 2: unknown action \"pan_sideways\"".to_string()));
+	assert_eq!(Keymap::parse("pan_left Hyper+A\n").err(), Some("line 1: unknown key \"Hyper+A\"".to_string()));
+}
+
+#[test]
+fn test_help() {
+	let help = Keymap::default().help();
+	assert_eq!(help.len(), ACTIONS.len());
+	assert_eq!(help[0], "zoom in: =, Keypad +");
+	assert!(help.contains(&"help: ?, F1, Shift+/".to_string()));
+	let help = Keymap::parse("pan_left Ctrl+A
+quit Escape
+").unwrap().help();
+	assert!(help.contains(&"pan left: Ctrl+A".to_string()));
+	assert!(help.contains(&"quit: Escape".to_string()));
+	assert!(!help.iter().any(|line| line.starts_with("cancel:"))); // Lost its only key to quit
+}
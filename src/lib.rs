@@ -0,0 +1,35 @@
+//! Reading and rendering Mapsforge map files.
+//!
+//! - `mapsforge` parses the binary map format: the file header, tile index, and the POIs and ways
+//!   in each tile, along with the coordinate systems used to place them.
+//! - `theme` decides which features get drawn and how, by matching their tags to materials.
+//! - `render` turns parsed tiles into themed geometry in map coordinates, caching them and
+//!   building them on a thread pool as the viewport moves.
+//! - `tilecache` (with the `disk-cache` feature) saves built tiles to disk between sessions.
+//! - `export` has drawing helpers for images saved outside the viewer window.
+//! - `osm` writes features back out as OpenStreetMap XML for editors like JOSM.
+//! - `geojson` writes them out as GeoJSON for GIS tools.
+//! - `projection` converts positions to flat coordinates in projections other than the viewer's,
+//!   with a choice of central meridian and standard parallel.
+//! - `dem` reads elevation grids and shades relief from them.
+//!
+//! The viewer binary handles windowing and input on top of these.
+
+#![feature(int_roundings)]
+#![feature(test)] // For benchmarks
+
+#[cfg(test)]
+extern crate test;
+
+extern crate rayon;
+
+pub mod dem;
+pub mod export;
+pub mod geojson;
+pub mod mapsforge;
+pub mod osm;
+pub mod projection;
+pub mod render;
+pub mod theme;
+#[cfg(feature = "disk-cache")]
+pub mod tilecache;
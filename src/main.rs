@@ -1,40 +1,102 @@
-#![feature(int_roundings)]
-
-extern crate rayon;
-
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use skulpin::rafx::api::RafxExtents2D;
 use skulpin::skia_safe::*;
+use sdl2::clipboard::ClipboardUtil;
 use sdl2::event::{Event, EventSender, WindowEvent};
 use sdl2::keyboard::{Keycode, Mod};
 use sdl2::mouse::MouseButton;
+use sdl2::video::FullscreenType;
+
+use mapviewer::{export, geojson, mapsforge, osm, projection, render, theme};
+use mapviewer::dem::Dem;
+use mapviewer::mapsforge::Coord;
+use mapviewer::render::{BoundingBox, Geometry, RenderManager, RenderTile, TileGrid, TileSink};
+use mapviewer::theme::PaintContext;
 
-mod mapsforge;
-mod render;
-mod theme;
+mod highlight;
+mod keymap;
+mod nmea;
+mod options;
+mod slippy;
+mod transform;
+mod viewurl;
 
-use mapsforge::Coord;
-use render::{BoundingBox, Geometry, RenderManager, RenderTile};
+use keymap::Action;
+use nmea::{Fix, GpsEvent};
+use transform::Transform;
 
 const ZOOM_MULTIPLIER: f64 = 1.2;
+const DOUBLE_CLICK_TIME: f64 = 0.4; // Most seconds between the clicks of a double click
+const DOUBLE_CLICK_DISTANCE: i32 = 4; // Most pixels apart the clicks of a double click can be
+const DOUBLE_CLICK_ZOOM: i32 = 4; // Zoom steps a double click takes, which is about one zoom level
 const PAN_INCREMENT: i32 = 100;
-const MAX_DETAIL: i64 = 4; // Smallest feature to display in pixels
+const STATUS_HEIGHT: f32 = 16.0; // Height of the status bar in pixels
+const LEGEND_ROW_HEIGHT: f32 = 16.0;
+const SCALE_BAR_WIDTH: f32 = 150.0; // Longest the scale bar can be in pixels
+const SLIPPY_TILE_SIZE: f64 = 256.0; // Pixels per tile in web map services
+const HEATMAP_CELL: i64 = 16; // Size of the squares POIs are counted in, in pixels
+const HIGHLIGHT_COLOR: Color4f = Color4f { r: 1.0, g: 0.9, b: 0.0, a: 1.0 };
+const DIM_ALPHA: f32 = 0.25; // How much of their usual opacity features that aren't highlighted keep
+const REFINE_DELAY: u32 = 300; // Milliseconds without events before redrawing with antialiasing
+const SETTLE_DELAY: u32 = 150; // Milliseconds the view has to stay still after dragging or zooming before new tiles are requested
+const REQUEST_DRIFT: i64 = 256; // Pixels the view can be dragged before new tiles are requested without waiting for it to settle
+const ANIMATION_TIME: f64 = 0.4; // Seconds an animated jump takes
+const ZOOM_ANIMATION_TIME: f64 = 0.15; // Seconds an animated zoom step takes
+const GLIDE_TIME: f64 = 0.3; // Seconds for the map to slow to about a third of its speed after a flick
+const GLIDE_MIN_SPEED: f64 = 20.0; // Pixels per second below which a glide stops
+const FLING_TIMEOUT: f64 = 0.05; // Seconds the mouse can rest before a release and still fling the map
+const PICK_RADIUS: i64 = 5; // How close in pixels a click has to be to a way to select it
+const OPACITY_STEP: f32 = 0.1; // How much [ and ] change an overlay's opacity
+const CULL_MARGIN: i64 = 16; // Pixels off the screen that ways can still be drawn in, so thick strokes aren't cut off
+const SUBTILE_COLOR: Color4f = Color4f { r: 1.0, g: 0.0, b: 1.0, a: 1.0 };
+const MEASURE_COLOR: Color4f = Color4f { r: 1.0, g: 0.3, b: 0.0, a: 1.0 };
+const NO_DATA_COLOR: Color4f = Color4f { r: 0.25, g: 0.25, b: 0.25, a: 1.0 };
+const NO_DATA_SPACING: f32 = 12.0; // Pixels between hatch lines, and the size of checkerboard squares
+const RELIEF_CELL: u32 = 2; // Pixels across each shaded relief sample
+// Night mode's color matrix, in rows for red, green, blue, and alpha.  It inverts each color and
+// then turns its hue halfway round, so light things go dark but water stays blue and parks green.
+const NIGHT_MATRIX: [f32; 20] = [
+	0.574, -1.430, -0.144, 0.0, 1.0,
+	-0.426, -0.430, -0.144, 0.0, 1.0,
+	-0.426, -1.430, 0.856, 0.0, 1.0,
+	0.0, 0.0, 0.0, 1.0, 0.0,
+];
+
+const BOOKMARK_KEYS: [Keycode; 9] = [Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4, Keycode::Num5, Keycode::Num6, Keycode::Num7, Keycode::Num8, Keycode::Num9];
 
 enum UpdateEvent {
-	Tile { generation: u64, tile: Arc<RenderTile> },
+	Tile { viewer: usize, generation: u64, tile: Arc<RenderTile> },
+	Gps(GpsEvent),
 }
 
 #[derive(Clone)]
 pub struct Updater {
 	sender: Arc<EventSender>,
+	viewer: usize, // Which viewer tiles are for
 }
 
 impl Updater {
-	fn send(&self, event: UpdateEvent) {
-		self.sender.push_custom_event(event).unwrap();
+	fn for_viewer(self, viewer: usize) -> Self {
+		Self { viewer, ..self }
+	}
+
+	// Fails if the event queue is full or SDL is shutting down
+	fn send(&self, event: UpdateEvent) -> Result<(), String> {
+		self.sender.push_custom_event(event)
+	}
+}
+
+impl TileSink for Updater {
+	fn tile_ready(&self, generation: u64, tile: Arc<RenderTile>) {
+		// The tile is dropped if SDL's event queue is full or it's shutting down.  It's still in
+		// the cache, so it's drawn on the next full redraw, but until something else changes it
+		// stays missing from the screen.
+		let _ = self.send(UpdateEvent::Tile { viewer: self.viewer, generation, tile });
 	}
 }
 
@@ -47,18 +109,30 @@ struct Events {
 	frames: u64,
 	force_redraw: bool,
 	should_quit: bool,
-	tiles_ready: Vec<(u64, Arc<RenderTile>)>,
+	toggle_fullscreen: bool,
+	idle: bool, // Waited for the idle timeout without anything happening
+	active: bool, // Something happened during the last update, even if it was only the mouse moving
+	tiles_ready: Vec<(usize, u64, Arc<RenderTile>)>,
+	gps: Vec<GpsEvent>,
 	mouse_pos: (i32, i32),
 	prev_mouse_pos: (i32, i32),
 	drag_start: Option<(i32, i32)>,
 	button_change: i32,
-	clicks: u32,
+	clicks: Vec<(i32, i32)>, // Positions where the left button was pressed and released without dragging
+	double_clicks: Vec<(i32, i32)>, // Positions of the second clicks of double clicks, which are in clicks too
+	single_clicks: Vec<(i32, i32)>, // Clicks that aren't the second of a double click, so don't do anything on top of its zoom
+	last_click: Option<(Instant, (i32, i32))>,
+	drag_velocity: (f64, f64), // How fast the mouse was last moving during a drag, in pixels per second
+	last_drag_motion: Instant,
+	fling: Option<(f64, f64)>, // Drag velocity if the button was let go while the mouse was moving
+	last_update: Instant,
 	wheel: i32,
 	keys: Vec<(Keycode, Mod)>,
+	keymap: keymap::Keymap,
 }
 
 impl Events {
-	fn new(context: &sdl2::Sdl) -> Self {
+	fn new(context: &sdl2::Sdl, keymap: keymap::Keymap) -> Self {
 		let subsys = context.event().unwrap();
 		let pump = context.event_pump().unwrap();
 		subsys.register_custom_event::<UpdateEvent>().unwrap();
@@ -70,22 +144,35 @@ impl Events {
 			frames: 0,
 			force_redraw: false,
 			should_quit: false,
+			toggle_fullscreen: false,
+			idle: false,
+			active: false,
 			tiles_ready: vec![],
+			gps: vec![],
 			mouse_pos: mouse_pos,
 			prev_mouse_pos: mouse_pos,
 			drag_start: if mouse_state.left() { Some(mouse_pos) } else { None },
 			button_change: 0,
-			clicks: 0,
+			clicks: vec![],
+			double_clicks: vec![],
+			single_clicks: vec![],
+			last_click: None,
+			drag_velocity: (0.0, 0.0),
+			last_drag_motion: Instant::now(),
+			fling: None,
+			last_update: Instant::now(),
 			wheel: 0,
 			keys: vec![],
+			keymap,
 		}
 	}
 
 	fn get_updater(&mut self) -> Updater {
-		Updater { sender: Arc::new(self.subsystem.event_sender()) }
+		Updater { sender: Arc::new(self.subsystem.event_sender()), viewer: 0 }
 	}
 
-	fn get_events(&mut self, block: bool) -> Vec<Event> {
+	// If there's an idle timeout, blocking gives up after that long and returns nothing
+	fn get_events(&mut self, block: bool, idle_timeout: Option<u32>) -> Vec<Event> {
 		if block {
 			let mut ret = vec![];
 			//let mut ret = vec![self.pump.wait_event()];
@@ -97,10 +184,11 @@ impl Events {
 			// threading-related.  Until I can figure it out, this is a hack that gets us close
 			// enough.
 			loop {
-				if let Some(event) = self.pump.wait_event_timeout(500) {
+				if let Some(event) = self.pump.wait_event_timeout(idle_timeout.unwrap_or(500)) {
 					ret.push(event);
 					break;
 				}
+				if idle_timeout.is_some() { return ret; }
 			}
 			ret.extend(self.pump.poll_iter());
 			ret
@@ -110,41 +198,63 @@ impl Events {
 		}
 	}
 
-	fn update(&mut self, block: bool) {
+	fn update(&mut self, block: bool, idle_timeout: Option<u32>) {
 		self.button_change = 0;
-		self.clicks = 0;
+		self.clicks.clear();
+		self.double_clicks.clear();
+		self.single_clicks.clear();
+		self.fling = None;
 		self.wheel = 0;
 		self.force_redraw = false;
+		self.toggle_fullscreen = false;
 		//self.tiles_ready.clear();
 		self.keys = vec![];
-		for event in self.get_events(block) {
+		self.gps.clear();
+		let events = self.get_events(block, idle_timeout);
+		self.idle = block && idle_timeout.is_some() && events.is_empty();
+		self.active = !events.is_empty();
+		let mut released = false;
+		for event in events {
 			match event {
 				Event::Quit { .. } => self.should_quit = true,
 				Event::MouseButtonDown { mouse_btn, x, y, .. } if mouse_btn == MouseButton::Left => {
 					self.button_change += 1;
-					self.drag_start = Some((x, y))
+					self.drag_start = Some((x, y));
+					self.drag_velocity = (0.0, 0.0);
 				},
 				Event::MouseButtonUp { mouse_btn, x, y, .. } if mouse_btn == MouseButton::Left => {
 					self.button_change -= 1;
-					if self.drag_start == Some((x, y)) { self.clicks += 1; }
+					if self.drag_start == Some((x, y)) {
+						self.clicks.push((x, y));
+						// A third click starts a new double click rather than making another
+						match self.last_click.take() {
+							Some((time, pos)) if is_double_click(time.elapsed().as_secs_f64(), pos, (x, y)) => self.double_clicks.push((x, y)),
+							_ => {
+								self.single_clicks.push((x, y));
+								self.last_click = Some((Instant::now(), (x, y)));
+							},
+						}
+					}
+					else { released = true; }
 					self.drag_start = None;
 				},
 				Event::MouseWheel { y, .. } => self.wheel += y,
-				Event::Window { win_event, .. } => {
-					match win_event {
-						WindowEvent::Resized(_, _) | WindowEvent::SizeChanged(_, _) => self.force_redraw = true,
-						_ => (),
-					}
-				},
+				Event::Window { win_event: WindowEvent::Resized(_, _) | WindowEvent::SizeChanged(_, _), .. } => self.force_redraw = true,
 				Event::KeyDown { keycode, keymod, .. } => {
 					if let Some(code) = keycode {
 						self.keys.push((code, keymod));
-						if (code, keymod) == (Keycode::Q, Mod::empty()) { self.should_quit = true; }
+						// These are about the window rather than any one viewer
+						match self.keymap.action((code, keymod)) {
+							Some(Action::Quit) => self.should_quit = true,
+							Some(Action::Fullscreen) => self.toggle_fullscreen ^= true,
+							_ => (),
+						}
 					}
 				}
 				Event::User { .. } => {
 					match event.as_user_event_type::<UpdateEvent>().unwrap() {
-						UpdateEvent::Tile { generation, tile } => self.tiles_ready.push((generation, tile)),
+						UpdateEvent::Tile { viewer, generation, tile } => self.tiles_ready.push((viewer, generation, tile)),
+						UpdateEvent::Gps(event) => self.gps.push(event),
 					}
 				}
 				_ => (),
@@ -153,97 +263,742 @@ impl Events {
 		let mouse_state = self.pump.mouse_state();
 		self.prev_mouse_pos = self.mouse_pos;
 		self.mouse_pos = (mouse_state.x(), mouse_state.y());
+		// Motion is averaged over a couple of updates to smooth out uneven frame times
+		let now = Instant::now();
+		let dt = now.duration_since(self.last_update).as_secs_f64();
+		self.last_update = now;
+		let delta = (self.mouse_pos.0 - self.prev_mouse_pos.0, self.mouse_pos.1 - self.prev_mouse_pos.1);
+		if (self.drag_start.is_some() || released) && delta != (0, 0) && dt > 0.0 {
+			let speed = (delta.0 as f64 / dt, delta.1 as f64 / dt);
+			self.drag_velocity = ((self.drag_velocity.0 + speed.0) / 2.0, (self.drag_velocity.1 + speed.1) / 2.0);
+			self.last_drag_motion = now;
+		}
+		if released && self.last_drag_motion.elapsed().as_secs_f64() < FLING_TIMEOUT { self.fling = Some(self.drag_velocity); }
+	}
+}
+
+// Everything from Hebrew onwards needs more than one glyph per character in a row
+fn needs_shaping(text: &str) -> bool {
+	text.chars().any(|c| c >= '\u{0590}')
+}
+
+// Hebrew, Arabic, Syriac, Thaana, and friends, plus their presentation forms
+fn is_rtl(text: &str) -> bool {
+	text.chars().any(|c| matches!(c, '\u{0590}'..='\u{08ff}' | '\u{fb1d}'..='\u{fdff}' | '\u{fe70}'..='\u{feff}'))
+}
+
+// A label that's been measured and positioned but not drawn yet
+struct Label {
+	text: String,
+	origin: (f32, f32),
+	bounds: Rect, // On screen
+	blob: Option<TextBlob>, // Already shaped, if draw_str won't do
+	priority: i32,
+	layer: i8, // Breaks ties in priority, so labels of bridges win over the roads under them
+}
+
+// Keep the labels that don't overlap any already placed, trying higher priority ones first.  Those
+// kept are added to placed.
+fn cull_labels(mut labels: Vec<Label>, placed: &mut Vec<Rect>) -> Vec<Label> {
+	labels.sort_by_key(|label| std::cmp::Reverse((label.priority, label.layer)));
+	labels.into_iter().filter(|label| {
+		if placed.iter().any(|rect| rect.intersects(label.bounds)) { return false; }
+		placed.push(label.bounds);
+		true
+	}).collect()
+}
+
+// Count the points falling in each cell of a grid over a window of the given size
+fn bin_points(points: impl Iterator<Item = Coord>, cell: i64, size: (u32, u32)) -> HashMap<(i64, i64), u32> {
+	let mut ret = HashMap::new();
+	for point in points {
+		if point.x < 0 || point.y < 0 || point.x >= size.0 as i64 || point.y >= size.1 as i64 { continue; }
+		*ret.entry((point.x.div_euclid(cell), point.y.div_euclid(cell))).or_insert(0) += 1;
+	}
+	ret
+}
+
+// Blue for cold through yellow to red for hot, with heat running from 0 to 1
+fn heat_color(heat: f32) -> Color4f {
+	let heat = heat.clamp(0.0, 1.0);
+	if heat < 0.5 { Color4f::new(heat * 2.0, heat * 2.0, 1.0 - heat * 2.0, 0.8) }
+	else { Color4f::new(1.0, 2.0 - heat * 2.0, 0.0, 0.8) }
+}
+
+// The whole way goes into one path, with a contour per polyline, so that path effects like dashes
+// carry their phase around bends instead of starting over at every vertex.  Don't split this up by
+// segment.  Also gives the bounds of the path, in the coordinates xform puts it in.
+fn way_path(polies: &[Vec<Coord>], closed: bool, xform: impl Fn(Coord) -> Coord) -> (Path, BoundingBox) {
+	let mut path = Path::new();
+	let mut bounds = BoundingBox::empty();
+	for poly in polies {
+		let point = xform(poly[0]);
+		path.move_to((point.x as f32, point.y as f32));
+		bounds.include(point);
+		for point in &poly[1..] {
+			let point = xform(*point);
+			path.line_to((point.x as f32, point.y as f32));
+			bounds.include(point);
+		}
+		// So the outline joins up at the start instead of ending in two caps
+		if closed { path.close(); }
+	}
+	(path, bounds)
+}
+
+// The --clip polygon in screen coordinates, on the copy of the map the view is on
+fn clip_polygon(view: &Transform, polygon: &[Coord], size: (u32, u32)) -> Vec<Point> {
+	let view = match polygon.first() {
+		Some(first) => view.nearest_lap(*first, size),
+		None => *view,
+	};
+	polygon.iter().map(|point| {
+		let point = view.to_screen(*point);
+		Point::new(point.x as f32, point.y as f32)
+	}).collect()
+}
+
+// The view a map's header suggests with its start position and zoom, with the fallback's center
+// or zoom for whichever it leaves out
+fn start_view(header: &mapsforge::MapHeader, fallback: Transform, size: (u32, u32)) -> Transform {
+	suggested_view(header.start_pos(), header.start_zoom(), header.tile_size as f64, fallback, size)
+}
+
+fn suggested_view(pos: Option<mapsforge::LatLon>, zoom: Option<u8>, tile_size: f64, fallback: Transform, size: (u32, u32)) -> Transform {
+	if pos.is_none() && zoom.is_none() { return fallback; }
+	let center = pos.map_or(fallback.center(size), |pos| pos.to_coord());
+	let zoom = zoom.map_or(fallback.zoom_level(tile_size), f64::from);
+	Transform::centered(center, zoom, tile_size, size)
+}
+
+// Where to start from what the command line gives, on top of the view from the map or --fit.
+// --view replaces it, keeping its zoom if the link has none, and --center and --zoom each replace
+// their part of it.
+fn command_line_view(start: Transform, view: Option<viewurl::View>, center: Option<mapsforge::LatLon>, zoom: Option<f64>, size: (u32, u32)) -> Transform {
+	let (center, zoom) = match view {
+		Some(view) => (Some(view.center), view.zoom),
+		None => (center, zoom),
+	};
+	if center.is_none() && zoom.is_none() { return start; }
+	let center = center.map_or(start.center(size), |center| center.to_coord());
+	let zoom = zoom.unwrap_or(start.zoom_level(SLIPPY_TILE_SIZE));
+	Transform::centered(center, zoom, SLIPPY_TILE_SIZE, size)
+}
+
+// A line for each material in the theme, in the order the legend shows them
+fn legend_labels(theme: &theme::Theme) -> Vec<String> {
+	theme.materials().iter().map(|(name, material)| match material.description() {
+		Some(description) => format!("{}: {}", name, description),
+		None => name.to_string(),
+	}).collect()
+}
+
+// Where each of count viewers side by side goes in a window of the given size
+fn split_layout(size: (u32, u32), count: u32) -> Vec<((i32, i32), (u32, u32))> {
+	(0..count).map(|i| {
+		let left = size.0 * i / count;
+		let right = size.0 * (i + 1) / count;
+		((left as i32, 0), (right - left, size.1))
+	}).collect()
+}
+
+// Called once nothing has happened for the idle timeout.  Everything released here can be
+// rebuilt when the view next changes.  Returns a message for the status bar.
+fn go_idle(render: &RefCell<RenderManager>, viewers: &mut [Viewer]) -> String {
+	for viewer in viewers.iter_mut() { viewer.release(); }
+	let dropped = render.borrow_mut().trim_cache();
+	format!("Idle: dropped {} cached tiles", dropped)
+}
+
+// Draw each viewer in its own part of the window
+fn draw_viewers(canvas: &mut Canvas, viewers: &mut [Viewer], mut draw: impl FnMut(usize, &mut Viewer, &mut Canvas)) {
+	for (i, viewer) in viewers.iter_mut().enumerate() {
+		canvas.save();
+		canvas.translate((viewer.origin.0 as f32, viewer.origin.1 as f32));
+		canvas.clip_rect(Rect::new(0.0, 0.0, viewer.size.0 as f32, viewer.size.1 as f32), ClipOp::Intersect, false);
+		draw(i, viewer, canvas);
+		canvas.restore();
+	}
+}
+
+// A sub-tile bitmap as four rows of bits, top first
+fn bitmap_rows(bits: u16) -> String {
+	let bits = format!("{:016b}", bits);
+	[&bits[0..4], &bits[4..8], &bits[8..12], &bits[12..16]].join(" ")
+}
+
+// The longest round distance, 1, 2, or 5 times a power of ten meters, that fits in max_width
+// pixels, along with its width in pixels.  None if the scale doesn't make sense.
+fn scale_bar(meters_per_pixel: f64, max_width: f32) -> Option<(f64, f32)> {
+	if !meters_per_pixel.is_finite() || meters_per_pixel <= 0.0 || max_width < 1.0 { return None; }
+	let max_meters = meters_per_pixel * max_width as f64;
+	let power = 10.0_f64.powf(max_meters.log10().floor());
+	let meters = [5.0, 2.0, 1.0].iter().map(|step| step * power).find(|meters| *meters <= max_meters)?;
+	Some((meters, (meters / meters_per_pixel) as f32))
+}
+
+fn format_distance(meters: f64) -> String {
+	if meters >= 1000.0 { format!("{} km", meters / 1000.0) }
+	else { format!("{} m", meters) }
+}
+
+// Like format_distance, but for measurements that aren't round numbers
+fn format_length(meters: f64) -> String {
+	if meters >= 1000.0 { format!("{:.2} km", meters / 1000.0) }
+	else { format!("{:.0} m", meters) }
+}
+
+// Great-circle distance along the points in turn, in meters
+fn path_length(points: &[Coord]) -> f64 {
+	points.windows(2).map(|pair| projection::distance(pair[0].to_latlon(), pair[1].to_latlon())).sum()
+}
+
+// Slow at both ends, so a jump eases into motion and settles gently at the end
+fn ease(t: f64) -> f64 {
+	let t = t.clamp(0.0, 1.0);
+	t * t * (3.0 - 2.0 * t)
+}
+
+// Fast at first and slowing down to a stop.  Zooming starts at full speed so that when the wheel
+// keeps turning, each step picks up without stalling where the last one was cut off.
+fn ease_out(t: f64) -> f64 {
+	let t = t.clamp(0.0, 1.0);
+	1.0 - (1.0 - t).powi(3)
+}
+
+// Whether a click follows one secs seconds earlier closely enough to make a double click
+fn is_double_click(secs: f64, first: (i32, i32), second: (i32, i32)) -> bool {
+	secs <= DOUBLE_CLICK_TIME && (first.0 - second.0).abs() <= DOUBLE_CLICK_DISTANCE && (first.1 - second.1).abs() <= DOUBLE_CLICK_DISTANCE
+}
+
+// How far something moving at the given speed in pixels per second goes in dt seconds as it slows
+// down exponentially, and how fast it's going after that
+fn glide_step(velocity: f64, dt: f64) -> (f64, f64) {
+	let decay = (-dt / GLIDE_TIME).exp();
+	(velocity * GLIDE_TIME * (1.0 - decay), velocity * decay)
+}
+
+// Things drawn over the map, each of which can be faded to show the map underneath
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Overlay {
+	Position,
+	Grid,
+	Subtiles,
+}
+
+impl Overlay {
+	const COUNT: usize = 3;
+
+	fn name(&self) -> &'static str {
+		match self {
+			Overlay::Position => "GPS position",
+			Overlay::Grid => "Tile grid",
+			Overlay::Subtiles => "Sub-tiles",
+		}
+	}
+}
+
+// What to draw where there's no map at all, to tell it apart from empty parts of the map
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoDataPattern {
+	Plain,
+	Hatch,
+	Checker,
+}
+
+impl NoDataPattern {
+	pub fn parse(name: &str) -> Result<Self, String> {
+		match name {
+			"plain" => Ok(NoDataPattern::Plain),
+			"hatch" => Ok(NoDataPattern::Hatch),
+			"checker" => Ok(NoDataPattern::Checker),
+			_ => Err(format!("Unknown no-data pattern \"{}\": expected plain, hatch, or checker", name)),
+		}
+	}
+
+	fn next(self) -> Self {
+		match self {
+			NoDataPattern::Plain => NoDataPattern::Hatch,
+			NoDataPattern::Hatch => NoDataPattern::Checker,
+			NoDataPattern::Checker => NoDataPattern::Plain,
+		}
+	}
+
+	fn name(&self) -> &'static str {
+		match self {
+			NoDataPattern::Plain => "plain",
+			NoDataPattern::Hatch => "hatched",
+			NoDataPattern::Checker => "checkered",
+		}
+	}
+}
+
+// Momentum left over from flicking the map, which keeps it moving until it slows to a stop
+struct Glide {
+	velocity: (f64, f64), // Pixels per second
+	last: Instant,
+	carry: (f64, f64), // Fractions of a pixel not moved yet
+}
+
+// Timing for the debug HUD
+struct FrameStats {
+	frames: VecDeque<Instant>, // When each frame in the last second finished drawing
+	frame_time: Duration, // How long the last frame took, from handling its events to finishing drawing
+	cached: usize, // Tiles in the render cache as of the last time tiles were requested or came in
+}
+
+impl FrameStats {
+	fn new() -> Self {
+		Self { frames: VecDeque::new(), frame_time: Duration::ZERO, cached: 0 }
+	}
+
+	fn record(&mut self, start: Instant) {
+		let now = Instant::now();
+		self.frame_time = now - start;
+		self.frames.push_back(now);
+		while self.frames.front().is_some_and(|time| now - *time > Duration::from_secs(1)) { self.frames.pop_front(); }
 	}
 }
 
+// A jump from one view to another that's in progress
+struct Animation {
+	from: Transform,
+	to: Transform,
+	start: Instant,
+	duration: f64, // In seconds
+	ease: fn(f64) -> f64,
+	anchor: Option<(u32, u32)>, // Pixel to zoom about, or None to move the center in a straight line
+}
+
 struct Viewer {
+	origin: (i32, i32), // Top left corner of this viewer in the window
 	size: (u32, u32),
-	offset: Coord, // Offset of viewport from origin in coord units
-	scale: u32, // Coord units per pixel -- larger is zooming out
+	view: Transform,
 	font: Font,
 	text_paint: Paint,
-	render: RenderManager,
+	render: Rc<RefCell<RenderManager>>, // Shared with any other viewers
 	generation: u64,
+	clip: Option<Vec<Coord>>, // Nothing outside this polygon is drawn
+	bookmarks: [Option<Transform>; BOOKMARK_KEYS.len()],
+	message: Option<String>, // Shown in the status bar until the view next changes
+	overlay_dirty: bool, // Overlays need to be redrawn even if no new tiles arrive
+	hidden_layers: HashSet<i8>,
+	show_legend: bool,
+	theme_file: Option<std::path::PathBuf>, // The usual styling, if not the basic theme.  It's read again each time it's switched back to.
+	show_grid: bool,
+	show_subtiles: bool, // Clicking a way shows the sub-tile bitmap the map has for it
+	selected: Option<(Arc<RenderTile>, i8, usize)>, // Way whose sub-tiles are shown, by tile, layer, and index
+	grayscale: bool,
+	pick: bool, // Print the location of each click to stdout
+	gps_fix: Option<Fix>, // Latest position from the GPS, which the view follows
+	shaper: Shaper, // For labels that draw_str can't handle properly
+	antialias: bool, // Tiles are drawn without antialiasing until the view settles down
+	shown: Vec<Arc<RenderTile>>, // Tiles drawn for the current generation, to redraw them once it does
+	retained: Vec<Arc<RenderTile>>, // Tiles from older generations still on the screen, drawn under the current ones until they're replaced
+	drawn_view: Option<Transform>, // The view what's on the screen was drawn at, or None if it needs drawing from scratch
+	labels: Vec<Rect>, // Where labels have been drawn in the current generation
+	heatmap: bool, // Show the density of POIs instead of the map itself
+	night: bool, // Draw the map with its brightness inverted
+	dem: Option<Rc<Dem>>, // Elevations to shade relief from, shared with any other viewers
+	relief: Option<(Transform, (u32, u32), Image)>, // Relief shaded for a view and size, in cells of RELIEF_CELL pixels
+	vignette: f32, // How much screenshots fade out towards their edges
+	show_help: bool,
+	help: Vec<String>, // What each key does, a line each
+	highlight: Option<highlight::Query>, // Features matching this stand out and everything else is dimmed
+	measure: Option<Vec<Coord>>, // Points clicked so far while measuring, or None when not measuring
+	loading_shown: bool, // The loading indicator is on the screen and needs drawing over once it's done
+	clipboard: Option<ClipboardUtil>,
+	animate: bool, // Move gradually to bookmarks and GPS fixes instead of jumping straight there
+	animation: Option<Animation>,
+	glide: Option<Glide>,
+	overlay_opacity: [f32; Overlay::COUNT],
+	faded_overlay: Overlay, // The one [ and ] change, which is whichever was last shown
+	no_data: NoDataPattern, // Drawn over tiles outside the map
+	cursor: Option<(i32, i32)>, // Last mouse position over this viewer, whose latitude and longitude are shown
+	hud: Option<(usize, Duration, usize)>, // Frames drawn in the last second, how long the last took, and cached tiles, if the debug HUD is on
+	requested_view: Transform, // The view the current generation's tiles were requested for
+	last_move: Option<Instant>, // When the view last moved without new tiles being requested
+	requested_at: Option<Instant>, // When tiles were last requested, until they've all arrived
+	fill_time: Option<Duration>, // How long the last request took to fill the view
 }
 
 impl Viewer {
 	fn zoom_to_fit(&mut self) {
-		let bounds = self.render.bounds();
-		self.scale = (bounds.width() as u32 / self.size.0).max(bounds.height() as u32 / self.size.1);
-		let viewport_adj = Coord { x: -(self.scale as i64 * self.size.0 as i64) / 2, y: -(self.scale as i64 * self.size.1 as i64) / 2 };
-		self.offset = bounds.midpoint().unwrap().add(&viewport_adj);
+		self.view = Transform::fit(&self.render.borrow().bounds(), self.size);
+	}
+
+	// The view the first map's header suggests, with the whole map's center or fitted zoom for
+	// whichever part it leaves out
+	fn zoom_to_start(&mut self) {
+		self.zoom_to_fit();
+		if let Some(map) = self.render.borrow().maps.first() { self.view = start_view(map.header(), self.view, self.size); }
 	}
 
-	fn new(maps: Vec<Arc<mapsforge::MapFile>>, init_size: (u32, u32)) -> Self {
+	fn new(render: Rc<RefCell<RenderManager>>, origin: (i32, i32), init_size: (u32, u32)) -> Self {
 		let mut font = Font::default();
 		font.set_size(10.0);
 		let mut text_paint = Paint::new(Color4f::new(1.0, 1.0, 1.0, 1.0), None);
 		text_paint.set_anti_alias(true);
 		text_paint.set_style(paint::Style::Fill);
 		text_paint.set_stroke(false);
-		let render = RenderManager::new(maps);
-		let mut ret = Self { size: init_size, offset: Coord { x: 0, y: 0 }, scale: 0, font, text_paint, render, generation: 0 };
-		ret.zoom_to_fit();
+		let mut ret = Self { origin, size: init_size, view: Transform { offset: Coord { x: 0, y: 0 }, scale: 1 }, font, text_paint, render, generation: 0, clip: None, bookmarks: [None; BOOKMARK_KEYS.len()], message: None, overlay_dirty: false, hidden_layers: HashSet::new(), show_legend: false, theme_file: None, show_grid: false, show_subtiles: false, selected: None, grayscale: false, pick: false, gps_fix: None, shaper: Shaper::new(None), antialias: false, shown: vec![], retained: vec![], drawn_view: None, labels: vec![], heatmap: false, night: false, dem: None, relief: None, vignette: 0.0, show_help: false, help: vec![], highlight: None, measure: None, loading_shown: false, clipboard: None, animate: true, animation: None, glide: None, overlay_opacity: [1.0; Overlay::COUNT], faded_overlay: Overlay::Grid, no_data: NoDataPattern::Plain, cursor: None, hud: None, requested_view: Transform { offset: Coord { x: 0, y: 0 }, scale: 1 }, last_move: None, requested_at: None, fill_time: None };
+		ret.zoom_to_start();
 		ret
 	}
 
+	// Start over with tiles from a new request
+	fn restart(&mut self, generation: u64) {
+		self.generation = generation;
+		self.requested_view = self.view;
+		self.last_move = None;
+		self.requested_at = Some(Instant::now());
+		self.antialias = false;
+		// What's on the screen stays there, moved along with the view, until new tiles cover it
+		let viewport = self.viewport();
+		self.retained.append(&mut self.shown);
+		self.retained.retain(|tile| !tile.bounds().intersection(&viewport).is_empty());
+		self.drawn_view = None;
+		self.labels.clear();
+	}
+
 	fn viewport(&self) -> BoundingBox {
-		let winsize = Coord { x: self.size.0 as i64 * self.scale as i64, y: self.size.1 as i64 * self.scale as i64 };
-		BoundingBox::from_corners((self.offset, self.offset.add(&winsize)))
+		self.view.viewport(self.size)
 	}
 
-	fn zoom(&mut self, factor: i32, center: (u32, u32)) {
-		let scale_mul = ZOOM_MULTIPLIER.powf(factor as f64);
-		self.scale = (self.scale as f64 / scale_mul).round() as u32;
-		let offset_mul = self.scale as f64 * (1.0 - scale_mul);
-		self.offset = Coord {
-			x: self.offset.x - (center.0 as f64 * offset_mul) as i64,
-			y: self.offset.y - (center.1 as f64 * offset_mul) as i64,
-		};
+	// Whether the view has moved far enough from where tiles were last requested that they're
+	// needed now, without waiting for it to settle
+	fn drifted(&self) -> bool {
+		let (old, new) = (self.requested_view, self.view);
+		let moved = (new.offset.x - old.offset.x).abs().max((new.offset.y - old.offset.y).abs()) / new.scale as i64;
+		moved > REQUEST_DRIFT || new.scale > old.scale * 2 || old.scale > new.scale * 2
+	}
+
+	// Milliseconds until the view counts as settled, if it's moved since tiles were last requested
+	fn settle_wait(&self) -> Option<u32> {
+		self.last_move.map(|time| (SETTLE_DELAY as u128).saturating_sub(time.elapsed().as_millis()) as u32)
+	}
+
+	// Zoom in or out by steps about a pixel, gradually if animation is on.  Returns whether the
+	// view changed right away.
+	fn zoom(&mut self, factor: i32, center: (u32, u32)) -> bool {
+		let factor = ZOOM_MULTIPLIER.powf(factor as f64);
+		if !self.animate {
+			self.view.zoom(factor, center);
+			return true;
+		}
+		// Zooming again before the last step has finished heads for where that step was going,
+		// starting from wherever the view has got to
+		let mut to = self.animation.as_ref().map_or(self.view, |animation| animation.to);
+		to.zoom(factor, center);
+		self.animation = Some(Animation { from: self.view, to, start: Instant::now(), duration: ZOOM_ANIMATION_TIME, ease: ease_out, anchor: Some(center) });
+		false
 	}
 
 	fn pan(&mut self, delta: (i32, i32)) {
-		self.offset = Coord {
-			x: self.offset.x - delta.0 as i64 * self.scale as i64,
-			y: self.offset.y - delta.1 as i64 * self.scale as i64,
+		self.view.pan(delta);
+		let wrapped = self.view.wrapped(self.size);
+		// Points being measured go round the world with the view, so that ones added after it
+		// wraps still join up with the rest
+		let laps = wrapped.offset.x - self.view.offset.x;
+		if let Some(points) = self.measure.as_mut().filter(|_| laps != 0) {
+			for point in points { point.x += laps; }
+		}
+		self.view = wrapped;
+	}
+
+	// Go to a new view, gradually if animation is on.  Returns whether the view changed right
+	// away.
+	fn jump(&mut self, view: Transform) -> bool {
+		if self.animate {
+			self.animation = Some(Animation { from: self.view, to: view, start: Instant::now(), duration: ANIMATION_TIME, ease, anchor: None });
+			false
+		}
+		else {
+			self.view = view;
+			true
+		}
+	}
+
+	// Move the view along its animation.  New tiles are only requested once it arrives, and until
+	// then the ones already shown are moved along with it.  Returns whether it has arrived.
+	fn step_animation(&mut self) -> bool {
+		let animation = match &self.animation {
+			Some(animation) => animation,
+			None => return false,
 		};
+		let t = animation.start.elapsed().as_secs_f64() / animation.duration;
+		if t >= 1.0 {
+			self.view = animation.to;
+			self.animation = None;
+			true
+		}
+		else {
+			let t = (animation.ease)(t);
+			self.view = match animation.anchor {
+				Some(pixel) => animation.from.interpolate_about(&animation.to, t, pixel),
+				None => animation.from.interpolate(&animation.to, t, self.size),
+			};
+			self.overlay_dirty = true;
+			false
+		}
 	}
 
-	fn update(&mut self, events: &Events, size: (u32, u32)) -> bool {
+	// Move the view along with a glide, returning whether it moved
+	fn step_glide(&mut self) -> bool {
+		let glide = match &mut self.glide {
+			Some(glide) => glide,
+			None => return false,
+		};
+		let dt = glide.last.elapsed().as_secs_f64();
+		glide.last = Instant::now();
+		let (x, y) = (glide_step(glide.velocity.0, dt), glide_step(glide.velocity.1, dt));
+		let moved = (x.0 + glide.carry.0, y.0 + glide.carry.1);
+		let delta = (moved.0.trunc(), moved.1.trunc());
+		glide.carry = (moved.0 - delta.0, moved.1 - delta.1);
+		glide.velocity = (x.1, y.1);
+		if glide.velocity.0.hypot(glide.velocity.1) < GLIDE_MIN_SPEED { self.glide = None; }
+		if delta == (0.0, 0.0) { return false; }
+		self.pan((delta.0 as i32, delta.1 as i32));
+		true
+	}
+
+	// Only the viewer with input gets mouse and keyboard events
+	fn update(&mut self, events: &Events, origin: (i32, i32), size: (u32, u32), input: bool) -> bool {
 		let mut update = events.force_redraw;
-		if size != self.size || events.frames == 0 { update = true; }
+		let mut moved = false; // Changes to the view that come in a continuous stream
+		if size != self.size || origin != self.origin || events.frames == 0 { update = true; }
 		self.size = size;
+		self.origin = origin;
+		let mouse_pos = (events.mouse_pos.0 - origin.0, events.mouse_pos.1 - origin.1);
+		let arrived = self.step_animation();
+		if input && self.cursor != Some(mouse_pos) {
+			self.cursor = Some(mouse_pos);
+			self.overlay_dirty = true;
+		}
 
-		if events.drag_start.is_some() {
+		// Pressing the button again stops a glide in its tracks
+		if input && (events.button_change != 0 || events.drag_start.is_some()) { self.glide = None; }
+		if self.step_glide() { moved = true; }
+		if let Some(velocity) = events.fling.filter(|velocity| input && self.animate && velocity.0.hypot(velocity.1) >= GLIDE_MIN_SPEED) {
+			self.glide = Some(Glide { velocity, last: Instant::now(), carry: (0.0, 0.0) });
+		}
+		if input && events.drag_start.is_some() {
 			let delta = (events.mouse_pos.0 - events.prev_mouse_pos.0, events.mouse_pos.1 - events.prev_mouse_pos.1);
 			if delta != (0, 0) {
 				self.pan(delta);
-				update = true;
+				moved = true;
 			}
 		}
-		if events.wheel != 0 {
-			self.zoom(events.wheel, (events.mouse_pos.0.max(0) as u32, events.mouse_pos.1.max(0) as u32));
+		// Double clicks are for marking points in quick succession while measuring
+		let measuring = self.measure.is_some();
+		for click in events.double_clicks.iter().filter(|_| input && !measuring) {
+			let click = ((click.0 - origin.0).max(0) as u32, (click.1 - origin.1).max(0) as u32);
+			update |= self.zoom(DOUBLE_CLICK_ZOOM, click);
+		}
+		if input && events.wheel != 0 {
+			moved |= self.zoom(events.wheel, (mouse_pos.0.max(0) as u32, mouse_pos.1.max(0) as u32));
+		}
+		let mut message = None;
+		for event in &events.gps {
+			match event {
+				GpsEvent::Fix(fix) => {
+					let position = fix.position.to_coord();
+					let half_screen = self.view.to_world((self.size.0 as i32 / 2, self.size.1 as i32 / 2));
+					let view = Transform { offset: Coord { x: self.view.offset.x + position.x - half_screen.x, y: self.view.offset.y + position.y - half_screen.y }, ..self.view };
+					if self.gps_fix.is_none() { self.faded_overlay = Overlay::Position; }
+					self.gps_fix = Some(*fix);
+					update |= self.jump(view);
+				},
+				GpsEvent::Disconnected(reason) => message = Some(format!("Lost GPS: {}", reason)),
+			}
+		}
+		if let Some(points) = self.measure.as_mut().filter(|_| input && !events.clicks.is_empty()) {
+			let view = self.view;
+			points.extend(events.clicks.iter().map(|click| view.to_world((click.0 - origin.0, click.1 - origin.1))));
+			if points.len() > 1 { message = Some(format!("Total {}", format_length(path_length(points)))); }
 			update = true;
 		}
+		else if self.show_subtiles && input {
+			for click in &events.single_clicks {
+				let point = self.view.to_world((click.0 - origin.0, click.1 - origin.1));
+				self.selected = self.nearest_way(point, (PICK_RADIUS * self.view.scale as i64) as f64);
+				message = Some(match &self.selected {
+					Some((tile, layer, i)) => {
+						let obj = &tile.layers[layer][*i];
+						format!("{}: map says {}, geometry crosses {}", obj.name.as_deref().unwrap_or("Unnamed way"), bitmap_rows(obj.subtiles), bitmap_rows(render::subtile_bits(&obj.geo, &tile.bounds())))
+					},
+					None => "No way there".to_string(),
+				});
+				update = true;
+			}
+		}
+		if self.pick && input {
+			for click in &events.single_clicks {
+				println!("{}", self.view.to_world((click.0 - origin.0, click.1 - origin.1)).to_latlon());
+			}
+		}
 		let mut key_zoom = 0;
 		let mut key_pan = (0, 0);
 		let mut reset = false;
-		for key in &events.keys {
-			if !key.1.is_empty() { continue; }
-			match key.0 {
-				Keycode::Equals | Keycode::KpPlus => { key_zoom += 1; },
-				Keycode::Minus | Keycode::KpMinus => { key_zoom -= 1; },
-				Keycode::Left | Keycode::H => { key_pan.0 += PAN_INCREMENT; },
-				Keycode::Right | Keycode::L => { key_pan.0 -= PAN_INCREMENT; },
-				Keycode::Up | Keycode::K => { key_pan.1 += PAN_INCREMENT; },
-				Keycode::Down | Keycode::J => { key_pan.1 -= PAN_INCREMENT; },
-				Keycode::Num0 => { reset = true; },
-				_ => {}
+		for key in events.keys.iter().filter(|_| input) {
+			if let Some(slot) = BOOKMARK_KEYS.iter().position(|code| *code == key.0) {
+				if key.1.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+					self.bookmarks[slot] = Some(self.view);
+					message = Some(format!("Saved bookmark {}", slot + 1));
+				}
+				else if key.1.is_empty() {
+					if let Some(view) = self.bookmarks[slot] {
+						update |= self.jump(view);
+						message = Some(format!("Jumped to bookmark {}", slot + 1));
+					}
+					else { message = Some(format!("Bookmark {} is not set", slot + 1)); }
+				}
+				else if key.1.intersects(Mod::LALTMOD | Mod::RALTMOD) {
+					// Layers run from -5 to 10 but nearly everything is close to zero, so center the
+					// keys on the ground layer
+					let layer = slot as i8 - 4;
+					if !self.hidden_layers.remove(&layer) { self.hidden_layers.insert(layer); }
+					update = true;
+				}
+				continue;
+			}
+			let action = match events.keymap.action(*key) {
+				Some(action) => action,
+				None => continue,
+			};
+			match action {
+				Action::ZoomIn => { key_zoom += 1; },
+				Action::ZoomOut => { key_zoom -= 1; },
+				Action::PanLeft => { key_pan.0 += PAN_INCREMENT; },
+				Action::PanRight => { key_pan.0 -= PAN_INCREMENT; },
+				Action::PanUp => { key_pan.1 += PAN_INCREMENT; },
+				Action::PanDown => { key_pan.1 -= PAN_INCREMENT; },
+				Action::ResetView => { reset = true; },
+				Action::SwitchTheme if self.render.borrow().diff_mode() => {
+					message = Some("Themes can't be changed while comparing maps".to_string());
+				},
+				Action::SwitchTheme => {
+					// The theme is shared with the other half of a split view, so whether roads are
+					// colored by access is read from it rather than kept here
+					let access = self.render.borrow().theme().name() != theme::access().name();
+					let (theme, msg) = match (access, &self.theme_file) {
+						(true, _) => (theme::access(), "Showing road access".to_string()),
+						(false, None) => (theme::basic(), "Showing the basic theme".to_string()),
+						(false, Some(path)) => match theme::Theme::from_file(path) {
+							Ok(theme) => (theme, format!("Showing {}", path.display())),
+							Err(e) => (theme::basic(), format!("{}: {}", path.display(), e)),
+						},
+					};
+					self.render.borrow_mut().set_theme(theme);
+					update = true;
+					message = Some(msg);
+				},
+				Action::Grayscale => {
+					self.grayscale = !self.grayscale;
+					update = true;
+				},
+				Action::Subtiles => {
+					self.show_subtiles = !self.show_subtiles;
+					self.selected = None;
+					update = true;
+					if self.show_subtiles {
+						self.faded_overlay = Overlay::Subtiles;
+						message = Some("Click a way to show its sub-tiles".to_string());
+					}
+				},
+				Action::Grid => {
+					self.show_grid = !self.show_grid;
+					if self.show_grid { self.faded_overlay = Overlay::Grid; }
+					update = true;
+				},
+				Action::FainterOverlay | Action::StrongerOverlay => {
+					let step = if action == Action::FainterOverlay { -OPACITY_STEP } else { OPACITY_STEP };
+					let opacity = &mut self.overlay_opacity[self.faded_overlay as usize];
+					*opacity = ((*opacity + step) * 10.0).round().clamp(0.0, 10.0) / 10.0;
+					message = Some(format!("{} opacity {:.0}%", self.faded_overlay.name(), *opacity * 100.0));
+					update = true;
+				},
+				Action::Cancel => {
+					if self.highlight.take().is_some() { update = true; }
+					if self.measure.take().is_some() { update = true; }
+				},
+				Action::CopyLink => {
+					let url = viewurl::fragment(self.view.center(self.size).to_latlon(), self.display_zoom());
+					message = Some(match self.clipboard.as_ref().map(|clipboard| clipboard.set_clipboard_text(&url)) {
+						Some(Ok(())) => format!("Copied {}", url),
+						Some(Err(e)) => format!("Couldn't copy {}: {}", url, e),
+						None => url,
+					});
+				},
+				Action::Elevation => {
+					let point = self.view.to_world(mouse_pos);
+					message = Some(match render::elevation_at(self.shown.iter().map(|tile| &**tile), point) {
+						Some(ele) => format!("Elevation about {:.0} m at {}", ele, point.to_latlon()),
+						None => "No elevation data nearby".to_string(),
+					});
+				},
+				Action::NoDataPattern => {
+					self.no_data = self.no_data.next();
+					message = Some(format!("Areas with no map are {}", self.no_data.name()));
+					update = true;
+				},
+				Action::ExportOsm => {
+					message = Some(match self.export_osm() {
+						Ok((path, count)) => format!("Wrote {} features to {}", count, path.display()),
+						Err(e) => format!("Couldn't export the view: {}", e),
+					});
+				},
+				Action::Screenshot => {
+					message = Some(match self.screenshot() {
+						Ok(path) => format!("Saved the view to {}", path.display()),
+						Err(e) => format!("Couldn't save the view: {}", e),
+					});
+					// The screenshot took over the current generation's tiles, so they're
+					// requested and drawn again on the screen
+					update = true;
+				},
+				Action::ExportGeojson => {
+					message = Some(match self.export_geojson() {
+						Ok((path, count)) => format!("Wrote {} features to {}", count, path.display()),
+						Err(e) => format!("Couldn't export the view: {}", e),
+					});
+				},
+				Action::Heatmap => {
+					self.heatmap = !self.heatmap;
+					update = true;
+				},
+				Action::Help => {
+					self.show_help = !self.show_help;
+					self.drawn_view = None;
+					self.overlay_dirty = true;
+				},
+				// The tiles don't change, so they're just drawn again through the filter
+				Action::NightMode => {
+					self.night = !self.night;
+					self.drawn_view = None;
+					self.overlay_dirty = true;
+				},
+				Action::Legend => {
+					// Redraw the map too so the legend doesn't linger after it's hidden
+					self.show_legend = !self.show_legend;
+					update = true;
+				},
+				Action::Measure => {
+					message = Some(match self.measure.take() {
+						Some(_) => "Stopped measuring".to_string(),
+						None => {
+							self.measure = Some(vec![]);
+							"Click points to measure the distance along them".to_string()
+						},
+					});
+					update = true;
+				},
+				Action::Quit | Action::Fullscreen => {}
 			}
 		}
 		if reset {
-			self.zoom_to_fit();
-			update = true;
+			let view = Transform::fit(&self.render.borrow().bounds(), self.size);
+			update |= self.jump(view);
 		}
 		else {
 			if key_pan != (0, 0) {
@@ -251,128 +1006,1173 @@ impl Viewer {
 				update = true;
 			}
 			if key_zoom != 0 {
-				self.zoom(key_zoom, (self.size.0 / 2, self.size.1 / 2));
-				update = true;
+				update |= self.zoom(key_zoom, (self.size.0 / 2, self.size.1 / 2));
+			}
+		}
+
+		// Dragging, gliding, and zooming with the wheel would otherwise request a fresh set of tiles
+		// every frame and abandon them the next.  Instead the tiles already shown are moved along
+		// with the view until it settles or strays too far from them.
+		if moved {
+			self.last_move = Some(Instant::now());
+			self.overlay_dirty = true;
+			if self.drifted() { update = true; }
+		}
+		if self.settle_wait() == Some(0) { update = true; }
+
+		// Anything else that moves the view cuts an animation short, though a zoom still in
+		// progress is finished off at once rather than lost
+		if update || moved {
+			if let Some(Animation { to, anchor: Some(pixel), .. }) = &self.animation {
+				let (factor, pixel) = (self.view.scale as f64 / to.scale as f64, *pixel);
+				self.view.zoom(factor, pixel);
 			}
+			self.message = None;
+			self.animation = None;
 		}
+		if message.is_some() {
+			self.message = message;
+			self.overlay_dirty = true;
+		}
+		update || arrived
+	}
 
-		if update { self.generation = events.frames; }
-		update
+	fn tile_rect(&self, tile: &render::RenderTile) -> Rect {
+		let (topleft, botright) = tile.bounds().corners().unwrap();
+		let (topleft, botright) = (self.view.to_screen(topleft), self.view.to_screen(botright));
+		Rect::new(topleft.x as f32, topleft.y as f32, botright.x as f32, botright.y as f32)
 	}
 
-	fn place_tile(&mut self, canvas: &mut Canvas, tile: Arc<render::RenderTile>) {
-		let xform = |point: Coord| Coord { x: (point.x - self.offset.x) / self.scale as i64, y: (point.y - self.offset.y) / self.scale as i64 };
+	// Base tiles, with no maps below them, clear what's under them to the background or mark it as
+	// having no map.  The relief goes over all the ones the map covers in one go.
+	fn draw_bases(&self, canvas: &mut Canvas, tiles: &[&Arc<render::RenderTile>]) {
+		let background = Paint::new(self.render.borrow().theme().background(&self.paint_context()), None);
+		let mut covered = Path::new();
+		for tile in tiles {
+			let rect = self.tile_rect(tile);
+			canvas.draw_rect(rect, &background);
+			if tile.covered {
+				let corners = [(rect.left, rect.top), (rect.right, rect.top), (rect.right, rect.bottom), (rect.left, rect.bottom)];
+				covered.add_poly(&corners.iter().map(|corner| Point::new(corner.0, corner.1)).collect::<Vec<_>>(), true);
+			}
+			else {
+				canvas.save();
+				canvas.clip_rect(rect, ClipOp::Intersect, false);
+				self.draw_no_data(canvas, rect);
+				canvas.restore();
+			}
+		}
+		if self.relief.is_some() && tiles.iter().any(|tile| tile.covered) {
+			canvas.save();
+			canvas.clip_path(&covered, ClipOp::Intersect, false);
+			self.draw_relief(canvas);
+			canvas.restore();
+		}
+	}
+
+	// Labels aren't drawn right away, since ones from other tiles may need to take precedence.
+	// Tiles are drawn over what's under them, which draw_bases has cleared for base tiles.
+	fn place_tile(&mut self, canvas: &mut Canvas, tile: Arc<render::RenderTile>, labels: &mut Vec<Label>) {
+		let xform = |point: Coord| self.view.to_screen(point);
 		let downcast = |point: Coord| (point.x as f32, point.y as f32);
+		let context = self.paint_context();
 		let bounds = tile.bounds();
-		let (topleft, botright) = bounds.corners().unwrap();
-		let topleft = downcast(xform(topleft));
-		let botright = downcast(xform(botright));
-		canvas.draw_rect(Rect::new(topleft.0, topleft.1, botright.0, botright.1), &Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None));
+		let tile_rect = self.tile_rect(&tile);
+		// Ways shared with neighboring tiles are drawn by each of them, so nothing needs to spill
+		// over.  Labels are drawn later and aren't cut off.
+		canvas.save();
+		canvas.clip_rect(tile_rect, ClipOp::Intersect, false);
+		// Zoomed in past a tile's zoom, most of it is off the screen, and the map already says which
+		// parts of the tile each way is in
+		let margin = CULL_MARGIN * self.view.scale as i64;
+		let visible = render::subtiles_overlapping(&bounds, &self.viewport().grow(margin, margin));
 		/*canvas.draw_rect(Rect::new(topleft.0, topleft.1, botright.0, botright.1), &self.paints[&Material::Unknown]);
 		canvas.draw_str(format!("{:?} {}", (tile.x, tile.y), self.generation), downcast(xform(bounds.midpoint().unwrap())), &self.font, &self.text_paint);
 		return;*/
-		for (_, objs) in &tile.layers {
+		for (layer, objs) in &tile.layers {
+			if self.hidden_layers.contains(layer) { continue; }
 			for obj in objs {
+				if obj.subtiles != 0 && obj.subtiles & visible == 0 { continue; }
 				match &obj.geo {
 					Geometry::Point(point) => {
 						let loc = downcast(xform(*point));
-						for paint in obj.material.paints() {
+						for paint in self.object_paints(obj, &context) {
 							canvas.draw_point(loc, &paint);
 						}
 						if let Some(name) = &obj.name {
-							canvas.draw_str(name, loc, &self.font, &self.text_paint);
+							labels.push(self.layout_label(name, loc, &obj.material, *layer));
 						}
 					},
-					Geometry::Path(polies) => {
-						let mut path = Path::new();
-						let mut bounds = BoundingBox::empty();
-						for poly in polies {
-							let point = xform(poly[0]);
-							path.move_to(downcast(point));
-							bounds.include(point);
-							for point in poly[1..].into_iter() {
-								let point = xform(*point);
-								path.line_to(downcast(point));
-								bounds.include(point);
-							}
-						}
-						if bounds.max_dimension() > MAX_DETAIL {
-							for paint in obj.material.paints() {
+					Geometry::Path(polies) | Geometry::Area(polies) => {
+						let (path, bounds) = way_path(polies, matches!(obj.geo, Geometry::Area(_)), xform);
+						if obj.material.big_enough(bounds.max_dimension() as f32) {
+							for paint in self.object_paints(obj, &context) {
 								canvas.draw_path(&path, &paint);
 							}
-							/*if let Some(name) = &obj.name {
-								let loc = downcast(bounds.midpoint().expect("No midpoint of non-mepty bounding box"));
-								canvas.draw_str(name, loc, &self.font, &self.text_paint);
-							}*/
+							// Where the map gives a position, each tile the way crosses labels it there
+							// and the labels are culled down to one.  Otherwise only the tile with
+							// the middle of the way has a position for its label.
+							if let (Some(name), Some(pos)) = (&obj.name, obj.label_pos) {
+								labels.push(self.layout_label(name, downcast(xform(pos)), &obj.material, *layer));
+							}
 						}
 					},
 				}
 			}
 		}
+		canvas.restore();
 	}
-	
+
+	fn object_paints(&self, obj: &render::Object, context: &PaintContext) -> Vec<Paint> {
+		let mut paints = obj.material.paints(context);
+		if let Some(query) = &self.highlight {
+			let hit = query.matches(obj);
+			for paint in &mut paints {
+				if hit { paint.set_color4f(HIGHLIGHT_COLOR, None); }
+				else { paint.set_alpha_f(paint.alpha_f() * DIM_ALPHA); }
+			}
+		}
+		paints
+	}
+
+	// Plain draw_str lays glyphs out one after another, which is fine for Latin, Greek, and
+	// Cyrillic but mangles scripts that are written right to left or that join and reorder their
+	// glyphs.  Those go through the shaper instead, which is a good deal slower.
+	fn layout_label(&self, text: &str, loc: (f32, f32), material: &theme::Material, layer: i8) -> Label {
+		let (text_bounds, blob) = match needs_shaping(text) {
+			true => match self.shaper.shape_text_blob(text, &self.font, !is_rtl(text), f32::INFINITY, (0.0, 0.0)) {
+				Some((blob, _)) => (*blob.bounds(), Some(blob)),
+				None => (self.font.measure_str(text, Some(&self.text_paint)).1, None),
+			},
+			false => (self.font.measure_str(text, Some(&self.text_paint)).1, None),
+		};
+		let origin = material.label_origin(loc, &text_bounds);
+		let bounds = Rect::new(origin.0 + text_bounds.left, origin.1 + text_bounds.top, origin.0 + text_bounds.right, origin.1 + text_bounds.bottom);
+		Label { text: text.to_string(), origin, bounds, blob, priority: material.label_priority(), layer }
+	}
+
+	fn draw_label(&self, canvas: &mut Canvas, label: &Label) {
+		match &label.blob {
+			Some(blob) => { canvas.draw_text_blob(blob, label.origin, &self.text_paint); },
+			None => { canvas.draw_str(&label.text, label.origin, &self.font, &self.text_paint); },
+		}
+	}
+
+	// Relief is shaded for the view tiles were last requested at, since it takes a while, and moved
+	// along with the tiles until the next request
+	fn update_relief(&mut self) {
+		let dem = match &self.dem { Some(dem) => dem, None => return };
+		let (view, size) = (self.requested_view, self.size);
+		if self.relief.as_ref().is_some_and(|relief| relief.0 == view && relief.1 == size) { return; }
+		let cells = (size.0.div_ceil(RELIEF_CELL), size.1.div_ceil(RELIEF_CELL));
+		// The view's projection keeps latitude and longitude apart, so each only has to be worked out
+		// once per row or column, at the middle of the cell.  Columns on the copies of the map east
+		// and west of the first are brought back onto it, where the DEM's longitudes are.
+		let middle = |cell: u32| (cell * RELIEF_CELL + RELIEF_CELL / 2) as i32;
+		let lons = (0..cells.0).map(|x| view.to_world((middle(x), 0)).wrapped().to_latlon().degrees().1).collect::<Vec<_>>();
+		let lats = (0..cells.1).map(|y| view.to_world((0, middle(y))).to_latlon().degrees().0).collect::<Vec<_>>();
+		let pixels = dem.relief(&lats, &lons);
+		let info = ImageInfo::new((cells.0 as i32, cells.1 as i32), ColorType::RGBA8888, AlphaType::Premul, None);
+		self.relief = Image::from_raster_data(&info, Data::new_copy(&pixels), cells.0 as usize * 4).map(|image| (view, size, image));
+	}
+
+	// Multiplied over the background, so the map's colors show through and only get darker where
+	// slopes face away from the light
+	fn draw_relief(&self, canvas: &mut Canvas) {
+		let (view, size, image) = match &self.relief { Some(relief) => relief, None => return };
+		let extent = (size.0.div_ceil(RELIEF_CELL) * RELIEF_CELL, size.1.div_ceil(RELIEF_CELL) * RELIEF_CELL);
+		let topleft = self.view.to_screen(view.offset);
+		let botright = self.view.to_screen(view.to_world((extent.0 as i32, extent.1 as i32)));
+		let mut paint = Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None);
+		paint.set_blend_mode(BlendMode::Multiply);
+		canvas.draw_image_rect(image, None, Rect::new(topleft.x as f32, topleft.y as f32, botright.x as f32, botright.y as f32), &paint);
+	}
+
+	// The pattern lines up with the window rather than the tile so that it's seamless
+	fn draw_no_data(&self, canvas: &mut Canvas, rect: Rect) {
+		let paint = Paint::new(NO_DATA_COLOR, None);
+		let cell = |x: f32| (x / NO_DATA_SPACING).floor() as i64;
+		match self.no_data {
+			NoDataPattern::Plain => (),
+			NoDataPattern::Hatch => {
+				// Diagonals running down to the left, each where x + y is a multiple of the spacing
+				let height = rect.height();
+				for i in cell(rect.left + rect.top)..=cell(rect.right + rect.bottom) {
+					let start = i as f32 * NO_DATA_SPACING - rect.top;
+					canvas.draw_line((start, rect.top), (start - height, rect.bottom), &paint);
+				}
+			},
+			NoDataPattern::Checker => {
+				for row in cell(rect.top)..=cell(rect.bottom) {
+					for col in cell(rect.left)..=cell(rect.right) {
+						if (row + col) % 2 != 0 { continue; }
+						let (left, top) = (col as f32 * NO_DATA_SPACING, row as f32 * NO_DATA_SPACING);
+						canvas.draw_rect(Rect::new(left, top, left + NO_DATA_SPACING, top + NO_DATA_SPACING), &paint);
+					}
+				}
+			},
+		}
+	}
+
 	fn clear(&mut self, canvas: &mut Canvas) {
-		canvas.clear(Color4f::new(0.0, 0.0, 0.0, 1.0));
+		canvas.clear(self.render.borrow().theme().background(&self.paint_context()));
 	}
 
+	// The map and the overlays and controls drawn over it
 	fn draw(&mut self, canvas: &mut Canvas, tiles: &mut Vec<(u64, Arc<RenderTile>)>) {
+		let loading = self.draw_map(canvas, tiles);
+		if loading == 0 {
+			if let Some(time) = self.requested_at.take() { self.fill_time = Some(time.elapsed()); }
+		}
+		if let Some(fix) = self.gps_fix { self.draw_overlay(canvas, Overlay::Position, |viewer, canvas| viewer.draw_position(canvas, fix)); }
+		if self.show_grid { self.draw_overlay(canvas, Overlay::Grid, Self::draw_grid); }
+		if self.selected.is_some() { self.draw_overlay(canvas, Overlay::Subtiles, Self::draw_subtiles); }
+		if let Some(points) = &self.measure { self.draw_measure(canvas, points); }
+		self.draw_loading(canvas, loading);
+		self.draw_status(canvas);
+		self.draw_cursor_position(canvas);
+		self.draw_scale_bar(canvas);
+		if let Some((fps, frame_time, cached)) = self.hud { self.draw_hud(canvas, fps, frame_time, cached); }
+		if self.show_legend { self.draw_legend(canvas); }
+		if self.show_help { self.draw_help(canvas); }
+		self.overlay_dirty = false;
+	}
+
+	// Just the map, as exported, returning how many tiles are still loading
+	fn draw_map(&mut self, canvas: &mut Canvas, tiles: &mut Vec<(u64, Arc<RenderTile>)>) -> usize {
 		// These two lines do the transformation for us, but it's not faster and also scales fonts
 		// and line widths, which we don't want.
-		//canvas.scale(((1.0 / self.scale as f64) as f32, (1.0 / self.scale as f64) as f32));
-		//canvas.translate((-self.offset.x as f32, -self.offset.y as f32));
-		for tile in tiles.drain(..) {
-			if tile.0 == self.generation {
-				self.place_tile(canvas, tile.1);
+		//canvas.scale(((1.0 / self.view.scale as f64) as f32, (1.0 / self.view.scale as f64) as f32));
+		//canvas.translate((-self.view.offset.x as f32, -self.view.offset.y as f32));
+		// Animating or dragging the view doesn't request new tiles until it stops, and new tiles
+		// take a while to arrive, so the ones already shown are moved along with it.  Faded
+		// overlays would build up if drawn over themselves, so everything under them is drawn
+		// again too, as is everything under the loading indicator once there's nothing left to load.
+		let loading = self.render.borrow().loading();
+		// Night mode draws the map into a layer that goes through the color filter on its way to
+		// the screen, which leaves the overlays alone
+		let mut night_paint = Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None);
+		if self.night {
+			night_paint.set_color_filter(color_filters::matrix_row_major(&NIGHT_MATRIX));
+			canvas.save_layer(&canvas::SaveLayerRec::default().paint(&night_paint));
+		}
+		let full = self.drawn_view != Some(self.view) || self.animation.is_some() || self.overlays_faded() || (loading == 0 && self.loading_shown);
+		if full {
+			self.update_relief();
+			self.clear(canvas);
+			self.labels.clear();
+			let generation = self.generation;
+			tiles.extend(self.shown.drain(..).map(|tile| (generation, tile)));
+		}
+		canvas.save();
+		if let Some(clip) = &self.clip {
+			let mut path = Path::new();
+			path.add_poly(&clip_polygon(&self.view, clip, self.size), true);
+			canvas.clip_path(&path, ClipOp::Intersect, true);
+		}
+		// Maps are stacked in command line order, but their tiles arrive in any order.  When a
+		// tile turns up under ones already drawn from maps above it, those are drawn again over
+		// it, only inside it so that nothing else gets drawn twice.
+		let mut labels = vec![];
+		if full && !self.heatmap {
+			let retained = self.retained.clone();
+			let bases = retained.iter().filter(|tile| !retained.iter().any(|other| other.map < tile.map && other.overlaps(tile))).collect::<Vec<_>>();
+			self.draw_bases(canvas, &bases);
+			for tile in retained { self.place_tile(canvas, tile, &mut labels); }
+		}
+		let generation = self.generation;
+		let mut incoming = tiles.drain(..).filter(|tile| tile.0 == generation).map(|tile| tile.1).collect::<Vec<_>>();
+		incoming.sort_by_key(|tile| tile.map);
+		if !self.heatmap {
+			// Tiles from maps below can come in the same batch
+			let bases = incoming.iter().enumerate().filter(|(i, tile)| !self.shown.iter().chain(&incoming[..*i]).any(|other| other.map < tile.map && other.overlaps(tile))).map(|(_, tile)| tile).collect::<Vec<_>>();
+			self.draw_bases(canvas, &bases);
+		}
+		for tile in incoming {
+			if !self.heatmap {
+				let mut above = self.shown.iter().filter(|shown| shown.map > tile.map && shown.overlaps(&tile)).cloned().collect::<Vec<_>>();
+				above.sort_by_key(|shown| shown.map);
+				self.place_tile(canvas, tile.clone(), &mut labels);
+				if !above.is_empty() {
+					let (topleft, botright) = tile.bounds().corners().unwrap();
+					let (topleft, botright) = (self.view.to_screen(topleft), self.view.to_screen(botright));
+					canvas.save();
+					canvas.clip_rect(Rect::new(topleft.x as f32, topleft.y as f32, botright.x as f32, botright.y as f32), ClipOp::Intersect, false);
+					// Their labels were placed the first time round
+					for shown in above { self.place_tile(canvas, shown, &mut vec![]); }
+					canvas.restore();
+				}
 			}
+			self.shown.push(tile);
+		}
+		// Older tiles are dropped once they're covered up, or once everything requested has
+		// arrived and they have nothing left to fill in.  They're still on the screen until it's
+		// next drawn from scratch, by which time the last new tiles will have been drawn over them.
+		let shown = &self.shown;
+		self.retained.retain(|old| !old.hidden_by(shown.iter().filter(|tile| tile.map == old.map).map(|tile| &**tile)));
+		if loading == 0 { self.retained.clear(); }
+		self.drawn_view = Some(self.view);
+		if self.heatmap { self.draw_heatmap(canvas); }
+		for label in cull_labels(labels, &mut self.labels) {
+			self.draw_label(canvas, &label);
+		}
+		canvas.restore();
+		if self.night { canvas.restore(); }
+		loading
+	}
+
+	fn overlays_faded(&self) -> bool {
+		let shown = [(Overlay::Position, self.gps_fix.is_some()), (Overlay::Grid, self.show_grid), (Overlay::Subtiles, self.selected.is_some())];
+		shown.iter().any(|(overlay, shown)| *shown && self.overlay_opacity[*overlay as usize] < 1.0)
+	}
+
+	fn draw_overlay(&self, canvas: &mut Canvas, overlay: Overlay, draw: impl FnOnce(&Self, &mut Canvas)) {
+		let opacity = self.overlay_opacity[overlay as usize];
+		if opacity == 0.0 { return; }
+		canvas.save_layer_alpha(None, (opacity * 255.0).round() as u32);
+		draw(self, canvas);
+		canvas.restore();
+	}
+
+	// Every tile so far contributes to every cell, so the whole thing is redrawn each time
+	fn draw_heatmap(&mut self, canvas: &mut Canvas) {
+		self.clear(canvas);
+		let points = self.shown.iter().flat_map(|tile| tile.pois.iter()).map(|poi| self.view.to_screen(*poi));
+		let bins = bin_points(points, HEATMAP_CELL, self.size);
+		// POI density varies wildly, so a log scale shows more than a linear one would
+		let max = bins.values().copied().max().unwrap_or(0) as f32;
+		for ((x, y), count) in bins {
+			let heat = (count as f32).ln_1p() / max.ln_1p();
+			let (left, top) = ((x * HEATMAP_CELL) as f32, (y * HEATMAP_CELL) as f32);
+			let rect = Rect::new(left, top, left + HEATMAP_CELL as f32, top + HEATMAP_CELL as f32);
+			canvas.draw_rect(rect, &Paint::new(heat_color(heat), None));
 		}
 	}
+
+	fn needs_refinement(&self) -> bool {
+		!self.antialias && !self.shown.is_empty()
+	}
+
+	// Draw everything again, this time with antialiasing
+	fn refine(&mut self, canvas: &mut Canvas) {
+		self.antialias = true;
+		self.drawn_view = None;
+		self.draw(canvas, &mut vec![]);
+	}
+
+	// Drop the tiles on screen, so that ones built with another theme aren't moved along with the
+	// view while the new ones load
+	fn forget_tiles(&mut self) {
+		self.shown.clear();
+		self.release();
+	}
+
+	// Free memory that's only needed while tiles are arriving.  The tiles themselves are kept so
+	// the view can be redrawn without reading them again.
+	fn release(&mut self) {
+		self.labels = vec![];
+		self.retained = vec![];
+		self.shown.shrink_to_fit();
+	}
+
+	// The zoom level at which a web map would show about the same amount of detail as we are
+	fn display_zoom(&self) -> f64 {
+		self.view.zoom_level(SLIPPY_TILE_SIZE)
+	}
+
+	// An arrow pointing in the direction of travel, or a dot if we don't know it
+	fn draw_position(&self, canvas: &mut Canvas, fix: Fix) {
+		let position = fix.position.to_coord();
+		let center = self.view.nearest_lap(position, self.size).to_screen(position);
+		let center = (center.x as f32, center.y as f32);
+		let mut fill = Paint::new(Color4f::new(0.1, 0.4, 1.0, 1.0), None);
+		fill.set_anti_alias(true);
+		let mut outline = Paint::new(Color4f::new(1.0, 1.0, 1.0, 1.0), None);
+		outline.set_anti_alias(true);
+		outline.set_style(paint::Style::Stroke);
+		outline.set_stroke_width(2.0);
+		match fix.heading {
+			Some(heading) => {
+				let (sin, cos) = (heading as f32).to_radians().sin_cos();
+				let rotate = |(x, y): (f32, f32)| Point::new(center.0 + x * cos - y * sin, center.1 + x * sin + y * cos);
+				let mut path = Path::new();
+				path.add_poly(&[(0.0, -12.0), (7.0, 8.0), (0.0, 4.0), (-7.0, 8.0)].iter().map(|p| rotate(*p)).collect::<Vec<_>>(), true);
+				canvas.draw_path(&path, &fill);
+				canvas.draw_path(&path, &outline);
+			},
+			None => {
+				canvas.draw_circle(center, 6.0, &fill);
+				canvas.draw_circle(center, 6.0, &outline);
+			},
+		}
+	}
+
+	fn paint_context(&self) -> PaintContext {
+		PaintContext { zoom: self.display_zoom() as f32, grayscale: self.grayscale, antialias: self.antialias }
+	}
+
+	// The nearest zoom level that web map tiles actually exist for
+	fn slippy_zoom(&self) -> u8 {
+		self.display_zoom().round().clamp(0.0, 20.0) as u8
+	}
+
+	// Outline and label the slippy map tiles at the current display zoom
+	fn draw_grid(&self, canvas: &mut Canvas) {
+		let zoom = self.slippy_zoom();
+		let ntile = 1 << zoom;
+		let mut paint = Paint::new(Color4f::new(1.0, 0.5, 0.0, 1.0), None);
+		paint.set_style(paint::Style::Stroke);
+		let ((xmin, xmax), (ymin, ymax)) = TileGrid::MERCATOR.visible_tiles(&self.viewport(), zoom);
+		for y in ymin.max(0) ..= ymax.min(ntile - 1) {
+			for x in xmin..=xmax {
+				let (topleft, botright) = TileGrid::MERCATOR.bounds(zoom, x, y).corners().unwrap();
+				let (topleft, botright) = (self.view.to_screen(topleft), self.view.to_screen(botright));
+				canvas.draw_rect(Rect::new(topleft.x as f32, topleft.y as f32, botright.x as f32, botright.y as f32), &paint);
+				canvas.draw_str(format!("{}/{}/{}", zoom, TileGrid::MERCATOR.wrap_x(zoom, x), y), (topleft.x as f32 + 4.0, topleft.y as f32 + 14.0), &self.font, &self.text_paint);
+			}
+		}
+	}
+
+	// Save the features on the screen as OSM XML, in the first export-N.osm in the current
+	// directory that doesn't exist yet
+	fn export_osm(&self) -> std::io::Result<(std::path::PathBuf, usize)> {
+		use std::io::Write;
+		let path = export_path("osm");
+		let mut out = std::io::BufWriter::new(std::fs::File::create(&path)?);
+		let count = osm::write_xml(self.shown_objects(), &self.viewport(), &mut out)?;
+		out.flush()?;
+		Ok((path, count))
+	}
+
+	fn export_geojson(&self) -> std::io::Result<(std::path::PathBuf, usize)> {
+		use std::io::Write;
+		let path = export_path("geojson");
+		let mut out = std::io::BufWriter::new(std::fs::File::create(&path)?);
+		let count = geojson::write_geojson(self.shown_objects(), &self.viewport(), &mut out)?;
+		out.flush()?;
+		Ok((path, count))
+	}
+
+	// Everything in the tiles on the screen, apart from hidden layers
+	fn shown_objects(&self) -> impl Iterator<Item = &render::Object> {
+		self.shown.iter()
+			.flat_map(|tile| tile.layers.iter())
+			.filter(move |(layer, _)| !self.hidden_layers.contains(layer))
+			.flat_map(|(_, objs)| objs)
+	}
+
+	// Draw the view offscreen the same way as on the screen, but waiting for every tile, and save
+	// it as a PNG named after the current time
+	fn screenshot(&mut self) -> Result<std::path::PathBuf, String> {
+		let mut surface = Surface::new_raster_n32_premul((self.size.0 as i32, self.size.1 as i32)).ok_or("Couldn't create a drawing surface")?;
+		let generation = self.generation;
+		let antialias = self.antialias;
+		self.restart(generation);
+		self.retained.clear();
+		self.antialias = true;
+		let mut tiles = self.render.borrow_mut().viewport_tiles(&self.viewport(), self.size.0).into_iter().map(|tile| (generation, tile)).collect();
+		let canvas = surface.canvas();
+		self.clear(canvas);
+		// Only the map, without the status bar, legend, and the rest drawn over it in the window
+		self.draw_map(canvas, &mut tiles);
+		self.antialias = antialias;
+		let background = self.render.borrow().theme().background(&self.paint_context());
+		export::draw_vignette(canvas, Rect::new(0.0, 0.0, self.size.0 as f32, self.size.1 as f32), background, self.vignette);
+		let png = surface.image_snapshot().encode_to_data(EncodedImageFormat::PNG).ok_or("Couldn't encode the image")?;
+		let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_err(|e| e.to_string())?;
+		let path = std::path::PathBuf::from(format!("screenshot-{}.png", time.as_millis()));
+		std::fs::write(&path, png.as_bytes()).map_err(|e| format!("{}: {}", path.display(), e))?;
+		Ok(path)
+	}
+
+	// The way nearest the point among the tiles shown, if there's one within the distance
+	fn nearest_way(&self, point: Coord, within: f64) -> Option<(Arc<RenderTile>, i8, usize)> {
+		let mut best = None;
+		let mut best_distance = within;
+		for tile in &self.shown {
+			for (layer, objs) in tile.layers.iter().filter(|(layer, _)| !self.hidden_layers.contains(layer)) {
+				for (i, obj) in objs.iter().enumerate() {
+					if let Geometry::Point(_) = obj.geo { continue; }
+					let distance = obj.geo.distance(point);
+					if distance <= best_distance {
+						best = Some((tile.clone(), *layer, i));
+						best_distance = distance;
+					}
+				}
+			}
+		}
+		best
+	}
+
+	// Outline the selected way and divide its tile into sub-tiles, shading the ones the map says
+	// the way is in
+	fn draw_subtiles(&self, canvas: &mut Canvas) {
+		let (tile, layer, i) = match &self.selected {
+			Some(selected) => selected,
+			None => return,
+		};
+		let obj = &tile.layers[layer][*i];
+		// The tile was shown on whichever copy of the map the view was on when it was selected
+		let view = self.view.nearest_lap(tile.bounds().midpoint().unwrap(), self.size);
+		let rect = |bounds: &BoundingBox| {
+			let (topleft, botright) = bounds.corners().unwrap();
+			let (topleft, botright) = (view.to_screen(topleft), view.to_screen(botright));
+			Rect::new(topleft.x as f32, topleft.y as f32, botright.x as f32, botright.y as f32)
+		};
+		let shade = Paint::new(SUBTILE_COLOR.with_a(0.3), None);
+		let mut line = Paint::new(SUBTILE_COLOR, None);
+		line.set_style(paint::Style::Stroke);
+		for row in 0..4 {
+			for col in 0..4 {
+				let cell = rect(&render::subtile_bounds(&tile.bounds(), col, row));
+				if obj.subtiles & mapsforge::subtile_bit(col as u8, row as u8) != 0 { canvas.draw_rect(cell, &shade); }
+				canvas.draw_rect(cell, &line);
+			}
+		}
+		if let Geometry::Path(polies) | Geometry::Area(polies) = &obj.geo {
+			let mut path = Path::new();
+			for poly in polies {
+				for (j, point) in poly.iter().enumerate() {
+					let point = view.to_screen(*point);
+					if j == 0 { path.move_to((point.x as f32, point.y as f32)); }
+					else { path.line_to((point.x as f32, point.y as f32)); }
+				}
+			}
+			line.set_stroke_width(3.0);
+			canvas.draw_path(&path, &line);
+		}
+	}
+
+	// A line through the measured points with each leg's length at its middle, and the total at
+	// the end if there's more than one leg
+	fn draw_measure(&self, canvas: &mut Canvas, points: &[Coord]) {
+		let screen = points.iter().map(|point| {
+			let point = self.view.to_screen(*point);
+			Point::new(point.x as f32, point.y as f32)
+		}).collect::<Vec<_>>();
+		let mut line = Paint::new(MEASURE_COLOR, None);
+		line.set_anti_alias(true);
+		line.set_style(paint::Style::Stroke);
+		line.set_stroke_width(2.0);
+		let mut path = Path::new();
+		path.add_poly(&screen, false);
+		canvas.draw_path(&path, &line);
+		let dot = Paint::new(MEASURE_COLOR, None);
+		for point in &screen { canvas.draw_circle(*point, 4.0, &dot); }
+		let label = |canvas: &mut Canvas, text: String, at: Point| {
+			let width = self.font.measure_str(&text, Some(&self.text_paint)).0;
+			canvas.draw_rect(Rect::new(at.x + 6.0, at.y - STATUS_HEIGHT, at.x + width + 18.0, at.y), &Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None));
+			canvas.draw_str(text, (at.x + 12.0, at.y - 4.0), &self.font, &self.text_paint);
+		};
+		for (pair, coords) in screen.windows(2).zip(points.windows(2)) {
+			label(canvas, format_length(path_length(coords)), Point::new((pair[0].x + pair[1].x) / 2.0, (pair[0].y + pair[1].y) / 2.0));
+		}
+		if points.len() > 2 { label(canvas, format!("Total {}", format_length(path_length(points))), screen[screen.len() - 1]); }
+	}
+
+	// In the top right corner, on a background that covers up the last one shown
+	fn draw_cursor_position(&self, canvas: &mut Canvas) {
+		let cursor = match self.cursor {
+			Some(cursor) => cursor,
+			None => return,
+		};
+		let (lat, lon) = self.view.to_world(cursor).wrapped().to_latlon().degrees();
+		let text = format!("{:.5}, {:.5}", lat, lon);
+		// Sized for the longest text, so a shorter one doesn't leave the end of the last one showing
+		let box_width = self.font.measure_str("-00.00000, -000.00000", Some(&self.text_paint)).0 + 12.0;
+		let text_width = self.font.measure_str(&text, Some(&self.text_paint)).0;
+		let width = self.size.0 as f32;
+		canvas.draw_rect(Rect::new(width - box_width, 0.0, width, STATUS_HEIGHT), &Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None));
+		canvas.draw_str(text, (width - text_width - 6.0, STATUS_HEIGHT - 4.0), &self.font, &self.text_paint);
+	}
+
+	// In the top left corner, on a background like the cursor position's
+	fn draw_loading(&mut self, canvas: &mut Canvas, count: usize) {
+		self.loading_shown = count > 0;
+		if count == 0 { return; }
+		let text = format!("Loading {} tile{}", count, if count == 1 { "" } else { "s" });
+		// Wide enough that the text getting shorter as tiles come in doesn't leave the end showing
+		let width = self.font.measure_str(&text, Some(&self.text_paint)).0.max(self.font.measure_str("Loading 0000 tiles", Some(&self.text_paint)).0) + 12.0;
+		canvas.draw_rect(Rect::new(0.0, 0.0, width, STATUS_HEIGHT), &Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None));
+		canvas.draw_str(text, (6.0, STATUS_HEIGHT - 4.0), &self.font, &self.text_paint);
+	}
+
+	// Just under the loading indicator, on a background like the cursor position's
+	fn draw_hud(&self, canvas: &mut Canvas, fps: usize, frame_time: Duration, cached: usize) {
+		let mut text = format!("{} fps, {:.1} ms, {} pending, {} cached", fps, frame_time.as_secs_f64() * 1000.0, self.render.borrow().pending(), cached);
+		if let Some(fill_time) = self.fill_time { text += &format!(", filled in {:.0} ms", fill_time.as_secs_f64() * 1000.0); }
+		let width = self.font.measure_str(&text, Some(&self.text_paint)).0.max(self.font.measure_str("000 fps, 000.0 ms, 0000 pending, 00000 cached, filled in 0000 ms", Some(&self.text_paint)).0) + 12.0;
+		canvas.draw_rect(Rect::new(0.0, STATUS_HEIGHT, width, 2.0 * STATUS_HEIGHT), &Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None));
+		canvas.draw_str(text, (6.0, 2.0 * STATUS_HEIGHT - 4.0), &self.font, &self.text_paint);
+	}
+
+	// In the bottom left corner, above the status bar, on a background like the cursor position's
+	fn draw_scale_bar(&self, canvas: &mut Canvas) {
+		if self.size.0 == 0 || self.size.1 == 0 { return; }
+		let (meters, length) = match scale_bar(self.view.meters_per_pixel(self.size), (self.size.0 as f32 / 4.0).min(SCALE_BAR_WIDTH)) {
+			Some(bar) => bar,
+			None => return,
+		};
+		let bottom = self.size.1 as f32 - STATUS_HEIGHT - 4.0;
+		canvas.draw_rect(Rect::new(0.0, bottom - 2.0 * STATUS_HEIGHT, SCALE_BAR_WIDTH + 12.0, bottom), &Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None));
+		canvas.draw_str(format_distance(meters), (6.0, bottom - STATUS_HEIGHT - 4.0), &self.font, &self.text_paint);
+		let mut path = Path::new();
+		path.move_to((6.0, bottom - STATUS_HEIGHT + 2.0));
+		path.line_to((6.0, bottom - 6.0));
+		path.line_to((6.0 + length, bottom - 6.0));
+		path.line_to((6.0 + length, bottom - STATUS_HEIGHT + 2.0));
+		let mut line = self.text_paint.clone();
+		line.set_style(paint::Style::Stroke);
+		line.set_stroke_width(2.0);
+		canvas.draw_path(&path, &line);
+	}
+
+	// List every material in the current theme with a swatch drawn using its own paints
+	fn draw_legend(&self, canvas: &mut Canvas) {
+		let render = self.render.borrow();
+		let materials = render.theme().materials();
+		let labels = legend_labels(render.theme());
+		let text_width = labels.iter().map(|label| self.font.measure_str(label, Some(&self.text_paint)).0).fold(0.0, f32::max);
+		let swatch = LEGEND_ROW_HEIGHT - 6.0;
+		let panel = Rect::new(4.0, 4.0, 4.0 + swatch + text_width + 16.0, 4.0 + LEGEND_ROW_HEIGHT * labels.len() as f32 + 8.0);
+		canvas.draw_rect(panel, &Paint::new(Color4f::new(0.0, 0.0, 0.0, 0.8), None));
+		for (i, ((_, material), label)) in materials.iter().zip(&labels).enumerate() {
+			let top = panel.top + 4.0 + LEGEND_ROW_HEIGHT * i as f32;
+			let swatch_rect = Rect::new(panel.left + 4.0, top + 3.0, panel.left + 4.0 + swatch, top + 3.0 + swatch);
+			for paint in material.paints(&self.paint_context()) {
+				canvas.draw_rect(swatch_rect, &paint);
+			}
+			canvas.draw_str(label, (swatch_rect.right + 8.0, top + LEGEND_ROW_HEIGHT - 4.0), &self.font, &self.text_paint);
+		}
+	}
+
+	// In the middle of the screen, in as many columns as it takes to fit
+	fn draw_help(&self, canvas: &mut Canvas) {
+		let rows = (((self.size.1 as f32 - 16.0) / LEGEND_ROW_HEIGHT) as usize).max(1);
+		let columns = self.help.chunks(rows).collect::<Vec<_>>();
+		let widths = columns.iter().map(|lines| lines.iter().map(|line| self.font.measure_str(line, Some(&self.text_paint)).0).fold(0.0, f32::max)).collect::<Vec<_>>();
+		let width = widths.iter().map(|width| width + 16.0).sum::<f32>();
+		let height = LEGEND_ROW_HEIGHT * self.help.len().min(rows) as f32 + 8.0;
+		let (left, top) = ((self.size.0 as f32 - width) / 2.0, (self.size.1 as f32 - height) / 2.0);
+		canvas.draw_rect(Rect::new(left, top, left + width, top + height), &Paint::new(Color4f::new(0.0, 0.0, 0.0, 0.8), None));
+		let mut x = left + 8.0;
+		for (lines, width) in columns.iter().zip(widths) {
+			for (i, line) in lines.iter().enumerate() {
+				canvas.draw_str(line, (x, top + LEGEND_ROW_HEIGHT * (i + 1) as f32), &self.font, &self.text_paint);
+			}
+			x += width + 16.0;
+		}
+	}
+
+	fn draw_status(&self, canvas: &mut Canvas) {
+		let mut status = vec![];
+		if !self.hidden_layers.is_empty() {
+			let mut hidden = self.hidden_layers.iter().collect::<Vec<_>>();
+			hidden.sort();
+			status.push(format!("Hidden layers: {}", hidden.iter().map(|layer| layer.to_string()).collect::<Vec<_>>().join(" ")));
+		}
+		if let Some(query) = &self.highlight { status.push(format!("Highlighting {}", query)); }
+		let dense = self.shown.iter().filter(|tile| tile.truncated).count();
+		if dense > 0 { status.push(format!("{} tiles too dense to show everything", dense)); }
+		if let Some(message) = &self.message { status.push(message.clone()); }
+		if !status.is_empty() {
+			let (width, height) = (self.size.0 as f32, self.size.1 as f32);
+			canvas.draw_rect(Rect::new(0.0, height - STATUS_HEIGHT, width, height), &Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None));
+			canvas.draw_str(status.join(" | "), (4.0, height - 4.0), &self.font, &self.text_paint);
+		}
+	}
+}
+
+// export-1 with the given extension in the current directory, or the next number up that's free
+fn export_path(extension: &str) -> std::path::PathBuf {
+	(1..).map(|i| std::path::PathBuf::from(format!("export-{}.{}", i, extension))).find(|path| !path.exists()).unwrap()
+}
+
+// Switch between a window and borderless fullscreen, putting the window back the size it was.
+// The resize comes through as a window event like any other.
+fn toggle_fullscreen(window: &mut sdl2::video::Window, windowed_size: &mut Option<(u32, u32)>) {
+	let result = match windowed_size.take() {
+		Some((width, height)) => window.set_fullscreen(FullscreenType::Off).and_then(|_| window.set_size(width, height).map_err(|e| e.to_string())),
+		None => {
+			*windowed_size = Some(window.size());
+			window.set_fullscreen(FullscreenType::Desktop)
+		},
+	};
+	if let Err(e) = result { eprintln!("Couldn't switch fullscreen: {}", e); }
+}
+
+// Read every tile of each map and report anything wrong with the file.  Returns whether all
+// maps passed.
+fn check(maps: &[Arc<mapsforge::MapFile>]) -> bool {
+	let mut ok = true;
+	for map in maps {
+		let report = map.check();
+		println!("{}: checked {} tiles in {} zoom intervals, found {} problems", map.path().display(), report.tiles_checked, report.intervals, report.problems.len());
+		for problem in &report.problems {
+			println!("\t{}", problem);
+		}
+		ok &= report.problems.is_empty();
+	}
+	ok
+}
+
+// Draw every slippy map tile in the zoom range that touches the bounds and save them as
+// dir/z/x/y.png, the usual layout for web map tiles.  Returns how many were written.
+fn export_tiles(render: Rc<RefCell<RenderManager>>, dir: &std::path::Path, zooms: (u8, u8), bounds: &BoundingBox, clip: Option<Vec<Coord>>, no_data: NoDataPattern, vignette: f32) -> Result<usize, String> {
+	let size = (slippy::TILE_SIZE, slippy::TILE_SIZE);
+	let mut surface = Surface::new_raster_n32_premul((size.0 as i32, size.1 as i32)).ok_or("Couldn't create a drawing surface")?;
+	let mut viewer = Viewer::new(render.clone(), (0, 0), size);
+	viewer.clip = clip;
+	viewer.no_data = no_data;
+	let mut count = 0;
+	for zoom in zooms.0..=zooms.1 {
+		let ((xmin, xmax), (ymin, ymax)) = slippy::tile_range(bounds, zoom);
+		for x in xmin..=xmax {
+			let column = dir.join(zoom.to_string()).join(x.to_string());
+			std::fs::create_dir_all(&column).map_err(|e| format!("{}: {}", column.display(), e))?;
+			for y in ymin..=ymax {
+				// No window means no hurry, so tiles are drawn smoothly the first time
+				viewer.view = slippy::tile_transform(zoom, x, y);
+				viewer.restart(0);
+				viewer.antialias = true;
+				let mut tiles = render.borrow_mut().viewport_tiles(&viewer.viewport(), size.0).into_iter().map(|tile| (0, tile)).collect();
+				let canvas = surface.canvas();
+				viewer.clear(canvas);
+				viewer.draw_map(canvas, &mut tiles);
+				// The vignette fades out the edges of all the tiles at this zoom together rather
+				// than each one, so they still fit together
+				let tile_size = slippy::TILE_SIZE as f32;
+				let frame = Rect::new((xmin - x) as f32 * tile_size, (ymin - y) as f32 * tile_size, (xmax - x + 1) as f32 * tile_size, (ymax - y + 1) as f32 * tile_size);
+				let background = render.borrow().theme().background(&viewer.paint_context());
+				export::draw_vignette(canvas, frame, background, vignette);
+				let png = surface.image_snapshot().encode_to_data(EncodedImageFormat::PNG).ok_or(format!("Couldn't encode tile {}/{}/{}", zoom, x, y))?;
+				let path = column.join(format!("{}.png", y));
+				std::fs::write(&path, png.as_bytes()).map_err(|e| format!("{}: {}", path.display(), e))?;
+				count += 1;
+			}
+		}
+	}
+	Ok(count)
+}
+
+fn exit_with_error(msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(2);
 }
 
 fn main() {
-	let maps: Vec<Arc<mapsforge::MapFile>> = std::env::args().skip(1).map(|path| Arc::new(mapsforge::MapFile::new(PathBuf::from(path)))).collect();
+	let args = std::env::args().skip(1).collect::<Vec<_>>();
+	if args.first().map(String::as_str) == Some("coord") {
+		println!("{}", slippy::describe_position(&args[1..]).unwrap_or_else(|msg| exit_with_error(&msg)));
+		return;
+	}
+	let opts = options::Options::parse(args.into_iter()).unwrap_or_else(|msg| exit_with_error(&msg));
+	let clip = opts.clip.as_ref().map(|path| options::read_polygon(path)).transpose().unwrap_or_else(|msg| exit_with_error(&msg));
+	let dem = opts.dem.as_ref().map(|path| Dem::from_file(path).map(Rc::new).map_err(|e| format!("{}: {}", path.display(), e)))
+		.transpose().unwrap_or_else(|msg| exit_with_error(&msg));
+	let keymap = opts.keys.as_ref().map(|path| keymap::Keymap::from_file(path).map_err(|e| format!("{}: {}", path.display(), e)))
+		.transpose().unwrap_or_else(|msg| exit_with_error(&msg)).unwrap_or_default();
+	let mut help = keymap.help();
+	// Bookmarks and layers are always on the number keys, so they aren't in the key map
+	help.extend(["1-9: jump to bookmark", "Shift+1-9: save bookmark", "Alt+1-9: show or hide layers -4 to 4"].iter().map(|line| line.to_string()));
+	if opts.diff && opts.maps.len() != 2 { exit_with_error("--diff needs exactly two maps"); }
+	if opts.view.is_some() && (opts.center.is_some() || opts.zoom.is_some()) { exit_with_error("--view can't be used with --center or --zoom"); }
+	if opts.diff && opts.theme.is_some() { exit_with_error("--theme can't be used with --diff, which has its own theme"); }
+	#[cfg(not(feature = "disk-cache"))]
+	if opts.tile_cache.is_some() { exit_with_error("--tile-cache needs the disk-cache feature"); }
+	#[cfg(feature = "disk-cache")]
+	let disk_cache = opts.tile_cache.as_ref().map(|dir| mapviewer::tilecache::DiskCache::new(dir).map(Arc::new))
+		.transpose().unwrap_or_else(|e| exit_with_error(&format!("--tile-cache: {}", e)));
+	let open = if opts.no_mmap { mapsforge::MapFile::new_buffered } else { mapsforge::MapFile::new };
+	// One bad file shouldn't stop the rest from loading
+	let mut load_failed = false;
+	let maps: Vec<Arc<mapsforge::MapFile>> = opts.maps.into_iter().filter_map(|path| match open(path.clone()) {
+		Ok(map) => Some(Arc::new(map)),
+		Err(e) => {
+			eprintln!("{}: skipping: {}", path.display(), e);
+			load_failed = true;
+			None
+		},
+	}).collect();
+	if opts.diff && maps.len() != 2 { exit_with_error("--diff needs exactly two maps, and not all of them loaded"); }
+	if (opts.prefault || opts.prefault_all) && opts.no_mmap { exit_with_error("--prefault only works on mapped maps, so it can't be used with --no-mmap"); }
+	if opts.prefault_all {
+		for map in &maps {
+			if let Err(e) = map.prefault() { eprintln!("{}: couldn't prefault: {}", map.path().display(), e); }
+		}
+	}
+	if opts.check {
+		std::process::exit(if check(&maps) && !load_failed { 0 } else { 1 });
+	}
 	if maps.is_empty() {
 		println!("Nothing to display");
 		return;
 	}
 
+	let render = Rc::new(RefCell::new(RenderManager::with_cache_limit(maps, opts.cache_limit)));
+	{
+		let mut render = render.borrow_mut();
+		if opts.diff { render.set_diff(); }
+		else if let Some(path) = &opts.theme {
+			render.set_theme(theme::Theme::from_file(path).unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path.display(), e))));
+		}
+		render.set_density_limit(opts.density_limit);
+		render.set_lang(opts.lang.clone());
+		render.set_prefault(opts.prefault);
+		#[cfg(feature = "disk-cache")]
+		if let Some(cache) = &disk_cache { render.set_disk_cache(cache.clone()); }
+	}
+	let clip = clip.map(|polygon| polygon.iter().map(|point| point.to_coord()).collect::<Vec<_>>());
+	if let Some(dir) = &opts.tiles {
+		let zooms = opts.zoom_range.unwrap_or_else(|| exit_with_error("--tiles needs a --zoom-range"));
+		let bounds = match opts.bbox {
+			Some(corners) => BoundingBox::from_corners((corners.0.to_coord(), corners.1.to_coord())).intersection(&render.borrow().bounds()),
+			None => render.borrow().bounds(),
+		};
+		if bounds.is_empty() { exit_with_error("--bbox doesn't overlap the map"); }
+		let count = export_tiles(render.clone(), dir, zooms, &bounds, clip, opts.no_data, opts.vignette).unwrap_or_else(|msg| exit_with_error(&msg));
+		eprintln!("Wrote {} tiles to {}", count, dir.display());
+		render.borrow().shutdown(Duration::from_millis(500));
+		return;
+	}
+
 	let sdl_context = sdl2::init().unwrap();
 	let video = sdl_context.video().unwrap();
-	let window = video
+	let mut window = video
 		.window("Map Viewer", 800, 600)
 		.position_centered()
 		.allow_highdpi()
 		.resizable()
 		.build().unwrap();
 	let size = window.vulkan_drawable_size();
+	// TODO Multisampling would give smoother polygon edges than Skia's coverage antialiasing
+	// alone, but skulpin 0.14 creates both the swapchain pipeline and the Skia surface with a
+	// fixed sample count of 1 and RendererBuilder has no way to change it.  Supporting an MSAA
+	// option needs a newer skulpin or a patched renderer.
 	let mut renderer = skulpin::RendererBuilder::new()
 		.coordinate_system(skulpin::CoordinateSystem::Logical)
 		.build(&window, RafxExtents2D { width: size.0, height: size.1 }).unwrap();
-	let mut events = Events::new(&sdl_context);
+	let mut events = Events::new(&sdl_context, keymap);
 
-	let mut viewer = Viewer::new(maps, (size.0, size.1));
+	let query = opts.highlight.as_deref().map(highlight::Query::parse);
+	let (pick, animate, no_data, start, fit, count) = (opts.pick, opts.animate, opts.no_data, opts.view, opts.fit, if opts.split { 2 } else { 1 });
+	let (center, zoom) = (opts.center, opts.zoom);
+	let theme_file = opts.theme;
+	let vignette = opts.vignette;
+	let mut viewers = split_layout(size, count).into_iter().map(|(origin, size)| {
+		let mut viewer = Viewer::new(render.clone(), origin, size);
+		viewer.pick = pick;
+		viewer.animate = animate;
+		viewer.no_data = no_data;
+		viewer.theme_file = theme_file.clone();
+		viewer.help = help.clone();
+		viewer.dem = dem.clone();
+		viewer.vignette = vignette;
+		viewer.highlight = query.clone();
+		viewer.clip = clip.clone();
+		viewer.clipboard = Some(video.clipboard());
+		if fit { viewer.zoom_to_fit(); }
+		viewer.view = command_line_view(viewer.view, start, center, zoom, viewer.size);
+		viewer
+	}).collect::<Vec<_>>();
+	if let Some(source) = opts.gps {
+		let updater = events.get_updater();
+		nmea::spawn_reader(source, move |event| updater.send(UpdateEvent::Gps(event)).is_ok());
+	}
+	let idle_timeout = opts.idle_timeout;
+	let mut last_active = Instant::now();
+	let mut asleep = false; // The idle hook has run and nothing has happened since
 	let mut redraw = true;
+	let mut theme_name = render.borrow().theme().name().to_string();
+	let mut windowed_size = None; // What to go back to when leaving fullscreen
+	let mut frame_stats = if opts.debug_hud { Some(FrameStats::new()) } else { None };
 	renderer.draw(RafxExtents2D { width: size.0, height: size.1 }, 1.0, |canvas, _| {
 		canvas.clear(Color::from_argb(0, 0, 0, 255));
 	}).unwrap();
 
 	loop {
-		events.update(!redraw);
+		let refine = viewers.iter().any(|viewer| viewer.needs_refinement());
+		// Animations need a steady stream of frames, so don't wait for events during one
+		let animating = viewers.iter().any(|viewer| viewer.animation.is_some() || viewer.glide.is_some());
+		// Without a refinement to wait for, wake up in time to go idle
+		// A view that's stopped moving needs waking up for to request its tiles
+		let settle = viewers.iter().filter_map(|viewer| viewer.settle_wait()).min();
+		let wait = if let Some(settle) = settle { Some(settle.max(1)) }
+			else if refine { Some(REFINE_DELAY) }
+			else if asleep { None }
+			else { idle_timeout.map(|timeout| timeout.saturating_sub(last_active.elapsed()).as_millis().max(1) as u32) };
+		events.update(!redraw && !animating, wait);
+		let frame_start = Instant::now();
 		if events.should_quit { break; }
+		if events.toggle_fullscreen {
+			toggle_fullscreen(&mut window, &mut windowed_size);
+			events.force_redraw = true;
+		}
+		if events.active || redraw || animating || render.borrow().busy() {
+			last_active = Instant::now();
+			asleep = false;
+		}
+		else if let Some(timeout) = idle_timeout {
+			if !asleep && !refine && last_active.elapsed() >= timeout {
+				let msg = go_idle(&render, &mut viewers);
+				#[cfg(feature = "disk-cache")]
+				let msg = match &disk_cache {
+					Some(cache) => {
+						let (hits, misses) = cache.take_stats();
+						format!("{}, {} loaded from the tile cache and {} built since the last idle", msg, hits, misses)
+					},
+					None => msg,
+				};
+				// Shown with a single redraw, after which nothing more is drawn until something happens
+				for viewer in &mut viewers {
+					viewer.message = Some(msg.clone());
+					viewer.overlay_dirty = true;
+				}
+				asleep = true;
+			}
+		}
 		let size = window.vulkan_drawable_size();
 		let extents = RafxExtents2D { width: size.0, height: size.1 };
-		redraw = viewer.update(&mut events, (size.0, size.1));
-		if redraw {
-			viewer.render.async_viewport_tiles(&viewer.viewport(), viewer.size.0, events.frames, events.get_updater());
-			// Without this call, junk on the canvas is not cleared when the window is resized.  Race condition?
-			renderer.draw(extents, 1.0, |_canvas, _| {
-				//viewer.clear(canvas);
+		// Input goes to the viewer under the mouse, or the one a drag started in
+		let layout = split_layout(size, count);
+		let pointer = events.drag_start.unwrap_or(events.mouse_pos);
+		let active = layout.iter().rposition(|(origin, _)| pointer.0 >= origin.0).unwrap_or(0);
+		redraw = false;
+		for (i, (viewer, (origin, size))) in viewers.iter_mut().zip(layout).enumerate() {
+			redraw |= viewer.update(&events, origin, size, i == active);
+		}
+		// Either half of a split view can switch the theme they share, which restyles both
+		if render.borrow().theme().name() != theme_name {
+			theme_name = render.borrow().theme().name().to_string();
+			for viewer in &mut viewers { viewer.forget_tiles(); }
+			redraw = true;
+		}
+		// The cache is behind a lock the render threads use too, so it's only counted when tiles
+		// are requested or come in, which are the only times it changes
+		if let Some(stats) = frame_stats.as_mut().filter(|_| redraw || !events.tiles_ready.is_empty()) { stats.cached = render.borrow().cached(); }
+		for viewer in &mut viewers { viewer.hud = frame_stats.as_ref().map(|stats| (stats.frames.len(), stats.frame_time, stats.cached)); }
+		// Problems reading the maps show up in the status bar as well as on stderr
+		if let Some(msg) = render.borrow().take_error() {
+			for viewer in &mut viewers {
+				viewer.message = Some(msg.clone());
+				viewer.overlay_dirty = true;
+			}
+		}
+		let drew = if redraw {
+			// Tiles for every viewer are requested again, even ones that haven't changed, since a
+			// new request cancels everything older.  Their tiles will mostly be cached.
+			for (i, viewer) in viewers.iter_mut().enumerate() {
+				viewer.restart(events.frames);
+				render.borrow_mut().async_viewport_tiles(&viewer.viewport(), viewer.size.0, events.frames, events.get_updater().for_viewer(i));
+			}
+			// The old tiles are drawn again at the new view while the new ones load.  This also
+			// clears junk left on the canvas when the window is resized.
+			renderer.draw(extents, 1.0, |canvas, _| {
+				draw_viewers(canvas, &mut viewers, |_, viewer, canvas| viewer.draw(canvas, &mut vec![]));
+			}).unwrap();
+			true
+		}
+		else if !events.tiles_ready.is_empty() || viewers.iter().any(|viewer| viewer.overlay_dirty) {
+			let mut tiles = vec![vec![]; viewers.len()];
+			for (i, generation, tile) in events.tiles_ready.drain(..) { tiles[i].push((generation, tile)); }
+			renderer.draw(extents, 1.0, |canvas, _| {
+				draw_viewers(canvas, &mut viewers, |i, viewer, canvas| viewer.draw(canvas, &mut tiles[i]));
 			}).unwrap();
+			true
 		}
-		else if !events.tiles_ready.is_empty() {
+		else if events.idle && refine {
 			renderer.draw(extents, 1.0, |canvas, _| {
-				viewer.draw(canvas, &mut events.tiles_ready);
+				draw_viewers(canvas, &mut viewers, |_, viewer, canvas| if viewer.needs_refinement() { viewer.refine(canvas); });
 			}).unwrap();
+			true
 		}
+		else { false };
+		if let Some(stats) = frame_stats.as_mut().filter(|_| drew) { stats.record(frame_start); }
 		events.frames += 1;
 	}
+	// Let the render threads wind down before the event system they report to goes away
+	if !render.borrow().shutdown(Duration::from_millis(500)) {
+		eprintln!("Timed out waiting for render jobs to finish");
+	}
+	#[cfg(feature = "disk-cache")]
+	if let Some(cache) = disk_cache {
+		let (hits, misses) = cache.stats();
+		eprintln!("Tile cache: {} tiles loaded, {} built", hits, misses);
+	}
+}
+
+#[test]
+fn test_script_detection() {
+	assert!(!needs_shaping("Straße"));
+	assert!(!needs_shaping("Αθήνα Москва"));
+	assert!(needs_shaping("القاهرة") && is_rtl("القاهرة"));
+	assert!(needs_shaping("ירושלים") && is_rtl("ירושלים"));
+	assert!(needs_shaping("नई दिल्ली") && !is_rtl("नई दिल्ली"));
+	assert!(!is_rtl("Cairo"));
+}
+
+#[test]
+fn test_cull_labels() {
+	let label = |left: f32, priority: i32| Label { text: priority.to_string(), origin: (left, 10.0), bounds: Rect::new(left, 0.0, left + 30.0, 10.0), blob: None, priority, layer: 0 };
+	let mut placed = vec![Rect::new(200.0, 0.0, 220.0, 10.0)];
+	// The street name comes first but overlaps the city name, which wins
+	let kept = cull_labels(vec![label(0.0, 0), label(20.0, 10), label(100.0, 0), label(190.0, 10)], &mut placed);
+	assert_eq!(kept.iter().map(|label| label.text.as_str()).collect::<Vec<_>>(), vec!["10", "0"]);
+	assert_eq!(kept[1].origin.0, 100.0);
+	assert_eq!(placed.len(), 3);
+	// With the same priority, the higher layer wins
+	let bridge = Label { layer: 1, ..label(10.0, 0) };
+	let kept = cull_labels(vec![label(0.0, 0), bridge], &mut vec![]);
+	assert_eq!(kept.iter().map(|label| label.layer).collect::<Vec<_>>(), vec![1]);
+	// In the basic theme, a lake's name wins over the street running along its shore
+	let basic = theme::basic();
+	let priority = |name: &str| basic.material(name).unwrap().label_priority();
+	let (street, lake) = (label(0.0, priority("road")), label(20.0, priority("water_area")));
+	let kept = cull_labels(vec![street, lake], &mut vec![]);
+	assert_eq!(kept.iter().map(|label| label.origin.0).collect::<Vec<_>>(), vec![20.0]);
+}
+
+#[test]
+fn test_heatmap() {
+	let points = vec![(5, 5), (10, 15), (20, 3), (-1, 4), (100, 100), (799, 599)];
+	let bins = bin_points(points.into_iter().map(|(x, y)| Coord { x, y }), 16, (800, 600));
+	assert_eq!(bins.get(&(0, 0)), Some(&2));
+	assert_eq!(bins.get(&(1, 0)), Some(&1));
+	assert_eq!(bins.get(&(49, 37)), Some(&1));
+	assert_eq!(bins.values().sum::<u32>(), 5); // Offscreen ones are ignored
+	let (cold, warm, hot) = (heat_color(0.0), heat_color(0.5), heat_color(1.0));
+	assert!(cold.b > warm.b && warm.b >= hot.b);
+	assert!(cold.r < warm.r && warm.g > hot.g);
+}
+
+#[test]
+fn test_scale_bar() {
+	assert_eq!(scale_bar(1.0, 150.0), Some((100.0, 100.0)));
+	assert_eq!(scale_bar(10.0, 150.0), Some((1000.0, 100.0)));
+	assert_eq!(scale_bar(4.0, 150.0), Some((500.0, 125.0)));
+	assert_eq!(scale_bar(1.0, 250.0), Some((200.0, 200.0)));
+	assert_eq!(format_distance(500.0), "500 m");
+	assert_eq!(format_distance(2000.0), "2 km");
+	assert_eq!(scale_bar(f64::NAN, 150.0), None);
+	assert_eq!(scale_bar(1.0, 0.0), None);
+}
+
+#[test]
+fn test_double_click() {
+	assert!(is_double_click(0.2, (100, 100), (102, 97)));
+	assert!(!is_double_click(DOUBLE_CLICK_TIME + 0.1, (100, 100), (100, 100)));
+	assert!(!is_double_click(0.2, (100, 100), (100, 110)));
+}
+
+#[test]
+fn test_glide_step() {
+	// Moving in small steps ends up in the same place as one big one, however the frames fall
+	let (mut moved, mut velocity) = (0.0, 1000.0);
+	for _ in 0..10 {
+		let (distance, slower) = glide_step(velocity, 0.01);
+		moved += distance;
+		velocity = slower;
+	}
+	let (distance, slower) = glide_step(1000.0, 0.1);
+	assert!((moved - distance).abs() < 1e-6 && (velocity - slower).abs() < 1e-6);
+	// Left to itself it goes GLIDE_TIME seconds' worth of its starting speed
+	assert!((glide_step(1000.0, 100.0).0 - 1000.0 * GLIDE_TIME).abs() < 1e-6);
+}
+
+#[test]
+fn test_split_layout() {
+	assert_eq!(split_layout((800, 600), 1), vec![((0, 0), (800, 600))]);
+	assert_eq!(split_layout((801, 600), 2), vec![((0, 0), (400, 600)), ((400, 0), (401, 600))]);
+}
+
+#[test]
+fn test_night_matrix() {
+	let night = |(r, g, b): (f32, f32, f32)| {
+		let row = |i: usize| NIGHT_MATRIX[i * 5] * r + NIGHT_MATRIX[i * 5 + 1] * g + NIGHT_MATRIX[i * 5 + 2] * b + NIGHT_MATRIX[i * 5 + 4];
+		(row(0), row(1), row(2))
+	};
+	let close = |a: (f32, f32, f32), b: (f32, f32, f32)| (a.0 - b.0).abs() < 0.01 && (a.1 - b.1).abs() < 0.01 && (a.2 - b.2).abs() < 0.01;
+	assert!(close(night((1.0, 1.0, 1.0)), (0.0, 0.0, 0.0)));
+	assert!(close(night((0.0, 0.0, 0.0)), (1.0, 1.0, 1.0)));
+	assert!(close(night((0.5, 0.5, 0.5)), (0.5, 0.5, 0.5)));
+	// Light blue water comes out a darker blue
+	let (r, g, b) = night((0.6, 0.7, 0.9));
+	assert!(b > r && b > g && r + g + b < 0.6 + 0.7 + 0.9);
+}
+
+#[test]
+fn test_clip_polygon() {
+	let path = std::env::temp_dir().join(format!("mapviewer-clip-{}.txt", std::process::id()));
+	std::fs::write(&path, "# A square around the origin\n-1,-1\n1,-1\n1,1\n-1,1\n").unwrap();
+	let polygon = options::read_polygon(&path).unwrap().iter().map(|point| point.to_coord()).collect::<Vec<_>>();
+	std::fs::remove_file(&path).unwrap();
+	let size = (800, 600);
+	let view = Transform::centered(mapsforge::LatLon::from_degrees(0.0, 0.0).to_coord(), 8.0, SLIPPY_TILE_SIZE, size);
+	let square = clip_polygon(&view, &polygon, size);
+	assert_eq!(square.len(), 4);
+	let (xs, ys) = (square.iter().map(|point| point.x).collect::<Vec<_>>(), square.iter().map(|point| point.y).collect::<Vec<_>>());
+	// It's centered on the window and goes round the middle of it
+	assert!(xs.iter().any(|x| *x < 400.0) && xs.iter().any(|x| *x > 400.0), "{:?}", xs);
+	assert!(ys.iter().any(|y| *y < 300.0) && ys.iter().any(|y| *y > 300.0), "{:?}", ys);
+	// Panned once around the world, it's in the same place
+	let lapped = Transform { offset: Coord { x: view.offset.x + mapsforge::COORD_MAX, y: view.offset.y }, ..view };
+	assert_eq!(clip_polygon(&lapped, &polygon, size), square);
+}
+
+#[test]
+fn test_legend_labels() {
+	let mut render = RenderManager::new(vec![]);
+	let basic = legend_labels(render.theme());
+	assert!(basic.iter().any(|label| label.starts_with("water_area")), "{:?}", basic);
+	// The legend is read from whichever theme the tiles are drawn with
+	render.set_theme(theme::access());
+	let access = legend_labels(render.theme());
+	assert_ne!(access, basic);
+	assert!(access.iter().any(|label| label.starts_with("road_private")), "{:?}", access);
+	assert_eq!(access.len(), theme::access().materials().len());
+}
+
+#[test]
+fn test_start_view() {
+	use mapsforge::LatLon;
+	let size = (800, 600);
+	let fitted = Transform::centered(LatLon::from_degrees(10.0, 20.0).to_coord(), 5.0, SLIPPY_TILE_SIZE, size);
+	let close = |view: Transform, lat: f64, lon: f64, zoom: f64| {
+		let (center_lat, center_lon) = view.center(size).to_latlon().degrees();
+		(center_lat - lat).abs() < 1e-3 && (center_lon - lon).abs() < 1e-3 && (view.zoom_level(SLIPPY_TILE_SIZE) - zoom).abs() < 1e-2
+	};
+	// The map's suggestion, filling in from the fitted view what it leaves out
+	let pos = Some(LatLon::from_degrees(40.0, -70.0));
+	assert!(close(suggested_view(None, None, SLIPPY_TILE_SIZE, fitted, size), 10.0, 20.0, 5.0));
+	assert!(close(suggested_view(pos, Some(12), SLIPPY_TILE_SIZE, fitted, size), 40.0, -70.0, 12.0));
+	assert!(close(suggested_view(pos, None, SLIPPY_TILE_SIZE, fitted, size), 40.0, -70.0, 5.0));
+	assert!(close(suggested_view(None, Some(12), SLIPPY_TILE_SIZE, fitted, size), 10.0, 20.0, 12.0));
+	// --view replaces whatever the map or --fit start at, keeping only the zoom if it has none
+	let link = |zoom| Some(viewurl::View { center: LatLon::from_degrees(-30.0, 150.0), zoom });
+	assert!(close(command_line_view(fitted, None, None, None, size), 10.0, 20.0, 5.0));
+	assert!(close(command_line_view(fitted, link(Some(14.0)), None, None, size), -30.0, 150.0, 14.0));
+	assert!(close(command_line_view(fitted, link(None), None, None, size), -30.0, 150.0, 5.0));
+	// --center and --zoom each replace just their part
+	let center = Some(LatLon::from_degrees(51.5, -0.1));
+	assert!(close(command_line_view(fitted, None, center, None, size), 51.5, -0.1, 5.0));
+	assert!(close(command_line_view(fitted, None, None, Some(9.0), size), 10.0, 20.0, 9.0));
+	assert!(close(command_line_view(fitted, None, center, Some(9.0), size), 51.5, -0.1, 9.0));
+}
+
+#[test]
+fn test_way_path() {
+	// Each polyline of a way is a contour of its own, so joins go only at its bends and caps at
+	// both of its ends, rather than a stray segment joining one polyline to the next
+	let polies = vec![
+		vec![Coord { x: 0, y: 0 }, Coord { x: 10, y: 0 }, Coord { x: 10, y: 10 }],
+		vec![Coord { x: 20, y: 20 }, Coord { x: 30, y: 20 }, Coord { x: 30, y: 40 }],
+	];
+	let (path, bounds) = way_path(&polies, false, |point| Coord { x: point.x * 2, y: point.y * 2 });
+	assert_eq!((path.count_points(), path.count_verbs()), (6, 6)); // Two moves and four lines
+	assert!(!path.is_last_contour_closed());
+	assert_eq!(bounds.corners(), Some((Coord { x: 0, y: 0 }, Coord { x: 60, y: 80 })));
+	// Areas close each ring, so their outlines have no caps
+	let (path, _) = way_path(&polies, true, |point| point);
+	assert_eq!((path.count_points(), path.count_verbs()), (6, 8));
+	assert!(path.is_last_contour_closed());
 }
@@ -0,0 +1,261 @@
+// Small maps built in memory for tests, which saves committing binary fixtures.  This is the
+// parse module in reverse, but only writes what the parser needs: there's no debug information or
+// optional header field other than the start position and zoom, and tags are all literals.
+
+use std::path::PathBuf;
+
+use super::{coord2tile, subtile_bit, tile_origin, tile_range, LatLon, LatLonBounds, MapFile, MAGIC, WATER_TILE_FLAG};
+
+// Variable-length numbers the way maps store them
+pub fn encode_vbe_u(mut n: u64) -> Vec<u8> {
+	let mut ret = vec![];
+	while n >= 0x80 { ret.push(0x80 | (n & 0x7f) as u8); n >>= 7; }
+	ret.push(n as u8);
+	ret
+}
+
+pub fn encode_vbe_s(n: i64) -> Vec<u8> {
+	let mut mag = n.unsigned_abs();
+	let mut ret = vec![];
+	while mag >= 0x40 { ret.push(0x80 | (mag & 0x7f) as u8); mag >>= 7; }
+	ret.push(mag as u8 | if n < 0 { 0x40 } else { 0 });
+	ret
+}
+
+fn encode_string(s: &str) -> Vec<u8> {
+	[encode_vbe_u(s.len() as u64), s.as_bytes().to_vec()].concat()
+}
+
+fn encode_offset(from: LatLon, to: LatLon) -> Vec<u8> {
+	[encode_vbe_s((to.lat - from.lat) as i64), encode_vbe_s((to.lon - from.lon) as i64)].concat()
+}
+
+struct Feature {
+	zoom: u8, // The first zoom level it shows up at
+	layer: i8,
+	name: Option<String>,
+	tags: Vec<String>, // As key=value
+	points: Vec<LatLon>, // Just one for a POI
+	subtiles: Option<u16>, // Instead of working out which sub-tiles a way is in
+}
+
+impl Feature {
+	// The name and layer tags go in fields of their own
+	fn new(zoom: u8, points: &[(f64, f64)], tags: &[(&str, &str)], subtiles: Option<u16>) -> Self {
+		let mut ret = Self { zoom, layer: 0, name: None, tags: vec![], points: points.iter().map(|point| LatLon::from_degrees(point.0, point.1)).collect(), subtiles };
+		for (key, value) in tags {
+			match *key {
+				"name" => ret.name = Some(value.to_string()),
+				"layer" => ret.layer = value.parse().expect("Layer isn't a number"),
+				_ => ret.tags.push(format!("{}={}", key, value)),
+			}
+		}
+		ret
+	}
+
+	// The top left and bottom right tiles its points are in
+	fn tile_span(&self, zoom: u8) -> ((u32, u32), (u32, u32)) {
+		let lat = self.points.iter().map(|point| point.lat);
+		let lon = self.points.iter().map(|point| point.lon);
+		let (lat_min, lat_max, lon_min, lon_max) = (lat.clone().min().unwrap(), lat.max().unwrap(), lon.clone().min().unwrap(), lon.max().unwrap());
+		(coord2tile(zoom, LatLon::new(lat_max, lon_min)), coord2tile(zoom, LatLon::new(lat_min, lon_max)))
+	}
+
+	// The sub-tiles of a base tile that the feature's bounding box overlaps
+	fn subtile_map(&self, base: u8, x: u32, y: u32) -> u16 {
+		if let Some(subtiles) = self.subtiles { return subtiles; }
+		let (first, last) = self.tile_span(base + 2);
+		let mut ret = 0;
+		for dy in 0..4 {
+			for dx in 0..4 {
+				let (subx, suby) = (x * 4 + dx, y * 4 + dy);
+				if (first.0..=last.0).contains(&subx) && (first.1..=last.1).contains(&suby) { ret |= subtile_bit(dx as u8, dy as u8); }
+			}
+		}
+		ret
+	}
+
+	// The first byte after the position or sub-tile map, the tag IDs, and the optional fields'
+	// flags and the name if there is one
+	fn encode_tags(&self, table: &[String]) -> Vec<u8> {
+		assert!(self.tags.len() < 16, "Too many tags");
+		let mut ret = vec![((self.layer + 5) as u8) << 4 | self.tags.len() as u8];
+		for tag in &self.tags { ret.extend(encode_vbe_u(table.iter().position(|x| x == tag).unwrap() as u64)); }
+		ret.push(if self.name.is_some() { 0x80 } else { 0 });
+		if let Some(name) = &self.name { ret.extend(encode_string(name)); }
+		ret
+	}
+}
+
+pub struct MapBuilder {
+	bounds: LatLonBounds,
+	intervals: Vec<(u8, u8, u8)>, // Base, min, and max zoom
+	pois: Vec<Feature>,
+	ways: Vec<Feature>,
+	sea: bool, // Flag tiles with nothing in them as all water
+	start_pos: Option<LatLon>,
+	start_zoom: Option<u8>,
+}
+
+impl MapBuilder {
+	// A map of the area between two corners in degrees
+	pub fn new(min: (f64, f64), max: (f64, f64)) -> Self {
+		let (min, max) = (LatLon::from_degrees(min.0, min.1), LatLon::from_degrees(max.0, max.1));
+		Self { bounds: LatLonBounds { lat_min: min.lat, lon_min: min.lon, lat_max: max.lat, lon_max: max.lon }, intervals: vec![], pois: vec![], ways: vec![], sea: false, start_pos: None, start_zoom: None }
+	}
+
+	pub fn interval(mut self, base: u8, min: u8, max: u8) -> Self {
+		self.intervals.push((base, min, max));
+		self
+	}
+
+	// A POI that first shows up at a zoom level
+	pub fn poi(mut self, zoom: u8, point: (f64, f64), tags: &[(&str, &str)]) -> Self {
+		self.pois.push(Feature::new(zoom, &[point], tags, None));
+		self
+	}
+
+	// A way through the points, in each tile it passes near
+	pub fn way(mut self, zoom: u8, points: &[(f64, f64)], tags: &[(&str, &str)]) -> Self {
+		self.ways.push(Feature::new(zoom, points, tags, None));
+		self
+	}
+
+	// A way with a sub-tile map that needn't match where it is
+	pub fn way_in_subtiles(mut self, zoom: u8, points: &[(f64, f64)], tags: &[(&str, &str)], subtiles: u16) -> Self {
+		self.ways.push(Feature::new(zoom, points, tags, Some(subtiles)));
+		self
+	}
+
+	// Where the header suggests the view starts
+	pub fn start_pos(mut self, point: (f64, f64)) -> Self {
+		self.start_pos = Some(LatLon::from_degrees(point.0, point.1));
+		self
+	}
+
+	pub fn start_zoom(mut self, zoom: u8) -> Self {
+		self.start_zoom = Some(zoom);
+		self
+	}
+
+	// Flag the tiles that nothing is in as all water, the way open sea is stored
+	pub fn sea(mut self) -> Self {
+		self.sea = true;
+		self
+	}
+
+	// Each feature goes in every interval up to whose max zoom it shows up
+	fn subfile(&self, (base, min, max): (u8, u8, u8), poi_tags: &[String], way_tags: &[String]) -> Vec<u8> {
+		let (first, last) = tile_range(base, &self.bounds);
+		let ntiles = ((last.0 - first.0 + 1) * (last.1 - first.1 + 1)) as u64;
+		let row = |feature: &&Feature| feature.zoom.max(min);
+		let mut pois = self.pois.iter().filter(|poi| poi.zoom <= max).collect::<Vec<_>>();
+		let mut ways = self.ways.iter().filter(|way| way.zoom <= max).collect::<Vec<_>>();
+		pois.sort_by_key(row);
+		ways.sort_by_key(row);
+		let mut index = vec![];
+		let mut data = vec![];
+		for y in first.1..=last.1 {
+			for x in first.0..=last.0 {
+				let in_tile = |feature: &&&Feature| {
+					let (tl, br) = feature.tile_span(base);
+					(tl.0..=br.0).contains(&x) && (tl.1..=br.1).contains(&y)
+				};
+				let (pois, ways) = (pois.iter().filter(in_tile).collect::<Vec<_>>(), ways.iter().filter(in_tile).collect::<Vec<_>>());
+				let water = if self.sea && pois.is_empty() && ways.is_empty() { WATER_TILE_FLAG } else { 0 };
+				index.extend_from_slice(&((5 * ntiles + data.len() as u64) | water).to_be_bytes()[3..]);
+				if pois.is_empty() && ways.is_empty() { continue; }
+				for zoom in min..=max {
+					data.extend(encode_vbe_u(pois.iter().filter(|poi| row(poi) == zoom).count() as u64));
+					data.extend(encode_vbe_u(ways.iter().filter(|way| row(way) == zoom).count() as u64));
+				}
+				let origin = tile_origin(base, x, y);
+				let poi_data = pois.iter().flat_map(|poi| [encode_offset(origin, poi.points[0]), poi.encode_tags(poi_tags)].concat()).collect::<Vec<_>>();
+				data.extend(encode_vbe_u(poi_data.len() as u64));
+				data.extend(poi_data);
+				for way in ways {
+					let mut points = encode_vbe_u(way.points.len() as u64);
+					let mut prev = origin;
+					for point in &way.points {
+						points.extend(encode_offset(prev, *point));
+						prev = *point;
+					}
+					let way_data = [way.subtile_map(base, x, y).to_be_bytes().to_vec(), way.encode_tags(way_tags), encode_vbe_u(1), points].concat();
+					data.extend(encode_vbe_u(way_data.len() as u64));
+					data.extend(way_data);
+				}
+			}
+		}
+		[index, data].concat()
+	}
+
+	pub fn build(&self) -> Vec<u8> {
+		let tag_table = |features: &[Feature]| {
+			let mut ret: Vec<String> = vec![];
+			for tag in features.iter().flat_map(|feature| &feature.tags) {
+				if !ret.contains(tag) { ret.push(tag.clone()); }
+			}
+			ret
+		};
+		let (poi_tags, way_tags) = (tag_table(&self.pois), tag_table(&self.ways));
+		let subfiles = self.intervals.iter().map(|interval| self.subfile(*interval, &poi_tags, &way_tags)).collect::<Vec<_>>();
+		let mut header = [3_u32.to_be_bytes().to_vec(), vec![0; 16]].concat(); // Version, file size, and creation date
+		for field in &[self.bounds.lat_min, self.bounds.lon_min, self.bounds.lat_max, self.bounds.lon_max] { header.extend(&field.to_be_bytes()); }
+		header.extend(&256_u16.to_be_bytes());
+		header.extend(encode_string("Mercator"));
+		header.push(if self.start_pos.is_some() { 0x40 } else { 0 } | if self.start_zoom.is_some() { 0x20 } else { 0 });
+		if let Some(pos) = self.start_pos { header.extend([pos.lat.to_be_bytes(), pos.lon.to_be_bytes()].concat()); }
+		header.extend(self.start_zoom);
+		for table in &[&poi_tags, &way_tags] {
+			header.extend(&(table.len() as u16).to_be_bytes());
+			for tag in table.iter() { header.extend(encode_string(tag)); }
+		}
+		header.push(subfiles.len() as u8);
+		let mut start = 24 + header.len() as u64 + 19 * subfiles.len() as u64;
+		for ((base, min, max), subfile) in self.intervals.iter().zip(&subfiles) {
+			header.extend(&[*base, *min, *max]);
+			header.extend(&start.to_be_bytes());
+			header.extend(&(subfile.len() as u64).to_be_bytes());
+			start += subfile.len() as u64;
+		}
+		let mut ret = [MAGIC.to_vec(), (header.len() as u32).to_be_bytes().to_vec(), header].concat();
+		for subfile in subfiles { ret.extend(subfile); }
+		ret
+	}
+
+	pub fn open(&self) -> MapFile {
+		MapFile::from_bytes(PathBuf::from("test.map"), self.build()).unwrap()
+	}
+}
+
+#[test]
+fn test_round_trip() {
+	use super::TagValue;
+	let map = MapBuilder::new((51.0, -0.5), (52.0, 0.5))
+		.interval(10, 8, 11)
+		.poi(9, (51.5, -0.1), &[("amenity", "cafe"), ("name", "Corner Cafe")])
+		.poi(11, (51.5, -0.1), &[("amenity", "bench")])
+		.way(8, &[(51.49, -0.12), (51.51, -0.08)], &[("highway", "primary"), ("layer", "1")])
+		.way(10, &[(51.1, 0.4), (51.101, 0.401)], &[("building", "yes")])
+		.open();
+	let (x, y) = coord2tile(10, LatLon::from_degrees(51.5, -0.1));
+	let tile = map.tile(10, x, y).unwrap();
+	assert_eq!(tile.pois.len(), 1);
+	let cafe = &tile.pois[0];
+	assert_eq!(cafe.name(None), Some("Corner Cafe"));
+	assert_eq!(cafe.tags.get("amenity"), Some(&TagValue::Literal("cafe".to_string())));
+	assert_eq!(cafe.offset.add(&tile.origin), LatLon::from_degrees(51.5, -0.1));
+	assert_eq!(tile.ways.len(), 1);
+	let road = &tile.ways[0];
+	assert_eq!((road.layer, road.tags.get("highway")), (1, Some(&TagValue::Literal("primary".to_string()))));
+	let points = road.blocks[0][0].iter().map(|point| point.add(&tile.origin)).collect::<Vec<_>>();
+	assert_eq!(points, vec![LatLon::from_degrees(51.49, -0.12), LatLon::from_degrees(51.51, -0.08)]);
+	// Zoomed in further, the bench shows up too
+	let (x, y) = coord2tile(11, LatLon::from_degrees(51.5, -0.1));
+	assert_eq!(map.tile(11, x, y).unwrap().pois.len(), 2);
+	// The building is only in the tile it's in
+	let (x, y) = coord2tile(10, LatLon::from_degrees(51.1, 0.4));
+	let tile = map.tile(10, x, y).unwrap();
+	assert_eq!(tile.ways.iter().map(|way| way.tags.contains_key("building")).collect::<Vec<_>>(), vec![true]);
+	assert!(tile.pois.is_empty());
+}
@@ -0,0 +1,116 @@
+use super::{tile_range, MapError, MapFile};
+
+#[derive(Debug)]
+pub enum Problem {
+	// The tile's offset is lower than the one before it, so one of them must be wrong
+	OffsetOrder { zoom: u8, index: (u32, u32), offset: u64 },
+	// The tile's data doesn't lie entirely within the subfile for its zoom interval
+	OutsideSubfile { zoom: u8, index: (u32, u32), offset: u64 },
+	// Parsing the tile ran past the offset where the next tile starts
+	Overrun { zoom: u8, index: (u32, u32), end: u64, expected: u64 },
+	// The tile has data but nothing in it, where an empty tile should take up no space
+	EmptyWithData { zoom: u8, index: (u32, u32), water: bool },
+	Tile { zoom: u8, index: (u32, u32), error: MapError },
+}
+
+impl std::fmt::Display for Problem {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Problem::OffsetOrder { zoom, index, offset } => write!(f, "zoom {} tile {:?}: offset {:#x} is lower than the previous tile's", zoom, index, offset),
+			Problem::OutsideSubfile { zoom, index, offset } => write!(f, "zoom {} tile {:?}: offset {:#x} is outside its subfile", zoom, index, offset),
+			Problem::Overrun { zoom, index, end, expected } => write!(f, "zoom {} tile {:?}: data ends at {:#x} but the next tile starts at {:#x}", zoom, index, end, expected),
+			Problem::EmptyWithData { zoom, index, water } => write!(f, "zoom {} tile {:?}: data has no features but the tile isn't stored empty{}", zoom, index, if *water { " (it's flagged as water)" } else { "" }),
+			Problem::Tile { zoom, index, error } => write!(f, "zoom {} tile {:?}: {}", zoom, index, error),
+		}
+	}
+}
+
+pub struct CheckReport {
+	pub intervals: usize,
+	pub tiles_checked: u64,
+	pub problems: Vec<Problem>,
+}
+
+impl MapFile {
+	// Read every tile at the base zoom of every zoom interval, collecting anything that looks
+	// inconsistent rather than stopping at the first problem
+	pub fn check(&self) -> CheckReport {
+		let mut report = CheckReport { intervals: self.header.zoom_intervals.len(), tiles_checked: 0, problems: vec![] };
+		for (subfile_num, interval) in self.header.zoom_intervals.iter().enumerate() {
+			let zoom = interval.base;
+			let subfile_end = interval.start + interval.len;
+			let (min, max) = tile_range(zoom, &self.header.bounds);
+			let rowlen = max.0 - min.0 + 1;
+			let offsets = &self.indices[subfile_num].tile_offsets;
+			let mut prev_offset = interval.start;
+			for tile_idx in 0 .. offsets.len() as u32 {
+				report.tiles_checked += 1;
+				let index = (min.0 + tile_idx % rowlen, min.1 + tile_idx / rowlen);
+				let (offset, end, water) = match self.tile_span(subfile_num as u8, tile_idx) {
+					Ok(span) => span,
+					Err(error) => { report.problems.push(Problem::Tile { zoom, index, error }); continue; },
+				};
+				if offset < prev_offset { report.problems.push(Problem::OffsetOrder { zoom, index, offset }); }
+				prev_offset = offset;
+				if offset < interval.start || offset > subfile_end || end > subfile_end {
+					report.problems.push(Problem::OutsideSubfile { zoom, index, offset });
+					continue;
+				}
+				// Water tiles can still carry data, so parse those too
+				if end <= offset { continue; }
+				match self.read_tile(interval, index.0, index.1, offset, end) {
+					Ok((_, zoom_table, _)) => {
+						if zoom_table.iter().all(|&counts| counts == (0, 0)) { report.problems.push(Problem::EmptyWithData { zoom, index, water }); }
+					},
+					Err(error) => {
+						// If the tile parses once it's allowed to run into the tiles after it, the
+						// data itself is fine and it's the offsets that are wrong
+						match self.read_tile(interval, index.0, index.1, offset, subfile_end) {
+							Ok((_, _, tile_end)) if tile_end > end => report.problems.push(Problem::Overrun { zoom, index, end: tile_end, expected: end }),
+							_ => report.problems.push(Problem::Tile { zoom, index, error }),
+						}
+					},
+				}
+			}
+		}
+		report
+	}
+}
+
+#[cfg(test)]
+fn check_corrupted(corrupt: impl Fn(&mut Vec<u8>, usize, u64)) -> CheckReport {
+	use super::builder::MapBuilder;
+	use super::{coord2tile, LatLon};
+	let builder = MapBuilder::new((51.0, -0.5), (52.0, 0.5))
+		.interval(10, 8, 11)
+		.poi(9, (51.5, -0.1), &[("amenity", "cafe")])
+		.poi(9, (51.1, 0.4), &[("amenity", "bench")])
+		.sea();
+	let map = builder.open();
+	// Where the cafe's tile is listed in the index, and where its data starts
+	let (min, max) = tile_range(10, &map.header.bounds);
+	let (x, y) = coord2tile(10, LatLon::from_degrees(51.5, -0.1));
+	let tile_idx = ((y - min.1) * (max.0 - min.0 + 1) + x - min.0) as usize;
+	let mut data = builder.build();
+	corrupt(&mut data, map.header.zoom_intervals[0].start as usize + 5 * tile_idx, map.indices[0].tile_offsets[tile_idx]);
+	MapFile::from_bytes(map.path.clone(), data).unwrap().check()
+}
+
+#[test]
+fn test_check() {
+	use super::{coord2tile, LatLon};
+	let cafe = coord2tile(10, LatLon::from_degrees(51.5, -0.1));
+	let report = check_corrupted(|_, _, _| ());
+	assert!(report.problems.is_empty(), "{:?}", report.problems);
+	assert_eq!(report.intervals, 1);
+	assert!(report.tiles_checked > 1);
+	// A tile offset pointing past the end of the file
+	let report = check_corrupted(|data, entry, _| data[entry .. entry + 5].copy_from_slice(&[0x00, 0xff, 0xff, 0xff, 0xff]));
+	assert!(report.problems.iter().any(|problem| matches!(problem, Problem::OutsideSubfile { index, offset, .. } if *index == cafe && *offset > 0xffffffff)), "{:?}", report.problems);
+	// A tile flagged as water whose features have been taken out without it being stored empty
+	let report = check_corrupted(|data, entry, offset| {
+		data[entry] |= (super::WATER_TILE_FLAG >> 32) as u8;
+		for count in &mut data[offset as usize .. offset as usize + 8] { *count = 0; }
+	});
+	assert!(matches!(report.problems[..], [Problem::EmptyWithData { index, water: true, .. }] if index == cafe), "{:?}", report.problems);
+}
@@ -1,17 +1,59 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use memmap::Mmap;
+use nom::error::ErrorKind;
+use rayon::prelude::*;
 
+#[cfg(test)]
+pub mod builder;
+pub mod check;
 mod parse;
 
 pub const LON_MAX: f64 = 179.9999;
 pub const LAT_MAX: f64 = 85.0511;
 pub const COORD_MAX: i64 = 1 << 32;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+const WATER_TILE_FLAG: u64 = 0x8000000000;
+const MAGIC: &[u8] = b"mapsforge binary OSM";
+
+#[derive(Debug)]
+pub enum MapError {
+	Open(std::io::Error),
+	NotAMap,
+	NoZoomInterval(u8),
+	NotBaseZoom(u8),
+	TileIndexOutOfRange(u32),
+	OffsetOutOfRange(u64),
+	UnknownTag { offset: u64 },
+	Parse { offset: u64, kind: ErrorKind },
+	Read { offset: u64, error: std::io::Error },
+}
+
+impl std::fmt::Display for MapError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			MapError::Open(error) => write!(f, "couldn't open: {}", error),
+			MapError::NotAMap => write!(f, "not a mapsforge map (bad magic bytes)"),
+			MapError::NoZoomInterval(zoom) => write!(f, "no zoom interval covers zoom level {}", zoom),
+			MapError::NotBaseZoom(zoom) => write!(f, "zoom level {} isn't the base zoom of its interval", zoom),
+			MapError::TileIndexOutOfRange(idx) => write!(f, "tile index {} is outside the tile index", idx),
+			MapError::OffsetOutOfRange(offset) => write!(f, "offset {:#x} is outside the file", offset),
+			MapError::UnknownTag { offset } => write!(f, "tag ID out of range at offset {:#x}", offset),
+			MapError::Parse { offset, kind } => write!(f, "parse error at offset {:#x} ({:?})", offset, kind),
+			MapError::Read { offset, error } => write!(f, "read failed at offset {:#x}: {}", offset, error),
+		}
+	}
+}
+
+impl std::error::Error for MapError { }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "disk-cache", derive(serde::Serialize, serde::Deserialize))]
 pub struct Coord {
 	pub x: i64,
 	pub y: i64
@@ -21,6 +63,19 @@ impl Coord {
 	pub fn add(&self, other: &Self) -> Self {
 		Self { x: self.x + other.x, y: self.y + other.y }
 	}
+
+	// The same point on the copy of the map that starts at x = 0, for points on the copies to
+	// either side of it that a wrapped view shows
+	pub fn wrapped(&self) -> Self {
+		Self { x: self.x.rem_euclid(COORD_MAX), y: self.y }
+	}
+
+	// The inverse of LatLon::to_coord
+	pub fn to_latlon(&self) -> LatLon {
+		let lon = self.x as f64 * 360.0 / COORD_MAX as f64 - 180.0;
+		let lat = (std::f64::consts::PI * (1.0 - 2.0 * self.y as f64 / COORD_MAX as f64)).sinh().atan().to_degrees();
+		LatLon::from_degrees(lat, lon)
+	}
 }
 
 impl std::convert::From<(i64, i64)> for Coord {
@@ -41,6 +96,14 @@ impl LatLon {
 		Self { lat: lat, lon: lon }
 	}
 
+	pub fn from_degrees(lat: f64, lon: f64) -> Self {
+		Self::new((lat * 1e6).round() as i32, (lon * 1e6).round() as i32)
+	}
+
+	pub fn degrees(&self) -> (f64, f64) {
+		(self.lat as f64 / 1e6, self.lon as f64 / 1e6)
+	}
+
 	fn constrain(&self) -> Self {
 		Self {
 			lat: self.lat.clamp((-LAT_MAX * 1e6) as i32, (LAT_MAX * 1e6) as i32),
@@ -56,11 +119,19 @@ impl LatLon {
 		let lat_rad = (self.lat as f64 / 1000000.0).clamp(-LAT_MAX, LAT_MAX).to_radians();
 		Coord {
 			x: (self.lon + 180000000) as i64 * COORD_MAX / 360000000,
-			y: ((1.0 - (lat_rad.tan() + 1.0 / lat_rad.cos()).ln() / 3.141593) / 2.0 * COORD_MAX as f64) as i64,
+			y: ((1.0 - (lat_rad.tan() + 1.0 / lat_rad.cos()).ln() / std::f64::consts::PI) / 2.0 * COORD_MAX as f64) as i64,
 		}
 	}
 }
 
+// Formatted as "lat,lon" in decimal degrees, the same as polygon files
+impl std::fmt::Display for LatLon {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let (lat, lon) = self.degrees();
+		write!(f, "{:.6},{:.6}", lat, lon)
+	}
+}
+
 #[derive(Debug)]
 struct LatLonBounds {
 	// All fields in microdegrees
@@ -117,6 +188,7 @@ impl TagDesc {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "disk-cache", derive(serde::Serialize, serde::Deserialize))]
 pub enum TagValue {
 	Literal(String),
 	Byte(i8),
@@ -126,6 +198,31 @@ pub enum TagValue {
 	String(String),
 }
 
+impl TagValue {
+	// Plenty of numeric tags are stored as plain strings, so those are parsed too
+	pub fn number(&self) -> Option<f64> {
+		match self {
+			TagValue::Byte(x) => Some(*x as f64),
+			TagValue::Short(x) => Some(*x as f64),
+			TagValue::Int(x) => Some(*x as f64),
+			TagValue::Float(x) => Some(*x as f64),
+			TagValue::Literal(x) | TagValue::String(x) => x.trim().parse::<f64>().ok().filter(|x| !x.is_nan()),
+		}
+	}
+}
+
+impl std::fmt::Display for TagValue {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			TagValue::Literal(x) | TagValue::String(x) => write!(f, "{}", x),
+			TagValue::Byte(x) => write!(f, "{}", x),
+			TagValue::Short(x) => write!(f, "{}", x),
+			TagValue::Int(x) => write!(f, "{}", x),
+			TagValue::Float(x) => write!(f, "{}", x),
+		}
+	}
+}
+
 pub fn tile_origin(level: u8, xtile: u32, ytile: u32) -> LatLon {
 	use std::f64::consts::PI;
 	let n = (2 as i32).pow(level as u32) as f64;
@@ -150,19 +247,45 @@ fn biased_coord2tile(level: u8, coord: LatLon, bias_low: bool) -> (u32, u32) {
 	(xtile.min(maxtile), ytile.min(maxtile))
 }
 
-fn coord2tile(level: u8, coord: LatLon) -> (u32, u32) {
+pub fn coord2tile(level: u8, coord: LatLon) -> (u32, u32) {
 	biased_coord2tile(level, coord, false) // Not biasing low is more efficient when it doesn't matter
 }
 
+// The bit for the sub-tile in column dx and row dy in a way's sub-tile bitmap
+pub fn subtile_bit(dx: u8, dy: u8) -> u16 {
+	0x8000 >> (dy * 4 + dx)
+}
+
+// The bits of a way's sub-tile bitmap that fall inside a tile shift zoom levels below the base
+// tile containing it.  One level down is a quarter of the bitmap, and two or more is a single bit.
+fn subtile_mask(shift: u8, x: u32, y: u32) -> u16 {
+	match shift {
+		0 => 0xffff,
+		1 => 0xcc00 >> ((y & 1) * 8 + (x & 1) * 2),
+		_ => subtile_bit(((x >> (shift - 2)) & 3) as u8, ((y >> (shift - 2)) & 3) as u8),
+	}
+}
+
+// Features are stored in order of the zoom they first appear at, so the ones that show at a zoom
+// are the first so many of each kind
+fn visible_counts(zoom_table: &[(u64, u64)], rows: usize) -> (usize, usize) {
+	let rows = &zoom_table[..rows.min(zoom_table.len())];
+	(rows.iter().map(|row| row.0 as usize).sum(), rows.iter().map(|row| row.1 as usize).sum())
+}
+
 fn tileidx(level: u8, idx: u32) -> (u32, u32) {
 	let n = (2 as u32).pow(level as u32);
 	(idx % n, idx / n)
 }
 
-fn num_tiles(level: u8, bounds: &LatLonBounds) -> (u32, u32) {
+// The top left and bottom right tiles, inclusive, covered by the given bounding box
+fn tile_range(level: u8, bounds: &LatLonBounds) -> ((u32, u32), (u32, u32)) {
 	let (min_coord, max_coord) = bounds.minmax();
-	let min = biased_coord2tile(level, min_coord, false);
-	let max = biased_coord2tile(level, max_coord, true);
+	(biased_coord2tile(level, min_coord, false), biased_coord2tile(level, max_coord, true))
+}
+
+fn num_tiles(level: u8, bounds: &LatLonBounds) -> (u32, u32) {
+	let (min, max) = tile_range(level, bounds);
 	(max.0 - min.0 + 1, max.1 - min.1 + 1)
 }
 
@@ -170,9 +293,7 @@ fn num_tiles(level: u8, bounds: &LatLonBounds) -> (u32, u32) {
 // tile would get if all tiles covered by the given bounding box were counted off from zero in
 // reading order
 fn tile_idx_in_box(level: u8, bounds: &LatLonBounds, xtile: u32, ytile: u32) -> Option<u32> {
-	let (min_coord, max_coord) = bounds.minmax();
-	let min = biased_coord2tile(level, min_coord, false);
-	let max = biased_coord2tile(level, max_coord, true);
+	let (min, max) = tile_range(level, bounds);
 	if xtile < min.0 || xtile > max.0 || ytile < min.1 || ytile > max.1 { None }
 	else {
 		let rowlen = max.0 - min.0 + 1;
@@ -181,25 +302,55 @@ fn tile_idx_in_box(level: u8, bounds: &LatLonBounds, xtile: u32, ytile: u32) ->
 }
 
 #[derive(Debug)]
-pub struct TileIndex {
+struct TileIndex {
 	tile_offsets: Vec<u64>,
 }
 
+// Shared, so that everything built from a feature can keep its tags without copying them
+pub type Tags = Arc<HashMap<String, TagValue>>;
+
+// Names by language, with the map's default name under None
+pub type Names = HashMap<Option<String>, String>;
+
+// How many POIs and ways first appear at each zoom in a tile's interval
+type ZoomTable = Vec<(u64, u64)>;
+
+// A parsed tile, its zoom table, and the offset just past its data
+type ReadTile = (Tile, ZoomTable, u64);
+
+// The name in the language if there is one, falling back from a regional variant like en-GB to the
+// plain language and then to the default name
+fn pick_name<'a>(names: &'a Names, lang: Option<&str>) -> Option<&'a str> {
+	let base = lang.map(|lang| lang.split(['-', '_']).next().unwrap());
+	let find = |want: &str| names.iter().find(|(key, _)| key.as_ref().is_some_and(|key| key.eq_ignore_ascii_case(want))).map(|(_, name)| name.as_str());
+	lang.and_then(find).or_else(|| base.and_then(find)).or_else(|| names.get(&None).map(String::as_str))
+}
+
 #[derive(Debug)]
 pub struct Poi {
 	offset: LatLon,
 	pub layer: i8,
-	pub tags: HashMap<String, TagValue>,
-	pub name: Option<String>,
+	pub tags: Tags,
+	pub names: Names,
 	pub house_number: Option<String>,
 	pub elevation: Option<i64>,
 }
 
 impl Poi {
 	pub fn project(&self, tile: &Tile) -> Coord {
-		// TODO We always translate all POIs in a tile, so optimize by making a single call to project() with all POIs together.
 		tile.project(&[self.offset])[0]
 	}
+
+	pub fn name(&self, lang: Option<&str>) -> Option<&str> {
+		pick_name(&self.names, lang)
+	}
+
+	#[cfg(test)]
+	pub fn with_tags(tags: &[(&str, TagValue)], name: Option<&str>, house_number: Option<&str>, elevation: Option<i64>) -> Self {
+		let tags = Arc::new(tags.iter().map(|(key, value)| (key.to_string(), value.clone())).collect());
+		let names = name.map(|name| (None, name.to_string())).into_iter().collect();
+		Self { offset: LatLon { lat: 0, lon: 0 }, layer: 0, tags, names, house_number: house_number.map(str::to_string), elevation }
+	}
 }
 
 #[derive(Debug)]
@@ -207,8 +358,8 @@ pub struct Way {
 	size: u64,
 	subtile_map: u16,
 	pub layer: i8,
-	pub tags: HashMap<String, TagValue>,
-	pub name: Option<String>,
+	pub tags: Tags,
+	pub names: Names,
 	pub house_number: Option<String>,
 	pub reference: Option<String>,
 	pub label_pos: Option<LatLon>,
@@ -216,12 +367,47 @@ pub struct Way {
 }
 
 impl Way {
+	// Which of the 16 tiles two zoom levels below the base tile the way appears in, one bit per
+	// tile, row by row from the top left in the highest bit
+	pub fn subtile_map(&self) -> u16 {
+		self.subtile_map
+	}
+
+	pub fn subtile_visible(&self, dx: u8, dy: u8) -> bool {
+		self.subtile_map & subtile_bit(dx, dy) != 0
+	}
+
+	pub fn name(&self, lang: Option<&str>) -> Option<&str> {
+		pick_name(&self.names, lang)
+	}
+
+	fn rebase(mut self, by: &LatLon) -> Self {
+		for point in self.blocks.iter_mut().flatten().flatten() { *point = point.add(by); }
+		self
+	}
+
+	// Whether the way outlines an area rather than running along a line: tagged area=yes, or with
+	// every block's outline ending where it starts and not tagged area=no
+	pub fn is_area(&self) -> bool {
+		match self.tags.get("area") {
+			Some(TagValue::Literal(value)) if value == "yes" => true,
+			Some(TagValue::Literal(value)) if value == "no" => false,
+			_ => !self.blocks.is_empty() && self.blocks.iter().all(|block| block.first().is_some_and(|ring| ring.len() > 2 && ring.first() == ring.last())),
+		}
+	}
+
+	// Where the map says to put the way's label, which is stored relative to its first point
+	pub fn project_label(&self, tile: &Tile) -> Option<Coord> {
+		let first = self.blocks.first()?.first()?.first()?;
+		self.label_pos.map(|pos| tile.project(&[first.add(&pos)])[0])
+	}
+
 	pub fn project(&self, tile: &Tile) -> Vec<Vec<Vec<Coord>>> {
 		let mut ret = vec![];
 		for block in self.blocks.as_slice() {
 			let mut blockdata = vec![];
 			for path in block.as_slice() {
-				blockdata.push(tile.project(&path));
+				blockdata.push(tile.project(path));
 			}
 			ret.push(blockdata);
 		}
@@ -230,8 +416,8 @@ impl Way {
 }
 
 #[derive(Debug)]
-pub struct TileHeader {
-	zoom_table: Vec<(u64, u64)>,
+struct TileHeader {
+	zoom_table: ZoomTable,
 	poi_start: u64,
 	way_start: u64,
 }
@@ -240,23 +426,37 @@ pub struct TileHeader {
 pub struct Tile {
 	pub zoom: u8,
 	pub index: (u32, u32),
+	origin: LatLon, // What feature positions are relative to, which is the base tile's corner above the base zoom.  Worked out once when the tile is read.
 	pub ways: Vec<Way>,
 	pub pois: Vec<Poi>,
+	pub water: bool, // Flagged by the map as all water, which is how open sea is stored
 }
 
 impl Tile {
-	fn empty(zoom: u8, xtile: u32, ytile: u32) -> Self {
-		Self { zoom, index: (xtile, ytile), ways: vec![], pois: vec![] }
+	pub fn empty(zoom: u8, xtile: u32, ytile: u32) -> Self {
+		Self { zoom, index: (xtile, ytile), origin: tile_origin(zoom, xtile, ytile), ways: vec![], pois: vec![], water: false }
 	}
 
 	// For a given tile, translate a list of lat/lon offsets from the tile origin to absolute
 	// coordinates relative to the top left of the map that treats the map as a square of side
 	// length 2 ** 32 - 1.
 	fn project(&self, offsets: &[LatLon]) -> Vec<Coord> {
-		// TODO Do actual trig rather than stretching latitude
-		// TODO Cache origin rather than recalculating it every time
-		let origin = tile_origin(self.zoom, self.index.0, self.index.1);
-		offsets.iter().map(|offset| origin.add(offset).to_coord()).collect()
+		// Each point goes through the whole projection, since latitude isn't linear even within a tile
+		offsets.iter().map(|offset| self.origin.add(offset).to_coord()).collect()
+	}
+
+	// Every POI's position in one go, in the same order as pois
+	pub fn project_pois(&self) -> Vec<Coord> {
+		self.project(&self.pois.iter().map(|poi| poi.offset).collect::<Vec<_>>())
+	}
+
+	// Every way's blocks in one go, in the same order as ways
+	pub fn project_ways(&self) -> Vec<Vec<Vec<Vec<Coord>>>> {
+		let offsets = self.ways.iter().flat_map(|way| way.blocks.iter().flatten().flatten().copied()).collect::<Vec<_>>();
+		let mut points = self.project(&offsets).into_iter();
+		self.ways.iter().map(|way| {
+			way.blocks.iter().map(|block| block.iter().map(|path| points.by_ref().take(path.len()).collect()).collect()).collect()
+		}).collect()
 	}
 }
 
@@ -279,30 +479,241 @@ pub struct MapHeader {
 	zoom_intervals: Vec<ZoomInterval>,
 }
 
+impl MapHeader {
+	// Where the map suggests the view should start, if it does
+	pub fn start_pos(&self) -> Option<LatLon> {
+		self.start_pos
+	}
+
+	pub fn start_zoom(&self) -> Option<u8> {
+		self.start_zoom
+	}
+
+	// The language of the map's default names, if it says
+	pub fn pref_lang(&self) -> Option<&str> {
+		self.pref_lang.as_deref()
+	}
+
+	// Version of the mapsforge format the map is in
+	pub fn version(&self) -> u32 {
+		self.version
+	}
+
+	// When the map was made, as the map tells it
+	pub fn created(&self) -> SystemTime {
+		UNIX_EPOCH + Duration::from_millis(self.created)
+	}
+
+	pub fn comment(&self) -> Option<&str> {
+		self.comment.as_deref()
+	}
+
+	// The program that made the map
+	pub fn creator(&self) -> Option<&str> {
+		self.creator.as_deref()
+	}
+
+	// Name of the projection the map's coordinates are in, which in practice is always Mercator
+	pub fn projection(&self) -> &str {
+		&self.projection
+	}
+
+	// Top left and bottom right corners of the area the map covers
+	pub fn bounds_latlon(&self) -> (LatLon, LatLon) {
+		self.bounds.minmax()
+	}
+}
+
+// The basics of a map as described in its header
+#[derive(Debug, Clone)]
+pub struct MapSummary {
+	pub bounds: (LatLon, LatLon), // Top left and bottom right corners
+	pub zoom_intervals: Vec<ZoomSummary>,
+	pub tile_size: u16,
+	pub creator: Option<String>,
+	pub created: u64, // Milliseconds since the Unix epoch
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoomSummary {
+	pub base: u8, // The zoom level that tiles in this interval are stored at
+	pub min: u8,
+	pub max: u8,
+	pub tiles: u64, // Number of tiles covering the map's bounds at the base zoom
+}
+
+// Where a map's bytes come from.  Mapping the whole file is fastest, but huge maps may not fit in
+// the address space, in which case pieces are read from disk as they're needed instead.  Those
+// reads say where they start rather than seeking, so render threads can read at the same time.
+enum MapData {
+	Mapped(Mmap),
+	Buffered { file: File, len: u64 },
+	Memory(Vec<u8>),
+}
+
+impl MapData {
+	fn len(&self) -> u64 {
+		match self {
+			MapData::Mapped(map) => map.len() as u64,
+			MapData::Buffered { len, .. } => *len,
+			MapData::Memory(data) => data.len() as u64,
+		}
+	}
+
+	// The bytes from start up to end, or up to the end of the file if that comes first
+	fn read(&self, start: u64, end: u64) -> Result<Cow<'_, [u8]>, MapError> {
+		let end = end.min(self.len());
+		if start > end { return Err(MapError::OffsetOutOfRange(start)); }
+		match self {
+			MapData::Mapped(map) => Ok(Cow::Borrowed(&map[start as usize .. end as usize])),
+			MapData::Memory(data) => Ok(Cow::Borrowed(&data[start as usize .. end as usize])),
+			MapData::Buffered { file, .. } => {
+				let mut buf = vec![0; (end - start) as usize];
+				read_exact_at(file, &mut buf, start).map_err(|error| MapError::Read { offset: start, error })?;
+				Ok(Cow::Owned(buf))
+			},
+		}
+	}
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+	std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+	// seek_read moves the file position too, but nothing else relies on it
+	while !buf.is_empty() {
+		match std::os::windows::fs::FileExt::seek_read(file, buf, offset) {
+			Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+			Ok(n) => { buf = &mut buf[n..]; offset += n as u64; },
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+			Err(e) => return Err(e),
+		}
+	}
+	Ok(())
+}
+
 pub struct MapFile {
 	path: PathBuf,
-	data: Arc<Mmap>,
+	data: MapData,
 	header: MapHeader,
 	zoom_interval_map: HashMap<u8, u8>,
 	indices: Vec<TileIndex>,
 }
 
 impl MapFile {
-	pub fn new(path: PathBuf) -> Self {
-		let data = unsafe { Mmap::map(&File::open(&path).unwrap()).unwrap() };
-		let header = parse::header(&*data).unwrap().1;
+	pub fn new(path: PathBuf) -> Result<Self, MapError> {
+		let file = File::open(&path).map_err(MapError::Open)?;
+		let data = unsafe { Mmap::map(&file) }.map_err(MapError::Open)?;
+		Self::from_data(path, MapData::Mapped(data))
+	}
+
+	// Open the map without mapping it into memory, reading tiles from disk on demand instead
+	pub fn new_buffered(path: PathBuf) -> Result<Self, MapError> {
+		let file = File::open(&path).map_err(MapError::Open)?;
+		let len = file.metadata().map_err(MapError::Open)?.len();
+		Self::from_data(path, MapData::Buffered { file, len })
+	}
+
+	// A map that's already in memory.  The path is only used to refer to it.
+	pub fn from_bytes(path: PathBuf, data: Vec<u8>) -> Result<Self, MapError> {
+		Self::from_data(path, MapData::Memory(data))
+	}
+
+	// Read just the header of a map, skipping the tile indices that opening it properly would need
+	pub fn summary(path: &Path) -> Result<MapSummary, MapError> {
+		let file = File::open(path).map_err(MapError::Open)?;
+		let len = file.metadata().map_err(MapError::Open)?.len();
+		let header = Self::read_header(&MapData::Buffered { file, len })?;
+		Ok(MapSummary {
+			bounds: header.bounds.minmax(),
+			zoom_intervals: header.zoom_intervals.iter().map(|zoom| {
+				let n = num_tiles(zoom.base, &header.bounds);
+				ZoomSummary { base: zoom.base, min: zoom.min, max: zoom.max, tiles: n.0 as u64 * n.1 as u64 }
+			}).collect(),
+			tile_size: header.tile_size,
+			creator: header.creator,
+			created: header.created,
+		})
+	}
+
+	fn read_header(data: &MapData) -> Result<MapHeader, MapError> {
+		// The header's length follows the 20-byte magic string
+		let prefix = data.read(0, 24)?;
+		if !prefix.starts_with(MAGIC) { return Err(MapError::NotAMap); }
+		let header_len = match prefix.get(20..24) {
+			Some(&[a, b, c, d]) => 24 + u32::from_be_bytes([a, b, c, d]) as u64,
+			_ => prefix.len() as u64, // Too short to be a map, so let the parser complain
+		};
+		let buf = data.read(0, header_len)?;
+		let header = parse::header(&buf).map_err(|e| Self::parse_error(0, &buf, e))?.1;
+		Ok(header)
+	}
+
+	fn from_data(path: PathBuf, data: MapData) -> Result<Self, MapError> {
+		let header = Self::read_header(&data)?;
 		let mut zoom_map = HashMap::new();
 		for (idx, zoom) in header.zoom_intervals.iter().enumerate() {
 			for level in zoom.min..=zoom.max {
 				zoom_map.insert(level, idx as u8);
 			}
 		}
-		let indices = header.zoom_intervals.iter().map(|subfile| {
+		// The indices are independent and can be big for large maps, so read them all at once
+		let indices = header.zoom_intervals.par_iter().map(|subfile| {
 			let n = num_tiles(subfile.base, &header.bounds);
-			let i = &data[subfile.start as usize ..];
-			parse::tile_index((n.0 * n.1) as usize, header.debug, subfile.start, i).unwrap().1
-		}).collect();
-		Self { path, data: Arc::new(data), header: header, zoom_interval_map: zoom_map, indices }
+			let len = if header.debug { 16 } else { 0 } + 5 * n.0 as u64 * n.1 as u64;
+			let i = data.read(subfile.start, subfile.start + len)?;
+			Ok(parse::tile_index((n.0 * n.1) as usize, header.debug, subfile.start, &i).map_err(|e| Self::parse_error(subfile.start, &i, e))?.1)
+		}).collect::<Result<_, MapError>>()?;
+		Ok(Self { path, data, header: header, zoom_interval_map: zoom_map, indices })
+	}
+
+	// Ask the OS to start reading the whole map into memory now, so that tiles aren't held up by
+	// page faults the first time they're read.  The tile indices are read when the map is opened
+	// anyway, so this is all about tile data.  Does nothing for maps that aren't mapped.
+	pub fn prefault(&self) -> std::io::Result<()> {
+		self.advise(0, self.data.len())
+	}
+
+	// Like prefault, but only for some tiles at a base zoom, such as the ones about to be drawn.
+	// Their data is read in as few pieces as it can be, since neighbors in a row are stored one
+	// after another.
+	pub fn prefault_tiles(&self, zoom: u8, tiles: &[(u32, u32)]) -> std::io::Result<()> {
+		let subfile_num = match self.zoom_interval_map.get(&zoom) {
+			Some(&num) if self.header.zoom_intervals[num as usize].base == zoom => num,
+			_ => return Ok(()),
+		};
+		let mut spans = tiles.iter().filter_map(|&(x, y)| tile_idx_in_box(zoom, &self.header.bounds, x, y))
+			.filter_map(|tile_idx| self.tile_span(subfile_num, tile_idx).ok())
+			.filter(|(start, end, _)| start < end)
+			.map(|(start, end, _)| (start, end))
+			.collect::<Vec<_>>();
+		spans.sort_unstable();
+		let mut merged: Vec<(u64, u64)> = vec![];
+		for (start, end) in spans {
+			match merged.last_mut() {
+				Some(last) if start <= last.1 => last.1 = last.1.max(end),
+				_ => merged.push((start, end)),
+			}
+		}
+		merged.into_iter().try_for_each(|(start, end)| self.advise(start, end))
+	}
+
+	// Tell the OS that the bytes from start to end will be needed soon
+	fn advise(&self, start: u64, end: u64) -> std::io::Result<()> {
+		match &self.data {
+			#[cfg(unix)]
+			MapData::Mapped(map) if start < end && end <= map.len() as u64 => {
+				// The range has to start on a page boundary
+				let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+				let start = start / page * page;
+				let ret = unsafe { libc::madvise(map.as_ptr().add(start as usize) as *mut libc::c_void, (end - start) as usize, libc::MADV_WILLNEED) };
+				if ret == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+			},
+			_ => Ok(()),
+		}
 	}
 
 	pub fn path<'a>(&'a self) -> &'a Path {
@@ -328,45 +739,153 @@ impl MapFile {
 		else { None }
 	}
 
-	pub fn tile(&self, zoom: u8, x: u32, y: u32) -> Tile {
-		let subfile_num = self.zoom_interval_map.get(&zoom).unwrap().clone();
+	// Convert an error from parsing buf, which was read from the given offset in the file
+	fn parse_error(base: u64, buf: &[u8], err: nom::Err<nom::error::Error<&[u8]>>) -> MapError {
+		match err {
+			nom::Err::Error(e) | nom::Err::Failure(e) => {
+				let offset = base + (e.input.as_ptr() as usize - buf.as_ptr() as usize) as u64;
+				// The parser only uses Verify to reject tag IDs that aren't in the header's tag table
+				if e.code == ErrorKind::Verify { MapError::UnknownTag { offset } }
+				else { MapError::Parse { offset, kind: e.code } }
+			},
+			nom::Err::Incomplete(_) => MapError::Parse { offset: base + buf.len() as u64, kind: ErrorKind::Eof },
+		}
+	}
+
+	// The byte range occupied by a tile in its subfile, and whether the tile is flagged as water.
+	// A tile with no data has the same offset as the tile after it.
+	fn tile_span(&self, subfile_num: u8, tile_idx: u32) -> Result<(u64, u64, bool), MapError> {
 		let zoom_interval = &self.header.zoom_intervals[subfile_num as usize];
-		if zoom_interval.base != zoom { unimplemented!("Cannot retrieve tiles for non-base zoom levels"); } // TODO
+		let offsets = &self.indices[subfile_num as usize].tile_offsets;
+		let raw_offset = *offsets.get(tile_idx as usize).ok_or(MapError::TileIndexOutOfRange(tile_idx))?;
+		let end = match offsets.get(tile_idx as usize + 1) {
+			Some(next) => next & !WATER_TILE_FLAG,
+			None => zoom_interval.start + zoom_interval.len,
+		};
+		Ok((raw_offset & !WATER_TILE_FLAG, end, raw_offset & WATER_TILE_FLAG != 0))
+	}
+
+	// Whether the tile is inside the map's bounds at this base zoom, even if there's nothing in it
+	pub fn covers(&self, zoom: u8, x: u32, y: u32) -> bool {
+		tile_idx_in_box(zoom, &self.header.bounds, x, y).is_some()
+	}
+
+	// Everything in a tile at the base zoom of the given subfile, along with how many POIs and ways
+	// first appear at each zoom in the interval
+	fn base_tile(&self, subfile_num: u8, x: u32, y: u32) -> Result<(Tile, ZoomTable), MapError> {
+		let zoom_interval = &self.header.zoom_intervals[subfile_num as usize];
+		let zoom = zoom_interval.base;
 		match tile_idx_in_box(zoom, &self.header.bounds, x, y) {
-			None => Tile::empty(zoom, x, y),
+			None => Ok((Tile::empty(zoom, x, y), vec![])),
 			Some(tile_idx) => {
-				let tile_offset = self.indices.get(subfile_num as usize).unwrap().tile_offsets[tile_idx as usize];
-				if tile_offset & 0x8000000000 != 0 { Tile::empty(zoom, x, y) }
+				// Water tiles usually have nothing in them, but any features they do have are
+				// read as usual
+				let (tile_offset, tile_end, water) = self.tile_span(subfile_num, tile_idx)?;
+				if tile_end <= tile_offset { Ok((Tile { water, ..Tile::empty(zoom, x, y) }, vec![])) }
 				else {
-					let i = &self.data[tile_offset as usize ..];
-					let (mut i, tile_header) = parse::tile_header(self.header.debug, zoom_interval.max - zoom_interval.min + 1, tile_offset, i).unwrap();
-					let num_poi = tile_header.zoom_table.iter().map(|x| x.0).sum();
-					let num_way: u64 = tile_header.zoom_table.iter().map(|x| x.1).sum();
-					//let tile_origin = tile_origin(zoom_interval.base, x, y);
-					let mut pois = vec![];
-					for _ in  0 .. num_poi {
-						let (newi, poi) = parse::poi(self.header.debug, &self.header.poi_tags, i).unwrap();
-						i = newi;
-						pois.push(poi);
-					}
-					let mut ways = vec![];
-					for _ in  0 .. num_way {
-						let (newi, way) = parse::way(self.header.debug, &self.header.way_tags, i).unwrap();
-						i = newi;
-						ways.push(way);
-					}
-					Tile { zoom, index: (x, y), ways, pois }
+					let (tile, zoom_table, _) = self.read_tile(zoom_interval, x, y, tile_offset, tile_end)?;
+					Ok((Tile { water, ..tile }, zoom_table))
 				}
 			}
 		}
 	}
 
+	// A tile at the base zoom of its interval with every feature stored in it, including those
+	// that only show at higher zooms.  The viewer draws these at all of the interval's zooms.
+	pub fn stored_tile(&self, zoom: u8, x: u32, y: u32) -> Result<Tile, MapError> {
+		let subfile_num = *self.zoom_interval_map.get(&zoom).ok_or(MapError::NoZoomInterval(zoom))?;
+		if self.header.zoom_intervals[subfile_num as usize].base != zoom { return Err(MapError::NotBaseZoom(zoom)); }
+		Ok(self.base_tile(subfile_num, x, y)?.0)
+	}
+
+	// The features that show at this zoom according to the zoom table.  Above the base zoom,
+	// that's the part of the base tile the sub-tile bitmaps put in this tile; below it, the base
+	// tiles inside this one are combined.
+	pub fn tile(&self, zoom: u8, x: u32, y: u32) -> Result<Tile, MapError> {
+		let subfile_num = *self.zoom_interval_map.get(&zoom).ok_or(MapError::NoZoomInterval(zoom))?;
+		let zoom_interval = &self.header.zoom_intervals[subfile_num as usize];
+		let rows = (zoom - zoom_interval.min) as usize + 1;
+		if zoom >= zoom_interval.base {
+			let shift = zoom - zoom_interval.base;
+			let (tile, zoom_table) = self.base_tile(subfile_num, x >> shift, y >> shift)?;
+			let (npoi, nway) = visible_counts(&zoom_table, rows);
+			let mask = subtile_mask(shift, x, y);
+			let origin = tile.origin;
+			// POIs don't have sub-tile bitmaps, so they go by where they are
+			let pois = tile.pois.into_iter().take(npoi).filter(|poi| shift == 0 || coord2tile(zoom, origin.add(&poi.offset)) == (x, y)).collect();
+			let ways = tile.ways.into_iter().take(nway).filter(|way| way.subtile_map & mask != 0).collect();
+			Ok(Tile { zoom, index: (x, y), origin, ways, pois, water: tile.water })
+		}
+		else {
+			// Each base tile's features are moved to be relative to this tile's corner.  Their
+			// sub-tile bitmaps are still for the base tiles they came from.  The combined tile only
+			// counts as water if every base tile in it does.
+			let shift = zoom_interval.base - zoom;
+			let mut ret = Tile::empty(zoom, x, y);
+			let mut water = None;
+			let (min, max) = tile_range(zoom_interval.base, &self.header.bounds);
+			for by in (y << shift).max(min.1) ..= (((y + 1) << shift) - 1).min(max.1) {
+				for bx in (x << shift).max(min.0) ..= (((x + 1) << shift) - 1).min(max.0) {
+					let (tile, zoom_table) = self.base_tile(subfile_num, bx, by)?;
+					water = Some(water.unwrap_or(true) && tile.water);
+					let (npoi, nway) = visible_counts(&zoom_table, rows);
+					let offset = LatLon::new(tile.origin.lat - ret.origin.lat, tile.origin.lon - ret.origin.lon);
+					ret.pois.extend(tile.pois.into_iter().take(npoi).map(|poi| Poi { offset: poi.offset.add(&offset), ..poi }));
+					ret.ways.extend(tile.ways.into_iter().take(nway).map(|way| way.rebase(&offset)));
+				}
+			}
+			ret.water = water.unwrap_or(false);
+			Ok(ret)
+		}
+	}
+
+	// Parse the tile at the given file offset, without reading past tile_end, returning it along
+	// with its zoom table and the offset of the first byte after its data
+	fn read_tile(&self, zoom_interval: &ZoomInterval, x: u32, y: u32, tile_offset: u64, tile_end: u64) -> Result<ReadTile, MapError> {
+		let buf = self.data.read(tile_offset, tile_end)?;
+		let parse_error = |e| Self::parse_error(tile_offset, &buf, e);
+		let mut i = &*buf;
+		let (newi, tile_header) = parse::tile_header(self.header.debug, zoom_interval.max - zoom_interval.min + 1, tile_offset, i).map_err(parse_error)?;
+		i = newi;
+		let num_poi = tile_header.zoom_table.iter().map(|x| x.0).sum();
+		let num_way: u64 = tile_header.zoom_table.iter().map(|x| x.1).sum();
+		let mut pois = vec![];
+		for _ in  0 .. num_poi {
+			let (newi, poi) = parse::poi(self.header.debug, &self.header.poi_tags, i).map_err(parse_error)?;
+			i = newi;
+			pois.push(poi);
+		}
+		let mut ways = vec![];
+		for _ in  0 .. num_way {
+			let (newi, way) = parse::way(self.header.debug, &self.header.way_tags, i).map_err(parse_error)?;
+			i = newi;
+			ways.push(way);
+		}
+		let end = tile_offset + (i.as_ptr() as usize - buf.as_ptr() as usize) as u64;
+		Ok((Tile { zoom: zoom_interval.base, index: (x, y), origin: tile_origin(zoom_interval.base, x, y), ways, pois, water: false }, tile_header.zoom_table, end))
+	}
+
 	pub fn test(&self) {
 		for (name, desc) in &self.header.way_tags { println!("way\t{}\t{:?}", name, desc); }
 		for (name, desc) in &self.header.poi_tags { println!("poi\t{}\t{:?}", name, desc); }
 	}
 }
 
+#[test]
+fn test_coord_round_trip() {
+	for (lat, lon) in &[(0.0, 0.0), (51.477928, -0.001545), (-33.856784, 151.215297), (84.9, 179.9), (-84.9, -179.9)] {
+		let latlon = LatLon::from_degrees(*lat, *lon);
+		let back = latlon.to_coord().to_latlon();
+		assert!((back.lat - latlon.lat).abs() <= 1 && (back.lon - latlon.lon).abs() <= 1, "{} became {}", latlon, back);
+		// A lap either way round the world comes back to the same place
+		for laps in [-1, 1] {
+			let coord = latlon.to_coord();
+			let back = Coord { x: coord.x + laps * COORD_MAX, y: coord.y }.wrapped().to_latlon();
+			assert!((back.lat - latlon.lat).abs() <= 1 && (back.lon - latlon.lon).abs() <= 1, "{} became {} after {} laps", latlon, back, laps);
+		}
+	}
+}
+
 #[test]
 fn test_coord2tile() {
 	let tests = vec![
@@ -421,3 +940,261 @@ fn test_tile_idx_in_box() {
 		assert_eq!(actual, expected, "Index of tile {:?} in bounds {:?} at zoom {} is {:?}, but expected {:?}", tile, bounds, level, actual, expected);
 	}
 }
+
+// Every tile in a map's bounds should get its own index below num_tiles, in reading order, so that
+// tile() never looks past the end of the tile index
+#[test]
+fn test_tile_idx_consistency() {
+	let bounds = vec![
+		(-90, -180, 90, 180),
+		(-50, -100, 80, 90),
+		(0, 0, 45, 90), // Edges on tile boundaries at low zooms
+		(51, -1, 52, 0),
+		(-34, 151, -33, 152),
+		(10, -10, 10, -10), // A single point
+	];
+	for bounds in bounds {
+		let bounds = LatLonBounds { lat_min: bounds.0 * 1000000, lon_min: bounds.1 * 1000000, lat_max: bounds.2 * 1000000, lon_max: bounds.3 * 1000000 };
+		for level in 0..=14 {
+			let n = num_tiles(level, &bounds);
+			if n.0 as u64 * n.1 as u64 > 1 << 16 { continue; } // Keep the big boxes' high zooms from taking forever
+			let (min, max) = tile_range(level, &bounds);
+			let last = 2_u32.pow(level as u32) - 1;
+			let mut expected = 0;
+			// Check a margin of one tile around the range too, to make sure nothing outside it gets an index
+			for y in min.1.saturating_sub(1)..=(max.1 + 1).min(last) {
+				for x in min.0.saturating_sub(1)..=(max.0 + 1).min(last) {
+					let inside = (min.0..=max.0).contains(&x) && (min.1..=max.1).contains(&y);
+					let idx = tile_idx_in_box(level, &bounds, x, y);
+					if !inside {
+						assert_eq!(idx, None, "Tile {:?} outside bounds {:?} at zoom {} has an index", (x, y), bounds, level);
+						continue;
+					}
+					assert_eq!(idx, Some(expected), "Tile {:?} in bounds {:?} at zoom {} is out of order", (x, y), bounds, level);
+					assert_eq!((expected % n.0 + min.0, expected / n.0 + min.1), (x, y));
+					expected += 1;
+				}
+			}
+			assert_eq!(expected, n.0 * n.1, "Bounds {:?} at zoom {} have {} tiles but num_tiles says {:?}", bounds, level, expected, n);
+		}
+	}
+}
+
+#[test]
+fn test_open_errors() {
+	let dir = std::env::temp_dir();
+	assert!(matches!(MapFile::new(dir.join("mapviewer-test-no-such-map.map")), Err(MapError::Open(_))));
+	let not_map = dir.join(format!("mapviewer-test-not-a-map-{}.map", std::process::id()));
+	std::fs::write(&not_map, b"<?xml version=\"1.0\"?><osm></osm>").unwrap();
+	assert!(matches!(MapFile::new(not_map.clone()), Err(MapError::NotAMap)));
+	assert!(matches!(MapFile::new_buffered(not_map.clone()), Err(MapError::NotAMap)));
+	// Right magic, but cut off partway through the header
+	std::fs::write(&not_map, b"mapsforge binary OSM\0\0\0\x40\0\0\0\x03").unwrap();
+	assert!(matches!(MapFile::new(not_map.clone()), Err(MapError::Parse { .. })));
+	std::fs::remove_file(&not_map).unwrap();
+}
+
+#[test]
+fn test_buffered() {
+	let bytes = builder::MapBuilder::new((0.1, 0.1), (1.3, 1.3))
+		.interval(8, 5, 9)
+		.poi(5, (0.3, 0.3), &[("name", "a")])
+		.poi(9, (1.0, 1.0), &[("name", "b")])
+		.way(5, &[(0.3, 0.3), (1.0, 1.0)], &[("name", "everywhere")])
+		.build();
+	let path = std::env::temp_dir().join(format!("mapviewer-test-buffered-{}.map", std::process::id()));
+	std::fs::write(&path, &bytes).unwrap();
+	let (mapped, buffered) = (MapFile::new(path.clone()).unwrap(), MapFile::new_buffered(path.clone()).unwrap());
+	// Read from several threads at once, which buffered maps have to cope with
+	let tiles = (0..4).flat_map(|x| (0..4).map(move |y| (9, 254 + x, 252 + y))).chain([(8, 128, 127), (6, 32, 31)]).collect::<Vec<_>>();
+	let read = |map: &MapFile| tiles.par_iter().map(|&(zoom, x, y)| format!("{:?}", map.tile(zoom, x, y))).collect::<Vec<_>>();
+	assert_eq!(read(&buffered), read(&mapped));
+	assert!(read(&mapped).iter().any(|tile| tile.contains("everywhere")));
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_prefault() {
+	let bytes = builder::MapBuilder::new((0.1, 0.1), (1.3, 1.3))
+		.interval(10, 10, 12)
+		.way(10, &[(0.3, 0.3), (1.0, 1.0)], &[("name", "everywhere")])
+		.build();
+	let path = std::env::temp_dir().join(format!("mapviewer-test-prefault-{}.map", std::process::id()));
+	std::fs::write(&path, &bytes).unwrap();
+	let map = MapFile::new(path.clone()).unwrap();
+	let row = (512..516).map(|x| (x, 510)).collect::<Vec<_>>();
+	assert!(map.prefault_tiles(10, &row).is_ok());
+	// Tiles off the map, and zooms it has no tiles stored at, are skipped
+	assert!(map.prefault_tiles(10, &[(0, 0), (513, 509)]).is_ok());
+	assert!(map.prefault_tiles(11, &row).is_ok());
+	assert!(map.prefault().is_ok());
+	// Reading goes on as usual
+	assert!(map.tile(10, 513, 510).unwrap().ways.iter().any(|way| way.name(None) == Some("everywhere")));
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_summary() {
+	let bytes = builder::MapBuilder::new((0.1, 0.1), (1.3, 1.3))
+		.interval(8, 5, 9)
+		.interval(10, 10, 12)
+		.poi(5, (0.3, 0.3), &[("name", "a")])
+		.build();
+	let path = std::env::temp_dir().join(format!("mapviewer-test-summary-{}.map", std::process::id()));
+	std::fs::write(&path, &bytes).unwrap();
+	let summary = MapFile::summary(&path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+	assert_eq!(summary.bounds, (LatLon::from_degrees(1.3, 0.1), LatLon::from_degrees(0.1, 1.3)));
+	// One tile covers the map at zoom 8, and four by four at zoom 10
+	assert_eq!(summary.zoom_intervals, vec![ZoomSummary { base: 8, min: 5, max: 9, tiles: 1 }, ZoomSummary { base: 10, min: 10, max: 12, tiles: 16 }]);
+	assert_eq!((summary.tile_size, summary.creator, summary.created), (256, None, 0));
+	assert!(matches!(MapFile::summary(&path), Err(MapError::Open(_))));
+}
+
+#[test]
+fn test_header_accessors() {
+	let map = builder::MapBuilder::new((0.1, 0.2), (1.3, 1.4)).interval(8, 0, 10).open();
+	let header = map.header();
+	assert_eq!(header.version(), 3);
+	assert_eq!(header.created(), UNIX_EPOCH);
+	assert_eq!(header.comment(), None);
+	assert_eq!(header.creator(), None);
+	assert_eq!(header.projection(), "Mercator");
+	assert_eq!(header.bounds_latlon(), (LatLon::new(1300000, 200000), LatLon::new(100000, 1400000)));
+	assert_eq!((header.start_pos(), header.start_zoom()), (None, None));
+	// Either part of the suggested start can be given without the other
+	let map = builder::MapBuilder::new((0.1, 0.2), (1.3, 1.4)).interval(8, 0, 10).start_pos((0.5, 0.6)).start_zoom(9).open();
+	assert_eq!((map.header().start_pos(), map.header().start_zoom()), (Some(LatLon::from_degrees(0.5, 0.6)), Some(9)));
+	let map = builder::MapBuilder::new((0.1, 0.2), (1.3, 1.4)).interval(8, 0, 10).start_zoom(9).open();
+	assert_eq!((map.header().start_pos(), map.header().start_zoom()), (None, Some(9)));
+}
+
+#[test]
+fn test_zoom_levels() {
+	let middle = LatLon::from_degrees(0.4, 0.4);
+	let map = builder::MapBuilder::new((0.1, 0.1), (1.3, 1.3))
+		.interval(8, 5, 9)
+		.interval(12, 10, 14)
+		.poi(5, (0.3, 0.3), &[("name", "a")])
+		.poi(9, (1.0, 1.0), &[("name", "b")])
+		.way_in_subtiles(5, &[(0.3, 0.3), (1.0, 1.0)], &[("name", "everywhere")], 0xffff)
+		.way_in_subtiles(9, &[(1.2, 0.2), (1.3, 0.3)], &[("name", "top left")], 0x8000)
+		.way_in_subtiles(9, &[(1.2, 1.2), (1.3, 1.3)], &[("name", "top right")], 0x1000)
+		.poi(10, (0.4, 0.4), &[("name", "c")])
+		.way_in_subtiles(13, &[(0.4, 0.4), (0.401, 0.401)], &[("name", "detail")], 0xffff)
+		.open();
+	let names = |tile: Result<Tile, MapError>| {
+		let tile = tile.unwrap();
+		let pois = tile.pois.iter().map(|poi| poi.name(None).unwrap()).collect::<Vec<_>>();
+		let ways = tile.ways.iter().map(|way| way.name(None).unwrap()).collect::<Vec<_>>();
+		(pois.join(" "), ways.join(" "))
+	};
+	let expect = |pois: &str, ways: &str| (pois.to_string(), ways.to_string());
+	// The base zoom only gets what shows up to it, but the stored tile has everything
+	assert_eq!(names(map.tile(8, 128, 127)), expect("a", "everywhere"));
+	assert_eq!(names(map.stored_tile(8, 128, 127)), expect("a b", "everywhere top left top right"));
+	let top_right = map.stored_tile(8, 128, 127).unwrap().ways.into_iter().find(|way| way.name(None) == Some("top right")).unwrap();
+	assert!(top_right.subtile_visible(3, 0) && !top_right.subtile_visible(0, 0));
+	// One level up, the base tile is split in four by position and sub-tiles
+	assert_eq!(names(map.tile(9, 256, 254)), expect("", "everywhere top left"));
+	assert_eq!(names(map.tile(9, 257, 254)), expect("b", "everywhere top right"));
+	assert_eq!(names(map.tile(9, 256, 255)), expect("a", "everywhere"));
+	// Below the base zoom, features end up in the same places as they are in the base tile
+	assert_eq!(names(map.tile(6, 32, 31)), expect("a", "everywhere"));
+	let (base, combined) = (map.tile(8, 128, 127).unwrap(), map.tile(6, 32, 31).unwrap());
+	assert_eq!(combined.pois[0].project(&combined), base.pois[0].project(&base));
+	assert_eq!(combined.ways[0].project(&combined), base.ways[0].project(&base));
+	// The second interval is read from its own subfile, which has the features from lower zooms
+	// as well
+	let (x, y) = coord2tile(12, middle);
+	assert_eq!(names(map.tile(12, x, y)), expect("c", "everywhere"));
+	let child = coord2tile(13, middle);
+	assert_eq!(names(map.tile(13, child.0, child.1)), expect("c", "everywhere detail"));
+	let (base, combined) = (map.tile(12, x, y).unwrap(), map.tile(10, x >> 2, y >> 2).unwrap());
+	assert_eq!(combined.pois[0].project(&combined), base.pois[0].project(&base));
+	assert!(matches!(map.stored_tile(9, 256, 254), Err(MapError::NotBaseZoom(9))));
+	assert!(matches!(map.tile(4, 16, 15), Err(MapError::NoZoomInterval(4))));
+}
+
+#[test]
+fn test_project_all() {
+	let map = builder::MapBuilder::new((0.1, 0.1), (1.3, 1.3))
+		.interval(8, 5, 9)
+		.poi(5, (0.3, 0.3), &[("name", "a")])
+		.poi(5, (1.0, 1.2), &[("name", "b")])
+		.way(5, &[(0.3, 0.3), (1.0, 1.0)], &[("name", "line")])
+		.way(5, &[(0.3, 0.3), (0.3, 0.5), (0.5, 0.5), (0.3, 0.3)], &[("name", "ring")])
+		.way(5, &[(1.2, 0.2), (1.3, 0.3), (1.25, 0.4)], &[("name", "bend")])
+		.open();
+	let tile = map.tile(8, 128, 127).unwrap();
+	assert_eq!(tile.pois.len(), 2);
+	assert_eq!(tile.ways.len(), 3);
+	// Projecting everything together gives the same points as projecting each feature alone
+	assert_eq!(tile.project_pois(), tile.pois.iter().map(|poi| poi.project(&tile)).collect::<Vec<_>>());
+	assert_eq!(tile.project_ways(), tile.ways.iter().map(|way| way.project(&tile)).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_is_area() {
+	let map = builder::MapBuilder::new((0.1, 0.1), (1.3, 1.3))
+		.interval(8, 5, 9)
+		.way(5, &[(0.3, 0.3), (0.3, 0.5), (0.5, 0.5), (0.3, 0.3)], &[("name", "ring")])
+		.way(5, &[(0.3, 0.3), (0.3, 0.5), (0.5, 0.5)], &[("name", "line")])
+		.way(5, &[(0.3, 0.3), (0.3, 0.5), (0.5, 0.5)], &[("name", "tagged"), ("area", "yes")])
+		.way(5, &[(0.3, 0.3), (0.3, 0.5), (0.5, 0.5), (0.3, 0.3)], &[("name", "loop"), ("area", "no")])
+		.open();
+	let tile = map.tile(8, 128, 127).unwrap();
+	let areas = tile.ways.iter().filter(|way| way.is_area()).map(|way| way.name(None).unwrap()).collect::<Vec<_>>();
+	assert_eq!(areas, vec!["ring", "tagged"]);
+}
+
+#[test]
+fn test_project_high_latitude() {
+	let map = builder::MapBuilder::new((59.9, 9.9), (60.1, 10.1))
+		.interval(14, 12, 16)
+		.way(12, &[(60.0, 10.0), (60.01, 10.02)], &[("highway", "primary")])
+		.open();
+	let (x, y) = coord2tile(14, LatLon::from_degrees(60.0, 10.0));
+	let tile = map.tile(14, x, y).unwrap();
+	// Pixels at zoom 18, worked out separately from the usual Web Mercator formulas
+	let pixels = tile.ways[0].project(&tile)[0][0].iter().map(|coord| {
+		let scale = 256.0 * (1 << 18) as f64 / COORD_MAX as f64;
+		(coord.x as f64 * scale, coord.y as f64 * scale)
+	}).collect::<Vec<_>>();
+	for (actual, expected) in pixels.iter().zip(&[(35418567.11, 19488390.0), (35422295.38, 19484661.17)]) {
+		assert!((actual.0 - expected.0).abs() < 0.5 && (actual.1 - expected.1).abs() < 0.5, "{:?} should be {:?}", actual, expected);
+	}
+}
+
+// A base tile at zoom 14 in the middle of a city, packed with a few thousand buildings
+#[cfg(test)]
+fn city_tile() -> Tile {
+	let mut map = builder::MapBuilder::new((51.49, -0.11), (51.51, -0.09)).interval(14, 12, 16);
+	for row in 0..60 {
+		for col in 0..60 {
+			let (lat, lon) = (51.495 + row as f64 * 0.00015, -0.105 + col as f64 * 0.0002);
+			let ring = [(lat, lon), (lat, lon + 0.0001), (lat + 0.0001, lon + 0.0001), (lat + 0.0001, lon), (lat, lon)];
+			map = map.way(14, &ring, &[("building", "yes")]);
+		}
+	}
+	let (x, y) = coord2tile(14, LatLon::from_degrees(51.5, -0.1));
+	map.open().tile(14, x, y).unwrap()
+}
+
+// Projecting every way in a dense tile with the origin the tile keeps, against working it out
+// again for each way as tiles used to.  Run with cargo bench.
+#[bench]
+fn bench_project(b: &mut test::Bencher) {
+	let tile = city_tile();
+	assert!(tile.ways.len() > 1000);
+	b.iter(|| tile.ways.iter().map(|way| way.project(&tile)).collect::<Vec<_>>());
+}
+
+#[bench]
+fn bench_project_recomputing_origin(b: &mut test::Bencher) {
+	let tile = city_tile();
+	b.iter(|| tile.ways.iter().map(|way| {
+		let origin = tile_origin(tile.zoom, tile.index.0, tile.index.1);
+		way.blocks.iter().map(|block| block.iter().map(|path| path.iter().map(|offset| origin.add(offset).to_coord()).collect::<Vec<_>>()).collect::<Vec<_>>()).collect::<Vec<_>>()
+	}).collect::<Vec<_>>());
+}
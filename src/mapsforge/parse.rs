@@ -1,13 +1,15 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use nom::bytes::complete::*;
 use nom::combinator::*;
 use nom::multi::*;
 use nom::number::complete::{be_f32, be_i8, be_i16, be_i32, be_u8, be_u16, be_u32, be_u64};
 use nom::sequence::*;
-use nom::IResult;
+use nom::error::{Error, ErrorKind};
+use nom::{IResult, Parser};
 
-use super::{LatLonBounds, LatLon, MapHeader, Poi, TagDesc, TagValue, TileHeader, TileIndex, Way, ZoomInterval};
+use super::{LatLonBounds, LatLon, MapHeader, Names, Poi, TagDesc, TagValue, TileHeader, TileIndex, Way, ZoomInterval};
 
 fn merge_vbe(first: u8, rest: &[u8]) -> u64 {
 	let mut ret = (first as u64) << (7 * rest.len());
@@ -19,19 +21,38 @@ fn merge_vbe(first: u8, rest: &[u8]) -> u64 {
 	ret
 }
 
+// A 64-bit value needs at most nine continuation bytes; any more and the shifts in merge_vbe
+// would overflow
+fn vbe_bytes(i: &[u8]) -> IResult<&[u8], (&[u8], u8)> {
+	let (rest, (cont, last)) = pair(take_while(|c| c & 0x80 != 0), be_u8)(i)?;
+	if cont.len() > 9 { Err(nom::Err::Failure(Error::new(i, ErrorKind::TooLarge))) }
+	else { Ok((rest, (cont, last))) }
+}
+
 fn vbe_u(i: &[u8]) -> IResult<&[u8], u64> {
-	let (i, (rest, first)) = pair(take_while(|c| c & 0x80 != 0), be_u8)(i)?;
+	let (i, (rest, first)) = vbe_bytes(i)?;
 	let ret = merge_vbe(first, &rest);
 	Ok((i, ret))
 }
 
 fn vbe_s(i: &[u8]) -> IResult<&[u8], i64> {
-	let (i, (rest, first)) = pair(take_while(|c| c & 0x80 != 0), be_u8)(i)?;
+	let (i, (rest, first)) = vbe_bytes(i)?;
 	let ret = merge_vbe(first & 0x3f, rest);
 	let mul = if first & 0x40 != 0 { -1 } else { 1 };
 	Ok((i, mul * (ret as i64)))
 }
 
+// Like count, but for element counts read from the file: every element takes at least one byte,
+// so a count larger than the remaining input means the file is corrupt and we fail rather than
+// trying to allocate space for it
+fn bounded_count<'a, O, F: Parser<&'a [u8], O, Error<&'a [u8]>>>(f: F, num: u64) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], Vec<O>> {
+	let mut inner = count(f, num as usize);
+	move |i: &'a [u8]| {
+		if num > i.len() as u64 { Err(nom::Err::Failure(Error::new(i, ErrorKind::TooLarge))) }
+		else { inner(i) }
+	}
+}
+
 fn latlon(i: &[u8]) -> IResult<&[u8], LatLon> {
 	let (i, values) = tuple((vbe_s, vbe_s))(i)?;
 	Ok((i, LatLon::new(values.0 as i32, values.1 as i32)))
@@ -40,7 +61,24 @@ fn latlon(i: &[u8]) -> IResult<&[u8], LatLon> {
 fn string(i: &[u8]) -> IResult<&[u8], String> {
 	let (i, len) = vbe_u(i)?;
 	let (i, ret) = take(len as usize)(i)?;
-	Ok((i, String::from_utf8(ret.to_vec()).unwrap()))
+	match String::from_utf8(ret.to_vec()) {
+		Ok(s) => Ok((i, s)),
+		Err(_) => Err(nom::Err::Failure(Error::new(ret, ErrorKind::Char))),
+	}
+}
+
+// A name field holds the default name, then a carriage return, language code, backspace, and name
+// for each other language it's known in.  Parts that don't look like that are dropped.
+fn names(raw: String) -> Names {
+	let mut parts = raw.split('\r');
+	let mut ret = Names::new();
+	if let Some(default) = parts.next().filter(|name| !name.is_empty()) { ret.insert(None, default.to_string()); }
+	for part in parts {
+		if let Some((lang, name)) = part.split_once('\x08') {
+			if !lang.is_empty() && !name.is_empty() { ret.insert(Some(lang.to_string()), name.to_string()); }
+		}
+	}
+	ret
 }
 
 fn zoom_interval(i: &[u8]) -> IResult<&[u8], ZoomInterval> {
@@ -126,6 +164,9 @@ fn tag_value<'a, 'b>(desc: &TagDesc, i: &'b [u8]) -> IResult<&'b [u8], TagValue>
 
 fn tagmap<'a, 'b>(ntags: u8, tags: &'a [(String, TagDesc)], i: &'b [u8]) -> IResult<&'b [u8], HashMap<String, TagValue>> {
 	let (i, tag_ids) = count (|i| vbe_u(i), ntags as usize)(i)?;
+	if tag_ids.iter().any(|id| *id as usize >= tags.len()) {
+		return Err(nom::Err::Failure(Error::new(i, ErrorKind::Verify)));
+	}
 	let tag_descs = tag_ids.into_iter().map(|id| tags[id as usize].clone()).collect::<Vec<(String, TagDesc)>>();
 	let mut newi = i;
 	let mut tag_values = Vec::with_capacity(tag_descs.len());
@@ -156,8 +197,8 @@ pub fn poi<'a, 'b>(debug: bool, tags: &'a [(String, TagDesc)], i: &'b [u8]) -> I
 	Ok((i, Poi {
 		offset: head.1,
 		layer,
-		tags,
-		name: optfields.0,
+		tags: Arc::new(tags),
+		names: optfields.0.map_or(Names::new(), names),
 		house_number: optfields.1,
 		elevation: optfields.2,
 	}))
@@ -192,12 +233,12 @@ fn decode_double_delta(points: &[LatLon]) -> Vec<LatLon> {
 
 fn coord_block(i: &[u8]) -> IResult<&[u8], Vec<LatLon>> {
 	let (i, num) = vbe_u(i)?;
-	Ok(count(latlon, num as usize)(i)?)
+	bounded_count(latlon, num)(i)
 }
 
 fn way_block(double_delta: bool, i: &[u8]) -> IResult<&[u8], Vec<Vec<LatLon>>> {
 	let (i, num) = vbe_u(i)?;
-	let (i, points) = count(coord_block, num as usize)(i)?;
+	let (i, points) = bounded_count(coord_block, num)(i)?;
 	let decoded = points.into_iter().map(|poly| match double_delta {
 		false => decode_single_delta(&poly),
 		true => decode_double_delta(&poly),
@@ -225,13 +266,13 @@ pub fn way<'a, 'b>(debug: bool, tags: &'a [(String, TagDesc)], i: &'b [u8]) -> I
 	))(i)?;
 	let nblocks = optfields.4.unwrap_or(1);
 	let double_delta = flags & 0x04 != 0;
-	let (i, blocks) = count(|i| way_block(double_delta, i), nblocks as usize)(i)?;
+	let (i, blocks) = bounded_count(|i| way_block(double_delta, i), nblocks)(i)?;
 	Ok((i, Way {
 		size: fields.1,
 		subtile_map: fields.2,
 		layer,
-		tags,
-		name: optfields.0,
+		tags: Arc::new(tags),
+		names: optfields.0.map_or(Names::new(), names),
 		house_number: optfields.1,
 		reference: optfields.2,
 		label_pos: optfields.3,
@@ -269,3 +310,29 @@ fn test_string() {
 		(b"\x05helloworld".to_vec(), "hello".to_string(), b"world".to_vec()),
 	]);
 }
+
+#[test]
+fn test_names() {
+	let names = names("Köln\ren\x08Cologne\rit\x08Colonia\rbroken".to_string());
+	assert_eq!(names.len(), 3);
+	assert_eq!(names.get(&None).map(String::as_str), Some("Köln"));
+	assert_eq!(names.get(&Some("en".to_string())).map(String::as_str), Some("Cologne"));
+	let way = Way { size: 0, subtile_map: 0, layer: 0, tags: Default::default(), names, house_number: None, reference: None, label_pos: None, blocks: vec![] };
+	assert_eq!(way.name(Some("it")), Some("Colonia"));
+	assert_eq!(way.name(Some("EN-gb")), Some("Cologne"));
+	assert_eq!(way.name(Some("fr")), Some("Köln"));
+	assert_eq!(way.name(None), Some("Köln"));
+}
+
+#[test]
+fn test_tagmap() {
+	let table = vec![TagDesc::parse("highway=primary".to_string()), TagDesc::parse("ele=%i".to_string())];
+	let (rest, tags) = tagmap(2, &table, &[0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0xff]).unwrap();
+	assert_eq!(rest, &[0xff]);
+	assert_eq!(tags.get("highway"), Some(&TagValue::Literal("primary".to_string())));
+	assert_eq!(tags.get("ele"), Some(&TagValue::Int(300)));
+	// An ID past the end of the table is rejected rather than indexed, since there's no knowing
+	// how long its value is
+	assert!(matches!(tagmap(2, &table, &[0x00, 0x02]), Err(nom::Err::Failure(Error { code: ErrorKind::Verify, .. }))));
+	assert!(matches!(tagmap(1, &table, &[0x80, 0x80, 0x80, 0x80, 0x10]), Err(nom::Err::Failure(Error { code: ErrorKind::Verify, .. }))));
+}
@@ -0,0 +1,116 @@
+use std::io::{BufRead, BufReader, Read};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use mapviewer::mapsforge::LatLon;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fix {
+	pub position: LatLon,
+	pub heading: Option<f64>, // Degrees clockwise from north
+}
+
+pub enum GpsEvent {
+	Fix(Fix),
+	Disconnected(String),
+}
+
+// Check the two hex digits after the * against the XOR of everything between the $ and the *.
+// Sentences without a checksum are accepted as they are.
+fn checksum_ok(line: &str) -> bool {
+	match line.rsplit_once('*') {
+		Some((body, sum)) => match u8::from_str_radix(sum.trim(), 16) {
+			Ok(sum) => body.bytes().skip(1).fold(0, |acc, b| acc ^ b) == sum,
+			Err(_) => false,
+		},
+		None => true,
+	}
+}
+
+// NMEA gives angles as degrees and decimal minutes run together, like 4807.038 for 48°7.038'
+fn coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+	let raw = value.parse::<f64>().ok()?;
+	let degrees = (raw / 100.0).trunc() + (raw % 100.0) / 60.0;
+	match hemisphere {
+		"N" | "E" => Some(degrees),
+		"S" | "W" => Some(-degrees),
+		_ => None,
+	}
+}
+
+// Parse an RMC or GGA sentence from any talker.  Returns None for other sentences, corrupt
+// ones, and ones the receiver has marked as not having a fix.
+pub fn parse_sentence(line: &str) -> Option<Fix> {
+	let line = line.trim();
+	if !line.starts_with('$') || !checksum_ok(line) { return None; }
+	let fields = line.split('*').next()?.split(',').collect::<Vec<_>>();
+	let kind = fields[0].get(3..)?;
+	let (lat_field, valid, heading) = match kind {
+		"RMC" => (3, *fields.get(2)? == "A", fields.get(8).and_then(|x| x.parse::<f64>().ok())),
+		"GGA" => (2, fields.get(6)?.parse::<u8>().ok()? > 0, None),
+		_ => return None,
+	};
+	if !valid { return None; }
+	let lat = coordinate(fields.get(lat_field)?, fields.get(lat_field + 1)?)?;
+	let lon = coordinate(fields.get(lat_field + 2)?, fields.get(lat_field + 3)?)?;
+	Some(Fix { position: LatLon::from_degrees(lat, lon), heading })
+}
+
+// Sources that look like host:port are TCP servers.  Anything else is a file, which may be a
+// serial device that's already been configured (with stty, say) or a recording.
+fn open(source: &str) -> std::io::Result<(Box<dyn Read + Send>, bool)> {
+	if !std::path::Path::new(source).exists() && source.contains(':') {
+		Ok((Box::new(TcpStream::connect(source)?), false))
+	}
+	else {
+		let file = std::fs::File::open(source)?;
+		let recording = file.metadata()?.is_file();
+		Ok((Box::new(file), recording))
+	}
+}
+
+// Read fixes from the source on a background thread until send reports that nobody is listening
+// anymore.  Live sources are reopened if they drop out.  Recordings are played back at one fix
+// per second, which is what most receivers produce, and reading stops at the end.
+pub fn spawn_reader(source: String, send: impl Fn(GpsEvent) -> bool + Send + 'static) {
+	std::thread::spawn(move || loop {
+		let reason = match open(&source) {
+			Ok((reader, recording)) => {
+				let mut reason = "end of input".to_string();
+				for line in BufReader::new(reader).lines() {
+					match line {
+						Ok(line) => if let Some(fix) = parse_sentence(&line) {
+							if !send(GpsEvent::Fix(fix)) { return; }
+							if recording { std::thread::sleep(Duration::from_secs(1)); }
+						},
+						Err(e) => { reason = e.to_string(); break; },
+					}
+				}
+				if recording {
+					send(GpsEvent::Disconnected(format!("{}: {}", source, reason)));
+					return;
+				}
+				reason
+			},
+			Err(e) => e.to_string(),
+		};
+		if !send(GpsEvent::Disconnected(format!("{}: {}", source, reason))) { return; }
+		std::thread::sleep(Duration::from_secs(5));
+	});
+}
+
+#[test]
+fn test_parse_sentence() {
+	let rmc = parse_sentence("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A").unwrap();
+	assert_eq!(rmc.position, LatLon::from_degrees(48.0 + 7.038 / 60.0, 11.0 + 31.0 / 60.0));
+	assert_eq!(rmc.heading, Some(84.4));
+	let gga = parse_sentence("$GPGGA,123519,4807.038,S,01131.000,W,1,08,0.9,545.4,M,46.9,M,,*48").unwrap();
+	assert_eq!(gga.position, LatLon::from_degrees(-(48.0 + 7.038 / 60.0), -(11.0 + 31.0 / 60.0)));
+	assert_eq!(gga.heading, None);
+	assert!(parse_sentence("$GNRMC,123519,A,4807.038,N,01131.000,E,,,230394,,").is_some()); // No checksum or course
+	assert!(parse_sentence("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6B").is_none()); // Bad checksum
+	assert!(parse_sentence("$GPRMC,123519,V,,,,,,,230394,,*33").is_none()); // No fix
+	assert!(parse_sentence("$GPGGA,123519,,,,,0,00,,,M,,M,,*6B").is_none());
+	assert!(parse_sentence("$GPGSV,3,1,11,03,03,111,00,04,15,270,00,06,01,010,00,13,06,292,00*74").is_none());
+	assert!(parse_sentence("garbage").is_none());
+}
@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use mapviewer::mapsforge::LatLon;
+use mapviewer::render;
+
+use crate::{slippy, viewurl, NoDataPattern};
+
+pub struct Options {
+	pub maps: Vec<PathBuf>,
+	pub check: bool, // Validate the maps and exit instead of viewing them
+	pub clip: Option<PathBuf>, // Polygon outside of which nothing is drawn
+	pub no_mmap: bool, // Read maps from disk as needed rather than mapping them into memory
+	pub prefault: bool, // Start reading the tiles each view needs from mapped maps before building them
+	pub prefault_all: bool, // Start reading mapped maps into memory as soon as they're opened
+	pub diff: bool, // Highlight the differences between two maps
+	pub pick: bool, // Print clicked locations to stdout
+	pub density_limit: Option<usize>, // Most objects to draw per tile layer, or None for no limit
+	pub gps: Option<String>, // NMEA source to follow: a device, a recording, or host:port
+	pub cache_limit: usize, // Most built tiles to keep in memory
+	pub tile_cache: Option<PathBuf>, // Directory to keep built tiles in between sessions
+	pub theme: Option<PathBuf>, // Mapsforge render theme to use instead of the basic one
+	pub keys: Option<PathBuf>, // Key bindings to use instead of the usual ones
+	pub highlight: Option<String>, // Features to make stand out, by name or tag
+	pub lang: Option<String>, // Language to show names in where the map has them
+	pub split: bool, // Show two independent views side by side
+	pub animate: bool, // Move smoothly to bookmarks and GPS fixes
+	pub no_data: NoDataPattern, // What to draw where no map has any data
+	pub idle_timeout: Option<Duration>, // Free what can be rebuilt after this long with nothing happening
+	pub view: Option<viewurl::View>, // Where to start instead of showing the whole map
+	pub center: Option<LatLon>, // Where to center the view, keeping the zoom it would have had
+	pub zoom: Option<f64>, // Zoom level to start at, keeping the center it would have had
+	pub fit: bool, // Start out showing the whole map even if it says where to start
+	pub tiles: Option<PathBuf>, // Write slippy map tiles here instead of opening a window
+	pub zoom_range: Option<(u8, u8)>, // Zoom levels to write tiles for
+	pub bbox: Option<(LatLon, LatLon)>, // Area to write tiles for, if not the whole map
+	pub debug_hud: bool, // Show frame rate and tile counts for profiling
+	pub dem: Option<PathBuf>, // Elevations to shade relief from under the map
+	pub vignette: f32, // How far in from the edges screenshots and exported tiles fade out, from 0 to 1
+}
+
+impl Options {
+	pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+		let mut ret = Self { maps: vec![], check: false, clip: None, no_mmap: false, prefault: false, prefault_all: false, diff: false, pick: false, density_limit: Some(render::DEFAULT_DENSITY_LIMIT), gps: None, cache_limit: render::DEFAULT_CACHE_LIMIT, tile_cache: None, theme: None, keys: None, highlight: None, lang: None, split: false, animate: true, no_data: NoDataPattern::Plain, idle_timeout: None, view: None, center: None, zoom: None, fit: false, tiles: None, zoom_range: None, bbox: None, debug_hud: false, dem: None, vignette: 0.0 };
+		while let Some(arg) = args.next() {
+			let mut value = |name: &str| args.next().ok_or(format!("{} requires an argument", name));
+			match arg.as_str() {
+				"--check" => ret.check = true,
+				"--no-mmap" => ret.no_mmap = true,
+				"--prefault" => ret.prefault = true,
+				"--prefault-all" => ret.prefault_all = true,
+				"--diff" => ret.diff = true,
+				"--pick" => ret.pick = true,
+				"--split" => ret.split = true,
+				"--fit" => ret.fit = true,
+				"--debug-hud" => ret.debug_hud = true,
+				"--no-animation" => ret.animate = false,
+				"--no-data" => ret.no_data = NoDataPattern::parse(&value("--no-data")?)?,
+				"--vignette" => {
+					let strength = value("--vignette")?.parse::<f32>().map_err(|e| format!("--vignette: {}", e))?;
+					if !(0.0..=1.0).contains(&strength) { return Err("--vignette must be from 0 to 1".to_string()); }
+					ret.vignette = strength;
+				},
+				"--idle-timeout" => {
+					let secs = value("--idle-timeout")?.parse::<f64>().map_err(|e| format!("--idle-timeout: {}", e))?;
+					if !secs.is_finite() || secs <= 0.0 { return Err("--idle-timeout must be positive".to_string()); }
+					ret.idle_timeout = Some(Duration::from_secs_f64(secs));
+				},
+				"--gps" => ret.gps = Some(value("--gps")?),
+				"--max-objects" => {
+					let limit = value("--max-objects")?.parse::<usize>().map_err(|e| format!("--max-objects: {}", e))?;
+					ret.density_limit = if limit == 0 { None } else { Some(limit) };
+				},
+				"--theme" => ret.theme = Some(PathBuf::from(value("--theme")?)),
+				"--keys" => ret.keys = Some(PathBuf::from(value("--keys")?)),
+				"--cache-size" => {
+					ret.cache_limit = value("--cache-size")?.parse::<usize>().ok().filter(|limit| *limit > 0).ok_or("--cache-size needs a positive number of tiles")?;
+				},
+				"--highlight" => ret.highlight = Some(value("--highlight")?),
+				"--lang" => ret.lang = Some(value("--lang")?),
+				"--tile-cache" => ret.tile_cache = Some(PathBuf::from(value("--tile-cache")?)),
+				"--view" => ret.view = Some(viewurl::parse(&value("--view")?)?),
+				"--center" => ret.center = Some(viewurl::parse_center(&value("--center")?)?),
+				"--zoom" => ret.zoom = Some(viewurl::parse_zoom(&value("--zoom")?)?),
+				"--tiles" => ret.tiles = Some(PathBuf::from(value("--tiles")?)),
+				"--zoom-range" => ret.zoom_range = Some(slippy::parse_zoom_range(&value("--zoom-range")?)?),
+				"--bbox" => ret.bbox = Some(slippy::parse_bbox(&value("--bbox")?)?),
+				"--dem" => ret.dem = Some(PathBuf::from(value("--dem")?)),
+				"--clip" => ret.clip = Some(PathBuf::from(value("--clip")?)),
+				_ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+				_ => ret.maps.push(PathBuf::from(arg)),
+			}
+		}
+		Ok(ret)
+	}
+}
+
+// Read a polygon from a file containing one "lat,lon" pair in decimal degrees per line.  Blank
+// lines and lines starting with # are ignored.
+pub fn read_polygon(path: &Path) -> Result<Vec<LatLon>, String> {
+	let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+	parse_polygon(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn parse_polygon(text: &str) -> Result<Vec<LatLon>, String> {
+	let mut ret = vec![];
+	for (lineno, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') { continue; }
+		let fields = line.split(|c: char| c == ',' || c.is_whitespace()).filter(|s| !s.is_empty()).map(|s| s.parse::<f64>()).collect::<Vec<_>>();
+		match fields.as_slice() {
+			[Ok(lat), Ok(lon)] => ret.push(LatLon::from_degrees(*lat, *lon)),
+			_ => return Err(format!("line {}: expected \"lat,lon\"", lineno + 1)),
+		}
+	}
+	if ret.len() < 3 { return Err("a polygon needs at least three points".to_string()); }
+	Ok(ret)
+}
+
+#[test]
+fn test_parse_polygon() {
+	let polygon = parse_polygon("# Somewhere\n45.5,6.25\n\n  46, 6.5\n45.75 7\n").unwrap();
+	assert_eq!(polygon, vec![LatLon::from_degrees(45.5, 6.25), LatLon::from_degrees(46.0, 6.5), LatLon::from_degrees(45.75, 7.0)]);
+	assert_eq!(parse_polygon("45,6\n46\n46,7\n").unwrap_err(), "line 2: expected \"lat,lon\"");
+	assert_eq!(parse_polygon("45,6\n46,7,8\n46,7\n").unwrap_err(), "line 2: expected \"lat,lon\"");
+	assert_eq!(parse_polygon("45,6\nnorth,7\n46,7\n").unwrap_err(), "line 2: expected \"lat,lon\"");
+	assert!(parse_polygon("45,6\n46,7\n").is_err());
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use super::mapsforge::Coord;
+use super::render::{BoundingBox, Geometry, Object};
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+fn latlon_attrs(point: Coord) -> String {
+	let (lat, lon) = point.to_latlon().degrees();
+	format!("lat=\"{:.6}\" lon=\"{:.6}\"", lat, lon)
+}
+
+// The object's tags, plus its name, which maps keep separately, sorted so the output is stable
+fn tags(obj: &Object) -> Vec<(String, String)> {
+	let mut ret = obj.tags.iter().map(|(key, value)| (key.clone(), value.to_string())).collect::<Vec<_>>();
+	if let Some(name) = &obj.name {
+		if !obj.tags.contains_key("name") { ret.push(("name".to_string(), name.clone())); }
+	}
+	ret.sort();
+	ret
+}
+
+fn write_tags(out: &mut impl Write, tags: &[(String, String)]) -> io::Result<()> {
+	for (key, value) in tags {
+		writeln!(out, "\t\t<tag k=\"{}\" v=\"{}\"/>", escape(key), escape(value))?;
+	}
+	Ok(())
+}
+
+pub(crate) fn touches(geo: &Geometry, area: &BoundingBox) -> bool {
+	match geo {
+		// A point's bounding box has no size, so it would never count as intersecting
+		Geometry::Point(point) => match area.corners() {
+			Some((min, max)) => (min.x..=max.x).contains(&point.x) && (min.y..=max.y).contains(&point.y),
+			None => false,
+		},
+		Geometry::Path(polies) | Geometry::Area(polies) => {
+			let mut bounds = BoundingBox::empty();
+			for point in polies.iter().flatten() { bounds.include(*point); }
+			!bounds.intersection(area).is_empty()
+		},
+	}
+}
+
+// Write the objects that touch the area as OSM XML that JOSM can open, returning how many were
+// written.  Everything gets a new negative ID, as for features that haven't been uploaded yet.
+// Points along ways that are in the same place become one node, and areas with holes become
+// multipolygon relations.  Objects without any tags, like the tints the renderer adds itself, are
+// left out.  Ways are cut at tile edges when tiles are built, so a long way comes out as several
+// overlapping pieces.
+pub fn write_xml<'a>(objects: impl IntoIterator<Item = &'a Object>, area: &BoundingBox, out: &mut impl Write) -> io::Result<usize> {
+	let mut next_id: i64 = 0;
+	let mut new_id = || { next_id -= 1; next_id };
+	let mut nodes = vec![]; // ID, position, and tags
+	let mut ways = vec![]; // ID, node IDs, and tags
+	let mut relations = vec![]; // ID, members with their roles, and tags
+	let mut shared_nodes = HashMap::new();
+	let mut count = 0;
+	for obj in objects {
+		let tags = tags(obj);
+		if tags.is_empty() || !touches(&obj.geo, area) { continue; }
+		count += 1;
+		match &obj.geo {
+			Geometry::Point(point) => nodes.push((new_id(), *point, tags)),
+			Geometry::Path(polies) | Geometry::Area(polies) => {
+				let mut members = vec![];
+				for poly in polies {
+					let refs = poly.iter().map(|point| *shared_nodes.entry(*point).or_insert_with(|| {
+						let id = new_id();
+						nodes.push((id, *point, vec![]));
+						id
+					})).collect::<Vec<_>>();
+					members.push((new_id(), refs));
+				}
+				let closed = members.iter().all(|(_, refs)| refs.len() > 3 && refs.first() == refs.last());
+				if members.len() > 1 && closed {
+					// The first ring of an area is its outline and the rest are holes in it
+					let roles = members.iter().enumerate().map(|(i, (id, _))| (*id, if i == 0 { "outer" } else { "inner" })).collect::<Vec<_>>();
+					let mut tags = tags;
+					tags.push(("type".to_string(), "multipolygon".to_string()));
+					tags.sort();
+					relations.push((new_id(), roles, tags));
+					ways.extend(members.into_iter().map(|(id, refs)| (id, refs, vec![])));
+				}
+				else {
+					ways.extend(members.into_iter().map(|(id, refs)| (id, refs, tags.clone())));
+				}
+			},
+		}
+	}
+	writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+	// The data comes from a map rather than the OSM database, so editors shouldn't upload it
+	writeln!(out, "<osm version=\"0.6\" generator=\"mapviewer\" upload=\"false\">")?;
+	if let Some((min, max)) = area.corners() {
+		// Map coordinates run south from the top, so the top left corner is the north west one
+		let ((maxlat, minlon), (minlat, maxlon)) = (min.to_latlon().degrees(), max.to_latlon().degrees());
+		writeln!(out, "\t<bounds minlat=\"{:.6}\" minlon=\"{:.6}\" maxlat=\"{:.6}\" maxlon=\"{:.6}\"/>", minlat, minlon, maxlat, maxlon)?;
+	}
+	for (id, point, tags) in &nodes {
+		if tags.is_empty() { writeln!(out, "\t<node id=\"{}\" {}/>", id, latlon_attrs(*point))?; }
+		else {
+			writeln!(out, "\t<node id=\"{}\" {}>", id, latlon_attrs(*point))?;
+			write_tags(out, tags)?;
+			writeln!(out, "\t</node>")?;
+		}
+	}
+	for (id, refs, tags) in &ways {
+		writeln!(out, "\t<way id=\"{}\">", id)?;
+		for node in refs { writeln!(out, "\t\t<nd ref=\"{}\"/>", node)?; }
+		write_tags(out, tags)?;
+		writeln!(out, "\t</way>")?;
+	}
+	for (id, members, tags) in &relations {
+		writeln!(out, "\t<relation id=\"{}\">", id)?;
+		for (way, role) in members { writeln!(out, "\t\t<member type=\"way\" ref=\"{}\" role=\"{}\"/>", way, role)?; }
+		write_tags(out, tags)?;
+		writeln!(out, "\t</relation>")?;
+	}
+	writeln!(out, "</osm>")?;
+	Ok(count)
+}
+
+#[test]
+fn test_write_xml() {
+	use super::mapsforge::{LatLon, TagValue};
+	let c = |lat, lon| LatLon::from_degrees(lat, lon).to_coord();
+	let obj = |geo, tags: &[(&str, &str)], name: Option<&str>| Object {
+		geo,
+		name: name.map(|name| name.to_string()),
+		tags: std::sync::Arc::new(tags.iter().map(|(key, value)| (key.to_string(), TagValue::Literal(value.to_string()))).collect()),
+		material: Default::default(),
+		subtiles: 0,
+		label_pos: None,
+	};
+	let ring = |lat, lon, size| vec![c(lat, lon), c(lat, lon + size), c(lat - size, lon + size), c(lat - size, lon), c(lat, lon)];
+	let objects = vec![
+		obj(Geometry::Point(c(51.5, -0.1)), &[("amenity", "cafe")], Some("Tom & Jerry's")),
+		// Two roads meeting at a junction share the node there
+		obj(Geometry::Path(vec![vec![c(51.5, -0.1), c(51.501, -0.1)]]), &[("highway", "primary")], None),
+		obj(Geometry::Path(vec![vec![c(51.501, -0.1), c(51.501, -0.101)]]), &[("highway", "residential")], None),
+		obj(Geometry::Path(vec![ring(51.51, -0.11, 0.004), ring(51.509, -0.109, 0.001)]), &[("building", "yes")], None),
+		obj(Geometry::Path(vec![ring(51.51, -0.11, 0.004)]), &[], None), // Untagged
+		obj(Geometry::Point(c(48.85, 2.35)), &[("amenity", "cafe")], None), // Off in Paris
+	];
+	let area = BoundingBox::from_corners((c(51.52, -0.12), c(51.49, -0.09)));
+	let mut out = vec![];
+	assert_eq!(write_xml(&objects, &area, &mut out).unwrap(), 4);
+	let xml = String::from_utf8(out).unwrap();
+	assert!(xml.contains("\t<node id=\"-1\" lat=\"51.500000\" lon=\"-0.100000\">\n\t\t<tag k=\"amenity\" v=\"cafe\"/>\n\t\t<tag k=\"name\" v=\"Tom &amp; Jerry&apos;s\"/>\n\t</node>"));
+	assert!(xml.contains("<bounds minlat=\"51.490000\" minlon=\"-0.120000\" maxlat=\"51.520000\" maxlon=\"-0.090000\"/>"));
+	// Three nodes for the roads and eight for the building's rings
+	assert_eq!(xml.matches("<node ").count(), 1 + 3 + 8);
+	assert_eq!(xml.matches("<way ").count(), 4);
+	assert!(xml.contains("<tag k=\"type\" v=\"multipolygon\"/>"));
+	assert!(xml.contains("role=\"outer\"") && xml.contains("role=\"inner\""));
+	assert!(!xml.contains("48.85"));
+}
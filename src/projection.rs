@@ -0,0 +1,103 @@
+use super::mapsforge::{LatLon, LAT_MAX};
+
+// Radius of the sphere the projections are on, in meters, as used by Web Mercator
+pub const EARTH_RADIUS: f64 = 6378137.0;
+
+// Great-circle distance in meters between two positions on the same sphere, by the haversine
+// formula
+pub fn distance(a: LatLon, b: LatLon) -> f64 {
+	let ((lat1, lon1), (lat2, lon2)) = (a.degrees(), b.degrees());
+	let (dlat, dlon) = ((lat2 - lat1).to_radians(), (lon2 - lon1).to_radians());
+	let h = (dlat / 2.0).sin().powi(2) + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+	2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+}
+
+// Turns positions into flat x and y coordinates in meters, with y increasing northwards, for
+// library users who want map data in something other than the viewer's Web Mercator coordinates
+pub trait Projection {
+	fn project(&self, point: LatLon) -> (f64, f64);
+	fn unproject(&self, point: (f64, f64)) -> LatLon;
+}
+
+// Longitude east of the central meridian, from -180 up to 180 degrees, so that nothing near the
+// central meridian is split across the antimeridian
+fn recenter(lon: f64, central_meridian: f64) -> f64 {
+	(lon - central_meridian + 540.0).rem_euclid(360.0) - 180.0
+}
+
+fn uncenter(lon: f64, central_meridian: f64) -> f64 {
+	recenter(lon + central_meridian, 0.0)
+}
+
+// Spherical Mercator, like the viewer's but centered on any meridian
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mercator {
+	pub central_meridian: f64, // Degrees
+}
+
+impl Projection for Mercator {
+	fn project(&self, point: LatLon) -> (f64, f64) {
+		let (lat, lon) = point.degrees();
+		let lat = lat.clamp(-LAT_MAX, LAT_MAX).to_radians();
+		(EARTH_RADIUS * recenter(lon, self.central_meridian).to_radians(), EARTH_RADIUS * (std::f64::consts::FRAC_PI_4 + lat / 2.0).tan().ln())
+	}
+
+	fn unproject(&self, point: (f64, f64)) -> LatLon {
+		let lat = (2.0 * (point.1 / EARTH_RADIUS).exp().atan() - std::f64::consts::FRAC_PI_2).to_degrees();
+		LatLon::from_degrees(lat, uncenter((point.0 / EARTH_RADIUS).to_degrees(), self.central_meridian))
+	}
+}
+
+// Plate carrée stretched so that distances are true along the standard parallel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Equirectangular {
+	pub central_meridian: f64, // Degrees
+	pub standard_parallel: f64, // Degrees
+}
+
+impl Projection for Equirectangular {
+	fn project(&self, point: LatLon) -> (f64, f64) {
+		let (lat, lon) = point.degrees();
+		let scale = self.standard_parallel.to_radians().cos();
+		(EARTH_RADIUS * recenter(lon, self.central_meridian).to_radians() * scale, EARTH_RADIUS * lat.to_radians())
+	}
+
+	fn unproject(&self, point: (f64, f64)) -> LatLon {
+		let scale = self.standard_parallel.to_radians().cos();
+		LatLon::from_degrees((point.1 / EARTH_RADIUS).to_degrees(), uncenter((point.0 / EARTH_RADIUS / scale).to_degrees(), self.central_meridian))
+	}
+}
+
+#[test]
+fn test_projections() {
+	let close = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).abs() < 1.0 && (a.1 - b.1).abs() < 1.0;
+	let quarter = EARTH_RADIUS * std::f64::consts::FRAC_PI_2; // A quarter of the way round the equator
+	let standard = Mercator { central_meridian: 0.0 };
+	let pacific = Mercator { central_meridian: 180.0 };
+	assert!(close(standard.project(LatLon::from_degrees(0.0, 90.0)), (quarter, 0.0)));
+	assert!(close(pacific.project(LatLon::from_degrees(0.0, 90.0)), (-quarter, 0.0)));
+	// Either side of the antimeridian stays together when centered on it
+	assert!(close(pacific.project(LatLon::from_degrees(0.0, 179.0)), (-quarter / 90.0, 0.0)));
+	assert!(close(pacific.project(LatLon::from_degrees(0.0, -179.0)), (quarter / 90.0, 0.0)));
+	let half = Equirectangular { central_meridian: -90.0, standard_parallel: 60.0 };
+	assert!(close(half.project(LatLon::from_degrees(45.0, 0.0)), (quarter / 2.0, quarter / 2.0)));
+	let projections: [&dyn Projection; 4] = [&standard, &pacific, &half, &Equirectangular { central_meridian: 12.5, standard_parallel: 0.0 }];
+	for projection in &projections {
+		for point in &[(0.0, 0.0), (51.5, -0.1), (-33.9, 151.2), (64.8, -147.7), (-12.0, -179.9), (80.0, 179.9)] {
+			let point = LatLon::from_degrees(point.0, point.1);
+			assert_eq!(projection.unproject(projection.project(point)), point);
+		}
+	}
+}
+
+#[test]
+fn test_distance() {
+	let quarter = EARTH_RADIUS * std::f64::consts::FRAC_PI_2;
+	assert!((distance(LatLon::from_degrees(0.0, 0.0), LatLon::from_degrees(0.0, 90.0)) - quarter).abs() < 1.0);
+	assert!((distance(LatLon::from_degrees(0.0, 0.0), LatLon::from_degrees(90.0, 0.0)) - quarter).abs() < 1.0);
+	// Across the antimeridian the short way
+	assert!((distance(LatLon::from_degrees(0.0, 179.5), LatLon::from_degrees(0.0, -179.5)) - quarter / 90.0).abs() < 1.0);
+	// London to Paris is about 344 km
+	assert!((distance(LatLon::from_degrees(51.5074, -0.1278), LatLon::from_degrees(48.8566, 2.3522)) / 1000.0 - 344.0).abs() < 2.0);
+	assert_eq!(distance(LatLon::from_degrees(12.0, 34.0), LatLon::from_degrees(12.0, 34.0)), 0.0);
+}
@@ -1,11 +1,15 @@
-use std::collections::{BTreeMap, HashMap};
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 use super::theme;
 use super::mapsforge;
 use super::mapsforge::Coord;
-use super::UpdateEvent;
+#[cfg(feature = "disk-cache")]
+use super::tilecache::DiskCache;
 
 #[derive(Debug, Clone, Copy)]
 pub struct BoundingBox {
@@ -91,76 +95,855 @@ impl BoundingBox {
 	pub fn is_empty(&self) -> bool {
 		self.max_dimension() == 0
 	}
+
+	// The same box with the given margin added on every side
+	pub fn grow(&self, dx: i64, dy: i64) -> Self {
+		if self.empty { *self }
+		else { Self { empty: false, min: Coord { x: self.min.x - dx, y: self.min.y - dy }, max: Coord { x: self.max.x + dx, y: self.max.y + dy } } }
+	}
 }
 
+// Where the line from a to b crosses the vertical line at x, or the horizontal line at y if
+// vertical is false
+fn cross(a: Coord, b: Coord, at: i64, vertical: bool) -> Coord {
+	let (a0, a1, b0, b1) = if vertical { (a.x, a.y, b.x, b.y) } else { (a.y, a.x, b.y, b.x) };
+	let along = a1 + ((b1 - a1) as f64 * (at - a0) as f64 / (b0 - a0) as f64).round() as i64;
+	if vertical { Coord { x: at, y: along } } else { Coord { x: along, y: at } }
+}
+
+// Whether a point is on the inside of one side of the clip box, where that side is, and whether
+// it's upright
+type ClipSide<'a> = (&'a dyn Fn(Coord) -> bool, i64, bool);
+
+// Sutherland-Hodgman: cut a closed ring down to the part inside the box, one side at a time
+fn clip_polygon(ring: &[Coord], bounds: &BoundingBox) -> Vec<Coord> {
+	let sides: [ClipSide; 4] = [
+		(&|p: Coord| p.x >= bounds.min.x, bounds.min.x, true),
+		(&|p: Coord| p.x <= bounds.max.x, bounds.max.x, true),
+		(&|p: Coord| p.y >= bounds.min.y, bounds.min.y, false),
+		(&|p: Coord| p.y <= bounds.max.y, bounds.max.y, false),
+	];
+	let mut ret = ring.to_vec();
+	for (inside, at, vertical) in &sides {
+		let input = std::mem::take(&mut ret);
+		for (i, cur) in input.iter().enumerate() {
+			let prev = input[(i + input.len() - 1) % input.len()];
+			match (inside(prev), inside(*cur)) {
+				(true, true) => ret.push(*cur),
+				(true, false) => ret.push(cross(prev, *cur, *at, *vertical)),
+				(false, true) => { ret.push(cross(prev, *cur, *at, *vertical)); ret.push(*cur); },
+				(false, false) => (),
+			}
+		}
+	}
+	ret
+}
+
+// Liang-Barsky: the parts of an open line that lie inside the box, which may be several if it
+// wanders in and out
+fn clip_polyline(line: &[Coord], bounds: &BoundingBox) -> Vec<Vec<Coord>> {
+	let mut ret: Vec<Vec<Coord>> = vec![];
+	for segment in line.windows(2) {
+		let (a, b) = (segment[0], segment[1]);
+		let (dx, dy) = ((b.x - a.x) as f64, (b.y - a.y) as f64);
+		let (mut t0, mut t1) = (0.0, 1.0);
+		let edges = [(-dx, (a.x - bounds.min.x) as f64), (dx, (bounds.max.x - a.x) as f64), (-dy, (a.y - bounds.min.y) as f64), (dy, (bounds.max.y - a.y) as f64)];
+		let visible = edges.iter().all(|(p, q)| {
+			if *p == 0.0 { return *q >= 0.0; }
+			let t = q / p;
+			if *p < 0.0 { t0 = f64::max(t0, t); } else { t1 = f64::min(t1, t); }
+			t0 <= t1
+		});
+		if !visible { continue; }
+		let at = |t: f64| if t == 0.0 { a } else if t == 1.0 { b } else { Coord { x: a.x + (dx * t).round() as i64, y: a.y + (dy * t).round() as i64 } };
+		let (start, end) = (at(t0), at(t1));
+		match ret.last_mut() {
+			Some(run) if t0 == 0.0 && run.last() == Some(&a) => run.push(end),
+			_ => ret.push(vec![start, end]),
+		}
+	}
+	ret
+}
+
+// Join lines that share endpoints into closed rings, as for boundaries that are split into many
+// ways.  Lines that can't be closed up are left out.
+fn assemble_rings(mut lines: Vec<Vec<Coord>>) -> Vec<Vec<Coord>> {
+	let mut rings = vec![];
+	while let Some(mut ring) = lines.pop() {
+		loop {
+			if ring.len() > 2 && ring.first() == ring.last() {
+				rings.push(ring);
+				break;
+			}
+			let end = *ring.last().unwrap();
+			match lines.iter().position(|line| line.first() == Some(&end) || line.last() == Some(&end)) {
+				Some(i) => {
+					let mut next = lines.swap_remove(i);
+					if next.first() != Some(&end) { next.reverse(); }
+					ring.extend(next.into_iter().skip(1));
+				},
+				None => break,
+			}
+		}
+	}
+	rings
+}
+
+// Cut way geometry down to the given box.  An area's rings are cut as polygons, so they stay closed
+// with new edges along the box, while lines are cut into pieces even if they end where they start.
+fn clip_paths(paths: Vec<Vec<Coord>>, bounds: &BoundingBox, area: bool) -> Vec<Vec<Coord>> {
+	let mut ret = vec![];
+	for path in paths {
+		if area && path.len() > 2 {
+			let open = if path.first() == path.last() { &path[..path.len() - 1] } else { &path[..] };
+			let mut ring = clip_polygon(open, bounds);
+			if ring.len() > 2 {
+				ring.push(ring[0]);
+				ret.push(ring);
+			}
+		}
+		else {
+			ret.extend(clip_polyline(&path, bounds));
+		}
+	}
+	ret
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "disk-cache", derive(serde::Serialize, serde::Deserialize))]
 pub enum Geometry {
 	Path(Vec<Vec<Coord>>),
 	Point(Coord),
+	Area(Vec<Vec<Coord>>), // Closed rings to fill, the first the outline and any others holes in it
+}
+
+// Distance from p to the nearest point on the segment from a to b
+fn segment_distance(p: Coord, a: Coord, b: Coord) -> f64 {
+	let (dx, dy) = ((b.x - a.x) as f64, (b.y - a.y) as f64);
+	let (px, py) = ((p.x - a.x) as f64, (p.y - a.y) as f64);
+	let length = dx * dx + dy * dy;
+	let t = if length == 0.0 { 0.0 } else { ((px * dx + py * dy) / length).clamp(0.0, 1.0) };
+	(px - t * dx).hypot(py - t * dy)
 }
 
+impl Geometry {
+	// Distance in map units from the point to the nearest part of the geometry.  Areas count by
+	// their outlines, so being inside one doesn't make it any closer.
+	pub fn distance(&self, point: Coord) -> f64 {
+		match self {
+			Geometry::Point(p) => segment_distance(point, *p, *p),
+			Geometry::Path(polies) | Geometry::Area(polies) => polies.iter()
+				.flat_map(|poly| poly.windows(2))
+				.map(|segment| segment_distance(point, segment[0], segment[1]))
+				.fold(f64::INFINITY, f64::min),
+		}
+	}
+
+	fn translate(&mut self, dx: i64) {
+		match self {
+			Geometry::Point(point) => point.x += dx,
+			Geometry::Path(polies) | Geometry::Area(polies) => {
+				for point in polies.iter_mut().flatten() { point.x += dx; }
+			},
+		}
+	}
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "disk-cache", derive(serde::Serialize, serde::Deserialize))]
 pub struct Object {
 	pub geo: Geometry,
 	pub name: Option<String>,
+	#[cfg_attr(feature = "disk-cache", serde(with = "shared_tags"))]
+	pub tags: mapsforge::Tags, // Kept for searching, and shared with the map feature and its other objects
 	pub material: theme::Material,
+	pub subtiles: u16, // The map's sub-tile bitmap for ways, as described at subtile_bits, or 0 if there isn't one
+	pub label_pos: Option<Coord>, // Where a way's label goes, if this object labels it
+}
+
+impl Object {
+	// Size of the object's bounding box in map units, as a rough measure of how much it matters
+	fn size(&self) -> i64 {
+		match &self.geo {
+			Geometry::Point(_) => 0,
+			Geometry::Path(polies) | Geometry::Area(polies) => {
+				let mut bounds = BoundingBox::empty();
+				for point in polies.iter().flatten() { bounds.include(*point); }
+				bounds.max_dimension()
+			},
+		}
+	}
+}
+
+// Serde only handles Arc with its rc feature, so the disk cache writes the tags themselves.  Objects
+// read back from it each get their own copy.
+#[cfg(feature = "disk-cache")]
+mod shared_tags {
+	use std::collections::HashMap;
+	use std::sync::Arc;
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+	use super::mapsforge::{TagValue, Tags};
+
+	pub fn serialize<S: Serializer>(tags: &Tags, serializer: S) -> Result<S::Ok, S::Error> {
+		tags.as_ref().serialize(serializer)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Tags, D::Error> {
+		HashMap::<String, TagValue>::deserialize(deserializer).map(Arc::new)
+	}
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "disk-cache", derive(serde::Serialize, serde::Deserialize))]
 pub struct RenderTile {
 	pub zoom: u8,
 	pub x: i64,
 	pub y: i64,
 	pub layers: BTreeMap<i8, Vec<Object>>,
+	pub truncated: bool, // Some objects were dropped because the tile was too dense
+	pub pois: Vec<Coord>, // Every POI in the tile, whether or not the theme draws it
+	pub heights: Vec<(Geometry, f64)>, // Contour lines and spot heights, with their elevations in meters
+	pub covered: bool, // Inside the map's bounds, as opposed to off the edge where there's no data at all
+	pub grid: TileGrid,
+	// Which map the tile is from, by position on the command line.  Later maps are drawn over
+	// earlier ones.  It's filled in when the tile is delivered, since the order can change between
+	// sessions sharing a disk cache.
+	#[cfg_attr(feature = "disk-cache", serde(skip))]
+	pub map: usize,
+}
+
+// Keep at most limit objects in each layer, preferring the largest ones and otherwise keeping
+// them in their original order.  Returns whether anything was dropped.
+fn limit_density(layers: &mut BTreeMap<i8, Vec<Object>>, limit: usize) -> bool {
+	let mut truncated = false;
+	for objs in layers.values_mut() {
+		if objs.len() <= limit { continue; }
+		truncated = true;
+		let mut by_size = objs.iter().enumerate().map(|(i, obj)| (obj.size(), i)).collect::<Vec<_>>();
+		by_size.sort_by_key(|(size, i)| (std::cmp::Reverse(*size), *i));
+		let mut keep = vec![false; objs.len()];
+		for (_, i) in &by_size[..limit] { keep[*i] = true; }
+		let mut keep = keep.into_iter();
+		objs.retain(|_| keep.next().unwrap());
+	}
+	truncated
 }
 
 impl RenderTile {
-	fn new(tile: mapsforge::Tile, zoom: u8, x: i64, y: i64, theme: &theme::Theme) -> Self {
+	// Names are given in the language if they have it
+	fn new(tile: mapsforge::Tile, zoom: u8, x: i64, y: i64, theme: &theme::Theme, density_limit: Option<usize>, lang: Option<&str>) -> Self {
 		let mut layers = BTreeMap::new();
-		for way in &tile.ways {
-			if let Some(material) = theme.match_way(&way) {
-				for block in way.project(&tile) {
-					let geo = Geometry::Path(block);
-					layers.entry(way.layer).or_insert(vec![]).push(Object { geo, name: way.name.clone(), material: material.clone() });
+		// Long ways like coastlines can reach far outside the tile, so only the part inside is
+		// kept.  The margin keeps the edges of strokes that are clipped off from showing.
+		let (width, height) = TileGrid::MERCATOR.tile_size(zoom);
+		let clip = TileGrid::MERCATOR.bounds(zoom, x, y).grow(width / 16, height / 16);
+		// Open sea has no coastline to fill in from, so tiles that are all water are filled from
+		// corner to corner under everything else
+		if tile.water {
+			if let Some(material) = theme.water_fill(zoom) {
+				let (min, max) = TileGrid::MERCATOR.bounds(zoom, x, y).corners().unwrap();
+				let ring = vec![min, Coord { x: max.x, y: min.y }, max, Coord { x: min.x, y: max.y }, min];
+				layers.entry(WATER_LAYER).or_insert(vec![]).push(Object { geo: Geometry::Area(vec![ring]), name: None, tags: Default::default(), material, subtiles: 0, label_pos: None });
+			}
+		}
+		let ways = tile.project_ways();
+		let bounds = TileGrid::MERCATOR.bounds(zoom, x, y);
+		for (way, blocks) in tile.ways.iter().zip(&ways) {
+			if let Some(material) = theme.match_way(way, zoom) {
+				let label_pos = way.project_label(&tile).or_else(|| midpoint_label(blocks, &bounds));
+				let area = way.is_area();
+				for block in blocks {
+					let block = clip_paths(block.clone(), &clip, area);
+					if block.is_empty() { continue; }
+					let geo = if area { Geometry::Area(block) } else { Geometry::Path(block) };
+					layers.entry(way.layer).or_insert(vec![]).push(Object { geo, name: way.name(lang).map(str::to_string), tags: way.tags.clone(), material: material.clone(), subtiles: way.subtile_map(), label_pos });
+				}
+			}
+		}
+		// Boundaries are stored as lines, often many to an area, so to tint the areas inside them
+		// the lines are joined up into rings level by level.  Only rings that close within the
+		// tile can be filled.
+		if zoom <= ADMIN_FILL_MAX_ZOOM {
+			let mut boundaries = BTreeMap::new();
+			for (way, blocks) in tile.ways.iter().zip(&ways).filter(|(way, _)| way.tags.get("boundary") == Some(&mapsforge::TagValue::Literal("administrative".to_string()))) {
+				if let Some(level) = way.tags.get("admin_level").and_then(|level| level.number()) {
+					boundaries.entry((way.layer, level as i64)).or_insert(vec![]).extend(blocks.iter().flatten().cloned());
+				}
+			}
+			for ((layer, level), lines) in boundaries {
+				let material = match theme.admin_fill(level) {
+					Some(material) => material,
+					None => continue,
+				};
+				for ring in clip_paths(assemble_rings(lines), &clip, true) {
+					let geo = Geometry::Area(vec![ring]);
+					layers.entry(layer).or_insert(vec![]).push(Object { geo, name: None, tags: Default::default(), material: material.clone(), subtiles: 0, label_pos: None });
 				}
 			}
 		}
-		for poi in &tile.pois {
-			if let Some(material) = theme.match_poi(&poi) {
-				let geo = Geometry::Point(poi.project(&tile));
-				layers.entry(poi.layer).or_insert(vec![]).push(Object { geo, name: poi.name.clone(), material: material.clone() });
+		let pois = tile.project_pois();
+		for (poi, point) in tile.pois.iter().zip(&pois) {
+			if let Some(material) = theme.match_poi(poi, zoom) {
+				let geo = Geometry::Point(*point);
+				layers.entry(poi.layer).or_insert(vec![]).push(Object { geo, name: poi.name(lang).map(str::to_string), tags: poi.tags.clone(), material: material.clone(), subtiles: 0, label_pos: None });
+			}
+		}
+		// Contours are kept whether or not they're drawn, and only ever as lines, since clipping
+		// a closed one as an area would add edges along the clip box
+		let mut heights = vec![];
+		for (way, blocks) in tile.ways.iter().zip(&ways) {
+			if let Some(ele) = way.tags.get("ele").and_then(|ele| ele.number()) {
+				let lines = blocks.iter().flatten().flat_map(|line| clip_polyline(line, &clip)).collect::<Vec<_>>();
+				if !lines.is_empty() { heights.push((Geometry::Path(lines), ele)); }
+			}
+		}
+		for (poi, point) in tile.pois.iter().zip(&pois) {
+			if let Some(ele) = poi.elevation.map(|ele| ele as f64).or_else(|| poi.tags.get("ele").and_then(|ele| ele.number())) {
+				heights.push((Geometry::Point(*point), ele));
+			}
+		}
+		// Stable, so objects of the same material stay in map order
+		for objs in layers.values_mut() { objs.sort_by_key(|obj| obj.material.draw_rank()); }
+		let truncated = density_limit.is_some_and(|limit| limit_density(&mut layers, limit));
+		Self { zoom, x, y, layers, truncated, pois, heights, covered: true, grid: TileGrid::MERCATOR, map: 0 }
+	}
+
+	// Every feature of both tiles, colored by whether it appears in only the old tile, only the
+	// new one, or both
+	fn diff(old: mapsforge::Tile, new: mapsforge::Tile, zoom: u8, x: i64, y: i64, theme: &theme::Theme) -> Self {
+		let material = |name| theme.drawn_material(name).expect("Diff theme is missing a material");
+		let (added, removed, unchanged) = (material("added"), material("removed"), material("unchanged"));
+		let old_features = Feature::all(&old);
+		let new_features = Feature::all(&new);
+		// Count identical features so that duplicates within a tile are matched up one for one
+		let count = |features: &[Feature]| {
+			let mut ret = HashMap::new();
+			for feature in features { *ret.entry(feature.key).or_insert(0) += 1; }
+			ret
+		};
+		let mut old_counts = count(&old_features);
+		let mut new_counts = count(&new_features);
+		let mut layers = BTreeMap::new();
+		for feature in old_features {
+			let material = match new_counts.get_mut(&feature.key) {
+				Some(n) if *n > 0 => { *n -= 1; unchanged.clone() },
+				_ => removed.clone(),
+			};
+			layers.entry(feature.layer).or_insert(vec![]).push(Object { geo: feature.geo, name: feature.name, tags: feature.tags, material, subtiles: feature.subtiles, label_pos: feature.label_pos });
+		}
+		for feature in new_features {
+			match old_counts.get_mut(&feature.key) {
+				Some(n) if *n > 0 => *n -= 1, // Already drawn as unchanged
+				_ => layers.entry(feature.layer).or_insert(vec![]).push(Object { geo: feature.geo, name: feature.name, tags: feature.tags, material: added.clone(), subtiles: feature.subtiles, label_pos: feature.label_pos }),
 			}
 		}
-		Self { zoom, x, y, layers }
+		Self { zoom, x, y, layers, truncated: false, pois: vec![], heights: vec![], covered: true, grid: TileGrid::MERCATOR, map: 0 }
+	}
+
+	// Compare a tile of the first map with the same tile of the second.  If the second map can't
+	// give us that tile, whether it's unreadable or the map has no data at this zoom, there's
+	// nothing to compare against and the caller shows neither.
+	fn diff_with(old: mapsforge::Tile, other: &mapsforge::MapFile, zoom: u8, x: u32, y: u32, deg_lon_per_px: f64, theme: &theme::Theme) -> Result<Self, String> {
+		if other.desired_zoom_level(deg_lon_per_px) != Some(zoom) {
+			return Err(format!("{}: no zoom {} data to compare with", other.path().display(), zoom));
+		}
+		let new = other.stored_tile(zoom, x, y).map_err(|e| format!("{}: zoom {} tile {:?}: {}", other.path().display(), zoom, (x, y), e))?;
+		Ok(Self::diff(old, new, zoom, x as i64, y as i64, theme))
 	}
 
 	fn empty(zoom: u8, x: i64, y: i64) -> Self {
-		Self { zoom, x, y, layers: BTreeMap::new() }
+		Self { zoom, x, y, layers: BTreeMap::new(), truncated: false, pois: vec![], heights: vec![], covered: false, grid: TileGrid::MERCATOR, map: 0 }
 	}
 
 	pub fn bounds(&self) -> BoundingBox {
-		let tile_size = mapsforge::COORD_MAX >> self.zoom;
+		self.grid.bounds(self.zoom, self.x, self.y)
+	}
+
+	// Whether the tiles cover any of the same area, not counting edges that just touch
+	pub fn overlaps(&self, other: &RenderTile) -> bool {
+		let overlap = self.bounds().intersection(&other.bounds());
+		overlap.width() > 0 && overlap.height() > 0
+	}
+
+	// Whether tiles from a single request, which don't overlap each other, cover all of this one
+	pub fn hidden_by<'a>(&self, others: impl IntoIterator<Item = &'a RenderTile>) -> bool {
+		let bounds = self.bounds();
+		let area = |b: &BoundingBox| b.width() as i128 * b.height() as i128;
+		let covered = others.into_iter().map(|other| area(&other.bounds().intersection(&bounds))).sum::<i128>();
+		covered >= area(&bounds)
+	}
+
+	// A copy of the tile moved to column x, which is some number of times around the world from
+	// its own, for showing it again on the far side of the antimeridian
+	fn wrapped(&self, x: i64) -> Self {
+		let dx = (x - self.x) * self.grid.tile_size(self.zoom).0;
+		let mut ret = Self { x, ..self.clone() };
+		for obj in ret.layers.values_mut().flatten() {
+			obj.geo.translate(dx);
+			if let Some(pos) = &mut obj.label_pos { pos.x += dx; }
+		}
+		for poi in &mut ret.pois { poi.x += dx; }
+		for (geo, _) in &mut ret.heights { geo.translate(dx); }
+		ret
+	}
+}
+
+// Where to label a way the map doesn't give a label position for: the middle of the whole way,
+// which only the tile it falls in gets, so that a way crossing many tiles is labeled once
+fn midpoint_label(blocks: &[Vec<Vec<Coord>>], tile: &BoundingBox) -> Option<Coord> {
+	let mut bounds = BoundingBox::empty();
+	for point in blocks.iter().flatten().flatten() { bounds.include(*point); }
+	let mid = bounds.midpoint()?;
+	let (min, max) = tile.corners()?;
+	// Half-open, so a midpoint on the edge between two tiles goes to only one of them
+	(min.x <= mid.x && mid.x < max.x && min.y <= mid.y && mid.y < max.y).then_some(mid)
+}
+
+// The bounds of one of the 4×4 sub-tiles of a tile, by column and row
+pub fn subtile_bounds(bounds: &BoundingBox, col: i64, row: i64) -> BoundingBox {
+	let (min, _) = bounds.corners().unwrap();
+	let (width, height) = (bounds.width() / 4, bounds.height() / 4);
+	BoundingBox::from_corners((
+		Coord { x: min.x + width * col, y: min.y + height * row },
+		Coord { x: min.x + width * (col + 1), y: min.y + height * (row + 1) },
+	))
+}
+
+// Which of the 4×4 sub-tiles of the bounds the geometry passes through, laid out like a Mapsforge
+// way's sub-tile bitmap: one bit per sub-tile, row by row from the top left, which is the highest
+// bit.  Areas count by their outlines, so one that covers a sub-tile without crossing it doesn't
+// mark it.
+pub fn subtile_bits(geo: &Geometry, bounds: &BoundingBox) -> u16 {
+	let mut bits = 0;
+	for row in 0..4 {
+		for col in 0..4 {
+			let cell = subtile_bounds(bounds, col, row);
+			let touched = match geo {
+				Geometry::Point(point) => {
+					let (min, max) = cell.corners().unwrap();
+					(min.x..=max.x).contains(&point.x) && (min.y..=max.y).contains(&point.y)
+				},
+				Geometry::Path(polies) | Geometry::Area(polies) => polies.iter().any(|poly| !clip_polyline(poly, &cell).is_empty()),
+			};
+			if touched { bits |= mapsforge::subtile_bit(col as u8, row as u8); }
+		}
+	}
+	bits
+}
+
+// The sub-tiles of a tile that overlap an area, as a bitmap like a way's
+pub fn subtiles_overlapping(bounds: &BoundingBox, area: &BoundingBox) -> u16 {
+	let mut bits = 0;
+	for row in 0..4 {
+		for col in 0..4 {
+			if !subtile_bounds(bounds, col, row).intersection(area).is_empty() { bits |= mapsforge::subtile_bit(col as u8, row as u8); }
+		}
+	}
+	bits
+}
+
+// Estimate the elevation at a point from the contour lines and spot heights around it.  Between
+// contours, this interpolates between the nearest feature and the nearest one at a different
+// elevation, weighted by how close each is.  None if there's nothing to go on.
+pub fn elevation_at<'a>(tiles: impl IntoIterator<Item = &'a RenderTile>, point: Coord) -> Option<f64> {
+	let mut heights = tiles.into_iter().flat_map(|tile| &tile.heights).map(|(geo, ele)| (geo.distance(point), *ele)).collect::<Vec<_>>();
+	heights.sort_by(|a, b| a.0.total_cmp(&b.0));
+	let (near_dist, near_ele) = *heights.first()?;
+	match heights.iter().find(|(_, ele)| *ele != near_ele) {
+		Some((far_dist, far_ele)) if near_dist > 0.0 => Some((near_ele * far_dist + far_ele * near_dist) / (near_dist + far_dist)),
+		_ => Some(near_ele),
+	}
+}
+
+// How map coordinates are divided into tiles.  The single tile at zoom 0 covers the given extent
+// and each zoom level in splits every tile into four, but tiles need not be square.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "disk-cache", derive(serde::Serialize, serde::Deserialize))]
+pub struct TileGrid {
+	pub width: i64,
+	pub height: i64,
+}
+
+impl TileGrid {
+	// Map coordinates are Web Mercator, so these line up with the usual slippy map tiles.  This
+	// is the only projection Mapsforge supports.
+	pub const MERCATOR: Self = Self { width: mapsforge::COORD_MAX, height: mapsforge::COORD_MAX };
+
+	pub fn tile_size(&self, zoom: u8) -> (i64, i64) {
+		(self.width >> zoom, self.height >> zoom)
+	}
+
+	pub fn bounds(&self, zoom: u8, x: i64, y: i64) -> BoundingBox {
+		let (width, height) = self.tile_size(zoom);
 		BoundingBox::from_corners((
-			Coord { x: tile_size * self.x, y: tile_size * self.y },
-			Coord { x: tile_size * (self.x + 1), y: tile_size * (self.y + 1) },
+			Coord { x: width * x, y: height * y },
+			Coord { x: width * (x + 1), y: height * (y + 1) },
 		))
 	}
+
+	// The inclusive ranges of tile x and y indices that intersect the viewport.  The map goes on
+	// around the world east and west, so x can be outside the grid, and wrap_x says which tile it
+	// is really.
+	pub fn visible_tiles(&self, viewport: &BoundingBox, zoom: u8) -> ((i64, i64), (i64, i64)) {
+		let (width, height) = self.tile_size(zoom);
+		let (min, max) = viewport.corners().unwrap();
+		((min.x.div_floor(width), max.x.div_floor(width)), (min.y.div_floor(height), max.y.div_floor(height)))
+	}
+
+	// The visible tiles, nearest the middle of the viewport first since that's where people look
+	pub fn tiles_from_center(&self, viewport: &BoundingBox, zoom: u8) -> Vec<(i64, i64)> {
+		let (xrange, yrange) = self.visible_tiles(viewport, zoom);
+		let center = viewport.midpoint().unwrap();
+		let mut ret = (yrange.0..=yrange.1).flat_map(|y| (xrange.0..=xrange.1).map(move |x| (x, y))).collect::<Vec<_>>();
+		ret.sort_by_key(|&(x, y)| {
+			let mid = self.bounds(zoom, x, y).midpoint().unwrap();
+			((mid.x - center.x) as i128).pow(2) + ((mid.y - center.y) as i128).pow(2)
+		});
+		ret
+	}
+
+	pub fn wrap_x(&self, zoom: u8, x: i64) -> i64 {
+		x.rem_euclid(1 << zoom)
+	}
+}
+
+// A feature from a map tile, along with a hash of everything about it that can be compared
+// between maps
+struct Feature {
+	key: u64,
+	layer: i8,
+	name: Option<String>,
+	tags: mapsforge::Tags,
+	geo: Geometry,
+	subtiles: u16,
+	label_pos: Option<Coord>,
+}
+
+impl Feature {
+	fn all(tile: &mapsforge::Tile) -> Vec<Self> {
+		let mut ret = vec![];
+		let bounds = TileGrid::MERCATOR.bounds(tile.zoom, tile.index.0 as i64, tile.index.1 as i64);
+		for (way, blocks) in tile.ways.iter().zip(tile.project_ways()) {
+			let label_pos = way.project_label(tile).or_else(|| midpoint_label(&blocks, &bounds));
+			let area = way.is_area();
+			let mut hasher = DefaultHasher::new();
+			hash_tags(&way.tags, &mut hasher);
+			(way.layer, way.name(None), &blocks).hash(&mut hasher);
+			let key = hasher.finish();
+			for block in blocks {
+				let geo = if area { Geometry::Area(block) } else { Geometry::Path(block) };
+				ret.push(Self { key, layer: way.layer, name: way.name(None).map(str::to_string), tags: way.tags.clone(), geo, subtiles: way.subtile_map(), label_pos });
+			}
+		}
+		for (poi, point) in tile.pois.iter().zip(tile.project_pois()) {
+			let mut hasher = DefaultHasher::new();
+			hash_tags(&poi.tags, &mut hasher);
+			(poi.layer, poi.name(None), point).hash(&mut hasher);
+			ret.push(Self { key: hasher.finish(), layer: poi.layer, name: poi.name(None).map(str::to_string), tags: poi.tags.clone(), geo: Geometry::Point(point), subtiles: 0, label_pos: None });
+		}
+		ret
+	}
+}
+
+fn hash_tags(tags: &HashMap<String, mapsforge::TagValue>, hasher: &mut DefaultHasher) {
+	use mapsforge::TagValue;
+	let mut sorted = tags.iter().collect::<Vec<_>>();
+	sorted.sort_by_key(|(tag, _)| *tag);
+	for (tag, value) in sorted {
+		tag.hash(hasher);
+		match value {
+			TagValue::Literal(x) => (0, x).hash(hasher),
+			TagValue::Byte(x) => (1, x).hash(hasher),
+			TagValue::Short(x) => (2, x).hash(hasher),
+			TagValue::Int(x) => (3, x).hash(hasher),
+			TagValue::Float(x) => (4, x.to_bits()).hash(hasher),
+			TagValue::String(x) => (5, x).hash(hasher),
+		}
+	}
+}
+
+// Receives tiles as they finish rendering on the background threads, tagged with the generation
+// of the request that produced them
+pub trait TileSink: Clone + Send + 'static {
+	fn tile_ready(&self, generation: u64, tile: Arc<RenderTile>);
+}
+
+impl TileSink for mpsc::Sender<Arc<RenderTile>> {
+	fn tile_ready(&self, _generation: u64, tile: Arc<RenderTile>) {
+		let _ = self.send(tile);
+	}
+}
+
+// What tiles are cached on disk under, besides the map.  Tiles in other languages or with another
+// density limit are kept apart as if they had another theme.
+#[cfg(feature = "disk-cache")]
+fn cache_style(theme: &theme::Theme, lang: Option<&str>, density_limit: Option<usize>) -> String {
+	let limit = density_limit.map_or("all".to_string(), |limit| limit.to_string());
+	format!("{}:{}:{}", theme.name(), lang.unwrap_or(""), limit)
 }
 
-fn visible_tiles(viewport: &BoundingBox, zoom: u8) -> ((i64, i64), (i64, i64)) {
-	let tileidx = |coord: i64| coord.div_floor(mapsforge::COORD_MAX >> zoom);
-	let (min, max) = viewport.corners().unwrap();
-	((tileidx(min.x), tileidx(max.x)), (tileidx(min.y), tileidx(max.y)))
+// Generation of the latest request and how many of its tiles haven't been delivered yet
+type Loading = Arc<Mutex<(u64, usize)>>;
+
+// Counts a job as in flight for as long as it's held, however the job exits.  It also counts
+// toward the tiles still loading, unless a newer request has started the count over by then.
+struct PendingJob {
+	pending: Arc<AtomicUsize>,
+	loading: Loading,
+	generation: u64,
+}
+
+impl PendingJob {
+	fn new(pending: Arc<AtomicUsize>, loading: Loading, generation: u64) -> Self {
+		pending.fetch_add(1, Ordering::SeqCst);
+		let mut count = loading.lock().expect("Poisoned lock");
+		if count.0 == generation { count.1 += 1; }
+		drop(count);
+		Self { pending, loading, generation }
+	}
+}
+
+impl Drop for PendingJob {
+	fn drop(&mut self) {
+		self.pending.fetch_sub(1, Ordering::SeqCst);
+		let mut count = self.loading.lock().expect("Poisoned lock");
+		if count.0 == self.generation { count.1 -= 1; }
+	}
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct QueuedJob {
+	priority: (u64, Reverse<u64>), // Newest generation first, then in the order they were queued
+	job: Job,
+}
+
+impl PartialEq for QueuedJob {
+	fn eq(&self, other: &Self) -> bool { self.priority == other.priority }
+}
+
+impl Eq for QueuedJob { }
+
+impl PartialOrd for QueuedJob {
+	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for QueuedJob {
+	fn cmp(&self, other: &Self) -> std::cmp::Ordering { self.priority.cmp(&other.priority) }
+}
+
+// Rayon runs spawned jobs in roughly the order they were spawned, so after a quick pan the
+// tiles for the current view wait behind a backlog of stale ones.  Instead, each spawn just
+// takes whichever queued job has the newest generation when it gets to run.
+struct JobQueue {
+	jobs: Mutex<BinaryHeap<QueuedJob>>,
+	next_seq: AtomicU64,
+}
+
+impl JobQueue {
+	fn new() -> Self {
+		Self { jobs: Mutex::new(BinaryHeap::new()), next_seq: AtomicU64::new(0) }
+	}
+
+	fn push(&self, generation: u64, job: Job) {
+		let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+		self.jobs.lock().expect("Poisoned lock").push(QueuedJob { priority: (generation, Reverse(seq)), job });
+	}
+
+	fn pop(&self) -> Option<Job> {
+		self.jobs.lock().expect("Poisoned lock").pop().map(|queued| queued.job)
+	}
+}
+
+// Enough for any reasonable tile while keeping pathological ones from stalling the render threads
+pub const DEFAULT_DENSITY_LIMIT: usize = 20000;
+
+// Administrative areas are only filled in up to this zoom.  Beyond it, they're too big to be
+// much use and their boundaries are less likely to close up within one tile.
+pub const ADMIN_FILL_MAX_ZOOM: u8 = 10;
+
+// The bottom layer a map can put anything in, which is where tiles of open water are filled
+const WATER_LAYER: i8 = -5;
+
+// Built tiles kept in memory when there's no limit given
+pub const DEFAULT_CACHE_LIMIT: usize = 1000;
+
+type TileKey = (usize, u8, u32, u32); // Map index, zoom, x, y
+
+// Built tiles, dropping the least recently used ones once there are more than the limit
+struct TileCache {
+	tiles: HashMap<TileKey, (Arc<RenderTile>, u64)>, // With when each was last used
+	by_use: BTreeMap<u64, TileKey>,
+	clock: u64,
+	limit: usize,
+	epoch: u64, // Goes up whenever everything is thrown away, so jobs started before then don't put old tiles back
+}
+
+impl TileCache {
+	fn new(limit: usize) -> Self {
+		Self { tiles: HashMap::new(), by_use: BTreeMap::new(), clock: 0, limit, epoch: 0 }
+	}
+
+	fn touch(&mut self, key: TileKey) -> Option<Arc<RenderTile>> {
+		let (tile, used) = self.tiles.get_mut(&key)?;
+		self.by_use.remove(used);
+		self.clock += 1;
+		*used = self.clock;
+		self.by_use.insert(self.clock, key);
+		Some(tile.clone())
+	}
+
+	fn insert(&mut self, key: TileKey, tile: Arc<RenderTile>, epoch: u64) {
+		if epoch != self.epoch { return; }
+		self.clock += 1;
+		if let Some((_, used)) = self.tiles.insert(key, (tile, self.clock)) { self.by_use.remove(&used); }
+		self.by_use.insert(self.clock, key);
+		while self.tiles.len() > self.limit {
+			let (&used, &oldest) = self.by_use.iter().next().unwrap();
+			self.by_use.remove(&used);
+			self.tiles.remove(&oldest);
+		}
+	}
+
+	fn clear(&mut self) {
+		self.tiles.clear();
+		self.by_use.clear();
+		self.epoch += 1;
+	}
+
+	fn len(&self) -> usize {
+		self.tiles.len()
+	}
+
+	// Drop the tiles nothing outside the cache is using
+	fn trim(&mut self) {
+		let by_use = &mut self.by_use;
+		self.tiles.retain(|_, (tile, used)| {
+			let keep = Arc::strong_count(tile) > 1;
+			if !keep { by_use.remove(used); }
+			keep
+		});
+	}
 }
 
 pub struct RenderManager {
 	pub maps: Vec<Arc<mapsforge::MapFile>>,
 	theme: Arc<theme::Theme>,
-	tiles: HashMap<(PathBuf, u8), Arc<Mutex<HashMap<(u32, u32), Arc<RenderTile>>>>>,
+	tiles: Arc<Mutex<TileCache>>,
+	diff: bool, // Compare the first two maps instead of drawing them normally
+	density_limit: Option<usize>, // Most objects to draw in each layer of a tile
+	prefault: bool, // Have the OS start reading each request's tiles from mapped maps before they're built
+	lang: Option<String>, // Language to name things in, if not each map's own
 	cur_generation: Arc<AtomicU64>,
+	pending: Arc<AtomicUsize>, // Jobs spawned on the pool that haven't finished yet
+	loading: Loading,
+	error: Arc<Mutex<Option<String>>>, // Latest problem reading a map, until the viewer takes it to show
+	queue: Arc<JobQueue>,
+	#[cfg(feature = "disk-cache")]
+	disk_cache: Option<Arc<DiskCache>>,
 	render_threads: rayon::ThreadPool,
 }
 
 impl RenderManager {
 	pub fn new(maps: Vec<Arc<mapsforge::MapFile>>) -> Self {
-		Self { maps, theme: Arc::new(theme::basic()), tiles: HashMap::new(), cur_generation: Arc::new(AtomicU64::new(0)), render_threads: rayon::ThreadPoolBuilder::new().build().unwrap() }
+		Self::with_cache_limit(maps, DEFAULT_CACHE_LIMIT)
+	}
+
+	// Keep at most limit built tiles in memory
+	pub fn with_cache_limit(maps: Vec<Arc<mapsforge::MapFile>>, limit: usize) -> Self {
+		Self { maps, theme: Arc::new(theme::basic()), tiles: Arc::new(Mutex::new(TileCache::new(limit))), diff: false, density_limit: Some(DEFAULT_DENSITY_LIMIT), prefault: false, lang: None, cur_generation: Arc::new(AtomicU64::new(0)), pending: Arc::new(AtomicUsize::new(0)), loading: Arc::new(Mutex::new((0, 0))), error: Arc::new(Mutex::new(None)), queue: Arc::new(JobQueue::new()), #[cfg(feature = "disk-cache")] disk_cache: None, render_threads: rayon::ThreadPoolBuilder::new().build().unwrap() }
+	}
+
+	pub fn theme(&self) -> &theme::Theme {
+		&self.theme
+	}
+
+	// Cached tiles have their materials baked in, so they're thrown away along with the old theme
+	pub fn set_theme(&mut self, theme: theme::Theme) {
+		self.theme = Arc::new(theme);
+		self.tiles.lock().expect("Poisoned lock").clear();
+	}
+
+	// Cancel all outstanding jobs and wait up to the timeout for any that are already running to
+	// finish.  Returns whether the pool drained in time.  Nothing more should be requested after
+	// this.
+	pub fn shutdown(&self, timeout: Duration) -> bool {
+		self.cur_generation.store(u64::MAX, Ordering::Relaxed);
+		let start = Instant::now();
+		while self.pending.load(Ordering::SeqCst) > 0 {
+			if start.elapsed() > timeout { return false; }
+			std::thread::sleep(Duration::from_millis(5));
+		}
+		true
+	}
+
+	// Show the differences between the first map and the second instead of the maps themselves
+	pub fn set_diff(&mut self) {
+		assert!(self.maps.len() == 2, "Diff mode needs exactly two maps");
+		self.diff = true;
+		self.set_theme(theme::diff());
+	}
+
+	// Cached tiles were built with the old limit, so they're thrown away
+	pub fn set_density_limit(&mut self, limit: Option<usize>) {
+		self.density_limit = limit;
+		self.tiles.lock().expect("Poisoned lock").clear();
+	}
+
+	// Cached tiles have their names baked in, so they're thrown away like for a new theme
+	pub fn set_prefault(&mut self, prefault: bool) {
+		self.prefault = prefault;
+	}
+
+	pub fn set_lang(&mut self, lang: Option<String>) {
+		self.lang = lang;
+		self.tiles.lock().expect("Poisoned lock").clear();
+	}
+
+	// Keep built tiles on disk as well as in memory.  Diff tiles aren't cached, since they depend
+	// on two maps.
+	#[cfg(feature = "disk-cache")]
+	pub fn set_disk_cache(&mut self, cache: Arc<DiskCache>) {
+		self.disk_cache = Some(cache);
+	}
+
+	// Whether any tiles are still being built
+	pub fn busy(&self) -> bool {
+		self.pending.load(Ordering::SeqCst) > 0
+	}
+
+	// How many tile jobs are queued or running, from any request
+	pub fn pending(&self) -> usize {
+		self.pending.load(Ordering::SeqCst)
+	}
+
+	// How many built tiles are kept in memory
+	pub fn cached(&self) -> usize {
+		self.tiles.lock().expect("Poisoned lock").len()
+	}
+
+	// How many tiles from the latest request are still to come.  Ones from older requests that
+	// are still running don't count.
+	pub fn loading(&self) -> usize {
+		self.loading.lock().expect("Poisoned lock").1
+	}
+
+	// Drop cached tiles that nothing else holds onto, which keeps the ones that are still on screen.
+	// Returns how many were dropped.
+	pub fn trim_cache(&mut self) -> usize {
+		let mut tiles = self.tiles.lock().expect("Poisoned lock");
+		let before = tiles.len();
+		tiles.trim();
+		before - tiles.len()
+	}
+
+	// The latest error from reading a map, if there's been one since the last call
+	pub fn take_error(&self) -> Option<String> {
+		self.error.lock().expect("Poisoned lock").take()
+	}
+
+	pub fn diff_mode(&self) -> bool {
+		self.diff
 	}
 
 	pub fn bounds(&self) -> BoundingBox {
@@ -169,45 +952,465 @@ impl RenderManager {
 			.fold(BoundingBox::empty(), |accum, cur| accum.union(&cur))
 	}
 
-	pub fn async_viewport_tiles(&mut self, viewport: &BoundingBox, winwidth: u32, generation: u64, updater: super::Updater) {
+	// Like async_viewport_tiles, but waits for all the tiles and returns them, for use without a
+	// window.  This replaces any outstanding request.
+	pub fn viewport_tiles(&mut self, viewport: &BoundingBox, winwidth: u32) -> Vec<Arc<RenderTile>> {
+		let generation = self.cur_generation.load(Ordering::Relaxed) + 1;
+		let (send, recv) = mpsc::channel();
+		self.async_viewport_tiles(viewport, winwidth, generation, send);
+		// Every job holds a copy of the sender, so this ends once they've all finished
+		recv.into_iter().collect()
+	}
+
+	pub fn async_viewport_tiles<S: TileSink>(&mut self, viewport: &BoundingBox, winwidth: u32, generation: u64, updater: S) {
 		self.cur_generation.store(generation, Ordering::Relaxed);
+		// Several viewers can share a generation, in which case their tiles are counted together
+		{
+			let mut loading = self.loading.lock().expect("Poisoned lock");
+			if loading.0 != generation { *loading = (generation, 0); }
+		}
 		let deg_lon_per_px = viewport.width() as f64 * 360.0 / (winwidth as f64 * mapsforge::COORD_MAX as f64);
-		for map in &self.maps {
-			if BoundingBox::from_corners(map.bounds()).intersection(viewport).is_empty() { continue; }
+		// In diff mode, only the first map's tiles are requested and the second map's are read
+		// alongside them
+		let maps = if self.diff { &self.maps[..1] } else { &self.maps[..] };
+		// The viewport can run past the antimeridian, where the map carries on from its other side
+		let (view_min, view_max) = viewport.corners().unwrap();
+		let laps = view_min.x.div_floor(mapsforge::COORD_MAX)..=view_max.x.div_floor(mapsforge::COORD_MAX);
+		for (map_idx, map) in maps.iter().enumerate() {
+			let (min, max) = map.bounds();
+			let in_view = laps.clone().any(|lap| {
+				let shift = Coord { x: lap * mapsforge::COORD_MAX, y: 0 };
+				!BoundingBox::from_corners((min.add(&shift), max.add(&shift))).intersection(viewport).is_empty()
+			});
+			if !in_view { continue; }
 			let maybe_zoom = map.desired_zoom_level(deg_lon_per_px);
 			if let Some(zoom) = maybe_zoom {
-				let (xrange, yrange) = visible_tiles(&viewport, zoom);
-				let zoom_cache = self.tiles.entry((map.path().to_path_buf(), zoom)).or_insert(Arc::new(Mutex::new(HashMap::new())));
+				let epoch = self.tiles.lock().expect("Poisoned lock").epoch;
 				let ntile = 1 << zoom;
-				for y in yrange.0..=yrange.1 {
-					for x in xrange.0..=xrange.1 {
-						if y <= 0 || x <= 0 || y > ntile || x > ntile {
-							updater.send(UpdateEvent::Tile { generation, tile: Arc::new(RenderTile::empty(zoom, x, y)) });
-						}
-						else {
-							let (x, y) = (x as u32, y as u32);
-							let thread_updater = updater.clone();
-							let thread_map = map.clone();
-							let thread_cache = zoom_cache.clone();
-							let thread_generation = self.cur_generation.clone();
-							let thread_theme = self.theme.clone();
-							self.render_threads.spawn(move || {
-								if generation < thread_generation.load(Ordering::Relaxed) { return; }
-								let cached_tile = thread_cache.lock().expect("Poisoned lock").get(&(x, y)).cloned();
-								let tile = if let Some(existing_tile) = cached_tile {
-									existing_tile.clone()
-								}
-								else {
-									let new_tile = Arc::new(RenderTile::new(thread_map.tile(zoom, x, y), zoom, x as i64, y as i64, &thread_theme));
-									thread_cache.lock().expect("Poisoned lock").insert((x, y), new_tile.clone());
-									new_tile
+				if self.prefault {
+					let tiles = TileGrid::MERCATOR.tiles_from_center(viewport, zoom).into_iter()
+						.filter(|(_, y)| (0..ntile).contains(y))
+						.map(|(x, y)| (TileGrid::MERCATOR.wrap_x(zoom, x) as u32, y as u32))
+						.collect::<Vec<_>>();
+					if let Err(e) = map.prefault_tiles(zoom, &tiles) { eprintln!("{}: couldn't prefault: {}", map.path().display(), e); }
+				}
+				// Jobs run in the order they're queued within a generation
+				for (shown_x, y) in TileGrid::MERCATOR.tiles_from_center(viewport, zoom) {
+					if y < 0 || y >= ntile {
+						updater.tile_ready(generation, Arc::new(RenderTile { map: map_idx, ..RenderTile::empty(zoom, shown_x, y) }));
+					}
+					else {
+						// Past the antimeridian, the tile from the other side of the map is read
+						// and cached as usual, then moved over to where it shows
+						let (x, y) = (TileGrid::MERCATOR.wrap_x(zoom, shown_x) as u32, y as u32);
+						let thread_updater = updater.clone();
+						let thread_map = map.clone();
+						let thread_cache = self.tiles.clone();
+						let thread_generation = self.cur_generation.clone();
+						let thread_theme = self.theme.clone();
+						let thread_other = if self.diff { Some(self.maps[1].clone()) } else { None };
+						let thread_error = self.error.clone();
+						let density_limit = self.density_limit;
+						let lang = self.lang.clone().or_else(|| map.header().pref_lang().map(str::to_string));
+						#[cfg(feature = "disk-cache")]
+						let thread_disk_cache = if self.diff { None } else { self.disk_cache.clone() };
+						let pending = PendingJob::new(self.pending.clone(), self.loading.clone(), generation);
+						self.queue.push(generation, Box::new(move || {
+							if generation < thread_generation.load(Ordering::Relaxed) { return; }
+							let cached_tile = thread_cache.lock().expect("Poisoned lock").touch((map_idx, zoom, x, y));
+							let tile = if let Some(existing_tile) = cached_tile {
+								existing_tile.clone()
+							}
+							else {
+								let report = |msg: String| {
+									eprintln!("{}", msg);
+									*thread_error.lock().expect("Poisoned lock") = Some(msg);
+								};
+								let read_tile = |map: &mapsforge::MapFile| match map.stored_tile(zoom, x, y) {
+									Ok(tile) => Some(tile),
+									Err(e) => {
+										report(format!("{}: zoom {} tile {:?}: {}", map.path().display(), zoom, (x, y), e));
+										None
+									},
+								};
+								let build = || match (read_tile(&thread_map), thread_other) {
+									(Some(tile), None) if thread_map.covers(zoom, x, y) => RenderTile::new(tile, zoom, x as i64, y as i64, &thread_theme, density_limit, lang.as_deref()),
+									(Some(old), Some(other)) => RenderTile::diff_with(old, &other, zoom, x, y, deg_lon_per_px, &thread_theme).unwrap_or_else(|msg| {
+										report(msg);
+										RenderTile::empty(zoom, x as i64, y as i64)
+									}),
+									(Some(_), None) | (None, _) => RenderTile::empty(zoom, x as i64, y as i64),
+								};
+								#[cfg(feature = "disk-cache")]
+								let new_tile = match &thread_disk_cache {
+									Some(cache) => cache.load_or_build(thread_map.path(), &cache_style(&thread_theme, lang.as_deref(), density_limit), zoom, x, y, build),
+									None => build(),
 								};
-								thread_updater.send(UpdateEvent::Tile { generation, tile });
-							});
-						}
+								#[cfg(not(feature = "disk-cache"))]
+								let new_tile = build();
+								let new_tile = Arc::new(RenderTile { map: map_idx, ..new_tile });
+								thread_cache.lock().expect("Poisoned lock").insert((map_idx, zoom, x, y), new_tile.clone(), epoch);
+								new_tile
+							};
+							// Parsing can take a while, so don't bother delivering tiles nobody wants anymore
+							if generation < thread_generation.load(Ordering::Relaxed) { return; }
+							let tile = if shown_x == x as i64 { tile } else { Arc::new(tile.wrapped(shown_x)) };
+							// Done before delivering, so the count is already down when the tile is drawn
+							drop(pending);
+							thread_updater.tile_ready(generation, tile);
+						}));
+						let queue = self.queue.clone();
+						self.render_threads.spawn(move || if let Some(job) = queue.pop() { job() });
 					}
 				}
 			}
 		}
 	}
 }
+
+#[test]
+fn test_limit_density() {
+	let square = |size: i64| Object {
+		geo: Geometry::Path(vec![vec![Coord { x: 0, y: 0 }, Coord { x: size, y: 0 }, Coord { x: size, y: size }]]),
+		name: Some(size.to_string()),
+		tags: Default::default(),
+		material: theme::Material::default(),
+		subtiles: 0,
+		label_pos: None,
+	};
+	let mut layers = BTreeMap::new();
+	layers.insert(0, (0 .. 50000).map(|i| square(i % 1000)).collect::<Vec<_>>());
+	layers.insert(1, vec![square(5), square(3)]);
+	assert!(limit_density(&mut layers, 100));
+	assert_eq!(layers[&0].len(), 100);
+	assert!(layers[&0].iter().all(|obj| obj.size() >= 998)); // Largest objects kept
+	assert_eq!(layers[&1].len(), 2); // Under the limit, untouched
+	let mut layers = BTreeMap::new();
+	layers.insert(0, vec![square(1), square(9), square(4), square(7)]);
+	assert!(limit_density(&mut layers, 2));
+	let names = layers[&0].iter().map(|obj| obj.name.clone().unwrap()).collect::<Vec<_>>();
+	assert_eq!(names, vec!["9", "7"]); // Original order
+	assert!(!limit_density(&mut layers, 2));
+}
+
+#[test]
+fn test_density_limit() {
+	// A tile crowded with short roads only keeps as many as the limit allows
+	let mut builder = mapsforge::builder::MapBuilder::new((0.1, 0.1), (1.3, 1.3)).interval(8, 5, 9);
+	for i in 0 .. 300 {
+		let lat = 0.3 + 0.0005 * i as f64;
+		builder = builder.way(5, &[(lat, 0.3), (lat, 0.31)], &[("highway", "primary")]);
+	}
+	let map = builder.open();
+	let theme = theme::basic();
+	let build = |limit| RenderTile::new(map.stored_tile(8, 128, 127).unwrap(), 8, 128, 127, &theme, limit, None);
+	let count = |tile: &RenderTile| tile.layers.values().map(Vec::len).sum::<usize>();
+	let full = build(None);
+	assert_eq!((count(&full), full.truncated), (300, false));
+	let limited = build(Some(100));
+	assert_eq!((count(&limited), limited.truncated), (100, true));
+}
+
+#[test]
+fn test_job_queue_order() {
+	let queue = JobQueue::new();
+	let order = Arc::new(Mutex::new(vec![]));
+	for (generation, id) in &[(1, "a"), (3, "b"), (2, "c"), (3, "d"), (1, "e")] {
+		let order = order.clone();
+		queue.push(*generation, Box::new(move || order.lock().unwrap().push(*id)));
+	}
+	while let Some(job) = queue.pop() { job(); }
+	assert_eq!(*order.lock().unwrap(), vec!["b", "d", "c", "a", "e"]);
+}
+
+#[test]
+fn test_clip_paths() {
+	let bounds = BoundingBox::from_corners((Coord { x: 0, y: 0 }, Coord { x: 100, y: 100 }));
+	let c = |x, y| Coord { x, y };
+	// A square hanging off the right edge loses the part outside
+	let square = vec![c(50, 20), c(150, 20), c(150, 80), c(50, 80), c(50, 20)];
+	assert_eq!(clip_paths(vec![square.clone()], &bounds, true), vec![vec![c(50, 20), c(100, 20), c(100, 80), c(50, 80), c(50, 20)]]);
+	// As a line, such as a roundabout, it loses the side outside rather than being closed along the edge
+	assert_eq!(clip_paths(vec![square], &bounds, false), vec![vec![c(50, 20), c(100, 20)], vec![c(100, 80), c(50, 80), c(50, 20)]]);
+	// Cut across two edges at once, the corner of the box is filled in
+	let triangle = vec![c(50, 50), c(200, 50), c(50, 200), c(50, 50)];
+	assert_eq!(clip_paths(vec![triangle], &bounds, true), vec![vec![c(50, 100), c(50, 50), c(100, 50), c(100, 100), c(50, 100)]]);
+	// A line that leaves and comes back splits in two
+	let line = vec![c(-50, 50), c(50, 50), c(50, 150), c(80, 150), c(80, 50)];
+	assert_eq!(clip_paths(vec![line], &bounds, false), vec![vec![c(0, 50), c(50, 50), c(50, 100)], vec![c(80, 100), c(80, 50)]]);
+	// Completely outside
+	let away = vec![c(200, 200), c(300, 200), c(300, 300), c(200, 200)];
+	assert!(clip_paths(vec![away.clone()], &bounds, true).is_empty());
+	assert!(clip_paths(vec![away, vec![c(-10, -10), c(-10, 200)]], &bounds, false).is_empty());
+	// Everything inside is left alone
+	let inside = vec![c(10, 10), c(20, 30), c(40, 10)];
+	assert_eq!(clip_paths(vec![inside.clone()], &bounds, false), vec![inside]);
+}
+
+#[test]
+fn test_tile_grid() {
+	let square = TileGrid::MERCATOR;
+	let size = mapsforge::COORD_MAX >> 3;
+	assert_eq!(square.bounds(3, 2, 5).corners(), Some((Coord { x: 2 * size, y: 5 * size }, Coord { x: 3 * size, y: 6 * size })));
+	// Twice as wide as it is tall, as in an equirectangular projection
+	let wide = TileGrid { width: 3600, height: 1800 };
+	assert_eq!(wide.tile_size(1), (1800, 900));
+	assert_eq!(wide.bounds(1, 1, 0).corners(), Some((Coord { x: 1800, y: 0 }, Coord { x: 3600, y: 900 })));
+	assert_eq!(wide.bounds(2, 3, 3).corners(), Some((Coord { x: 2700, y: 1350 }, Coord { x: 3600, y: 1800 })));
+	let viewport = BoundingBox::from_corners((Coord { x: -10, y: 440 }, Coord { x: 1000, y: 460 }));
+	assert_eq!(wide.visible_tiles(&viewport, 2), ((-1, 1), (0, 1)));
+	assert_eq!(square.visible_tiles(&viewport, 2), ((-1, 0), (0, 0)));
+	assert_eq!((square.wrap_x(2, -1), square.wrap_x(2, 4), square.wrap_x(2, 2)), (3, 0, 2));
+	// A 3×3 block of tiles with the middle one in the middle of the viewport
+	let block = BoundingBox::from_corners((Coord { x: 450, y: 450 }, Coord { x: 2250, y: 2250 }));
+	let order = TileGrid { width: 3600, height: 3600 }.tiles_from_center(&block, 2);
+	assert_eq!(order.len(), 9);
+	assert_eq!(order[0], (1, 1));
+	assert!(order[1..5].iter().all(|(x, y)| (x - 1).abs() + (y - 1).abs() == 1));
+	assert!(order[5..].iter().all(|(x, y)| (x - 1).abs() == 1 && (y - 1).abs() == 1));
+}
+
+#[test]
+fn test_elevation_at() {
+	let contour = |y: i64, ele: f64| (Geometry::Path(vec![vec![Coord { x: 0, y }, Coord { x: 50, y }, Coord { x: 100, y }]]), ele);
+	let mut tile = RenderTile::empty(0, 0, 0);
+	assert_eq!(elevation_at(std::iter::once(&tile), Coord { x: 50, y: 50 }), None);
+	tile.heights = vec![contour(0, 100.0), contour(100, 120.0), contour(200, 140.0)];
+	assert_eq!(elevation_at(std::iter::once(&tile), Coord { x: 50, y: 25 }), Some(105.0));
+	assert_eq!(elevation_at(std::iter::once(&tile), Coord { x: 70, y: 100 }), Some(120.0)); // Right on a contour
+	assert_eq!(elevation_at(std::iter::once(&tile), Coord { x: 30, y: 150 }), Some(130.0));
+	tile.heights = vec![contour(0, 100.0), (Geometry::Point(Coord { x: 50, y: 30 }), 112.0)];
+	assert_eq!(elevation_at(std::iter::once(&tile), Coord { x: 50, y: 20 }), Some(108.0)); // Between a contour and a spot height
+}
+
+#[test]
+fn test_subtile_bits() {
+	let bounds = BoundingBox::from_corners((Coord { x: 0, y: 0 }, Coord { x: 400, y: 400 }));
+	let line = |points: &[(i64, i64)]| Geometry::Path(vec![points.iter().map(|(x, y)| Coord { x: *x, y: *y }).collect()]);
+	assert_eq!(subtile_bits(&line(&[(0, 50), (400, 50)]), &bounds), 0xf000);
+	assert_eq!(subtile_bits(&line(&[(150, 10), (150, 190)]), &bounds), 0x4400);
+	assert_eq!(subtile_bits(&line(&[(-100, 350), (-50, 350)]), &bounds), 0); // Entirely outside
+	assert_eq!(subtile_bits(&Geometry::Point(Coord { x: 350, y: 350 }), &bounds), 0x0001);
+	// A ring within the middle four sub-tiles marks just those
+	assert_eq!(subtile_bits(&line(&[(120, 120), (280, 120), (280, 280), (120, 280), (120, 120)]), &bounds), 0x0660);
+	// A view of the right half of the tile, and one off to its left
+	assert_eq!(subtiles_overlapping(&bounds, &BoundingBox::from_corners((Coord { x: 210, y: -50 }, Coord { x: 500, y: 450 }))), 0x3333);
+	assert_eq!(subtiles_overlapping(&bounds, &BoundingBox::from_corners((Coord { x: -100, y: 0 }, Coord { x: -10, y: 400 }))), 0);
+}
+
+#[test]
+fn test_assemble_rings() {
+	let line = |points: &[(i64, i64)]| points.iter().map(|(x, y)| Coord { x: *x, y: *y }).collect::<Vec<_>>();
+	// A square in three pieces, one of them backwards, plus a closed ring and a stray line
+	let lines = vec![line(&[(0, 0), (10, 0)]), line(&[(0, 10), (10, 10), (10, 0)]), line(&[(0, 10), (0, 0)]), line(&[(20, 20), (30, 20), (30, 30), (20, 20)]), line(&[(50, 50), (60, 60)])];
+	let mut rings = assemble_rings(lines);
+	rings.sort_by_key(|ring| ring.len());
+	assert_eq!(rings.len(), 2);
+	assert_eq!(rings[0], line(&[(20, 20), (30, 20), (30, 30), (20, 20)]));
+	assert_eq!(rings[1].len(), 5);
+	assert_eq!(rings[1].first(), rings[1].last());
+	for corner in &[(0, 0), (10, 0), (10, 10), (0, 10)] {
+		assert!(rings[1].contains(&Coord { x: corner.0, y: corner.1 }));
+	}
+}
+
+#[test]
+fn test_loading_count() {
+	let (pending, loading) = (Arc::new(AtomicUsize::new(0)), Arc::new(Mutex::new((1, 0))));
+	let old = PendingJob::new(pending.clone(), loading.clone(), 1);
+	assert_eq!(*loading.lock().unwrap(), (1, 1));
+	// A new request starts the count over, and the old job finishing doesn't take from it
+	*loading.lock().unwrap() = (2, 0);
+	let new = PendingJob::new(pending.clone(), loading.clone(), 2);
+	drop(old);
+	assert_eq!(*loading.lock().unwrap(), (2, 1));
+	assert_eq!(pending.load(Ordering::SeqCst), 1);
+	drop(new);
+	assert_eq!(*loading.lock().unwrap(), (2, 0));
+	assert_eq!(pending.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn test_trim_cache() {
+	let mut render = RenderManager::new(vec![]);
+	let shown = Arc::new(RenderTile::empty(3, 1, 1));
+	{
+		let mut tiles = render.tiles.lock().unwrap();
+		tiles.insert((0, 3, 1, 1), shown.clone(), 0);
+		tiles.insert((0, 3, 2, 1), Arc::new(RenderTile::empty(3, 2, 1)), 0);
+		tiles.insert((0, 4, 1, 1), Arc::new(RenderTile::empty(4, 1, 1)), 0);
+	}
+	assert_eq!(render.trim_cache(), 2);
+	assert!(render.tiles.lock().unwrap().touch((0, 3, 1, 1)).is_some());
+	drop(shown);
+	assert_eq!(render.trim_cache(), 1);
+	assert_eq!(render.tiles.lock().unwrap().len(), 0);
+}
+
+#[test]
+fn test_tile_cache_lru() {
+	let tile = |x| Arc::new(RenderTile::empty(5, x, 0));
+	let mut cache = TileCache::new(2);
+	cache.insert((0, 5, 1, 0), tile(1), 0);
+	cache.insert((0, 5, 2, 0), tile(2), 0);
+	assert!(cache.touch((0, 5, 1, 0)).is_some()); // Now 2 is the least recently used
+	cache.insert((0, 5, 3, 0), tile(3), 0);
+	assert!(cache.touch((0, 5, 2, 0)).is_none());
+	assert!(cache.touch((0, 5, 1, 0)).is_some() && cache.touch((0, 5, 3, 0)).is_some());
+	// Replacing a tile doesn't count it twice
+	cache.insert((0, 5, 3, 0), tile(3), 0);
+	assert_eq!((cache.len(), cache.by_use.len()), (2, 2));
+	// Tiles from before the cache was cleared aren't put back
+	cache.clear();
+	cache.insert((0, 5, 1, 0), tile(1), 0);
+	assert_eq!(cache.len(), 0);
+	cache.insert((0, 5, 1, 0), tile(1), 1);
+	assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn test_tile_overlaps() {
+	let tile = RenderTile::empty(3, 1, 1);
+	assert!(!tile.overlaps(&RenderTile::empty(3, 2, 1))); // Neighbors only share an edge
+	assert!(tile.overlaps(&RenderTile::empty(4, 3, 3)));
+	assert!(!tile.overlaps(&RenderTile::empty(4, 4, 3)));
+	let quarters = [(2, 2), (3, 2), (2, 3), (3, 3)].iter().map(|(x, y)| RenderTile::empty(4, *x, *y)).collect::<Vec<_>>();
+	assert!(tile.hidden_by(&quarters));
+	assert!(!tile.hidden_by(&quarters[1..]));
+	assert!(tile.hidden_by(&[RenderTile::empty(2, 0, 0)]));
+	assert!(!RenderTile::empty(2, 0, 0).hidden_by(&quarters));
+}
+
+#[test]
+fn test_diff() {
+	let build = |extra: bool| {
+		let mut map = mapsforge::builder::MapBuilder::new((0.1, 0.1), (1.3, 1.3))
+			.interval(8, 5, 9)
+			.way(5, &[(0.3, 0.3), (0.4, 0.4)], &[("name", "kept")])
+			.way(5, &[(0.5, 0.5), (0.6, 0.6)], &[("name", if extra { "renamed" } else { "original" })]);
+		if extra { map = map.way(5, &[(0.7, 0.7), (0.8, 0.8)], &[("name", "added")]); }
+		map.open()
+	};
+	let (old, new) = (build(false), build(true));
+	let theme = theme::diff();
+	let deg_lon_per_px = 360.0 / (256.0 * 256.0); // Zoom 8
+	let tile = RenderTile::diff_with(old.stored_tile(8, 128, 127).unwrap(), &new, 8, 128, 127, deg_lon_per_px, &theme).unwrap();
+	let mut materials = tile.layers.values().flatten().map(|obj| (obj.name.clone().unwrap(), obj.material.name().to_string())).collect::<Vec<_>>();
+	materials.sort();
+	let expect = [("added", "added"), ("kept", "unchanged"), ("original", "removed"), ("renamed", "added")];
+	assert_eq!(materials, expect.iter().map(|(name, material)| (name.to_string(), material.to_string())).collect::<Vec<_>>());
+	// Without data at the same zoom in the second map, it's an error like failing to read it
+	let far = 360.0 / (256.0 * 4096.0); // Zoom 12
+	assert!(RenderTile::diff_with(old.stored_tile(8, 128, 127).unwrap(), &new, 8, 128, 127, far, &theme).is_err());
+}
+
+#[test]
+fn test_draw_order() {
+	// Stored in an order that would put the park over the roads and building
+	let square = |lat: f64| vec![(lat, 0.3), (lat, 0.4), (lat + 0.1, 0.4), (lat + 0.1, 0.3), (lat, 0.3)];
+	let map = mapsforge::builder::MapBuilder::new((0.1, 0.1), (1.3, 1.3))
+		.interval(8, 5, 9)
+		.way(5, &[(0.3, 0.3), (0.4, 0.4)], &[("highway", "primary"), ("name", "first")])
+		.way(5, &[(0.5, 0.3), (0.6, 0.4)], &[("railway", "rail")])
+		.way(5, &square(0.7), &[("building", "yes")])
+		.way(5, &[(0.3, 0.5), (0.4, 0.6)], &[("highway", "primary"), ("name", "second")])
+		.way(5, &square(0.9), &[("leisure", "park")])
+		.open();
+	let tile = RenderTile::new(map.stored_tile(8, 128, 127).unwrap(), 8, 128, 127, &theme::basic(), None, None);
+	let drawn = tile.layers[&0].iter().map(|obj| (obj.material.name(), obj.name.as_deref())).collect::<Vec<_>>();
+	// In the theme's order, with the two roads left in map order
+	assert_eq!(drawn, vec![("greenspace", None), ("building", None), ("road", Some("first")), ("road", Some("second")), ("rail", None)]);
+}
+
+#[test]
+fn test_midpoint_label() {
+	// A road crossing several tiles, with no label position from the map
+	let (west, east) = ((0.3, 0.2), (0.3, 1.2));
+	let map = mapsforge::builder::MapBuilder::new((0.1, 0.1), (1.3, 1.3))
+		.interval(10, 5, 10)
+		.way(5, &[west, east], &[("highway", "primary"), ("name", "Long Road")])
+		.open();
+	let theme = theme::basic();
+	let corner = |(lat, lon)| mapsforge::coord2tile(10, mapsforge::LatLon::from_degrees(lat, lon));
+	let (first, last) = (corner(west), corner(east));
+	assert!(last.0 > first.0 + 1);
+	let mut labels = vec![];
+	for x in first.0 ..= last.0 {
+		let tile = RenderTile::new(map.stored_tile(10, x, first.1).unwrap(), 10, x as i64, first.1 as i64, &theme, None, None);
+		assert!(!tile.layers.is_empty(), "Road missing from tile {}", x);
+		labels.extend(tile.layers.values().flatten().filter_map(|obj| obj.label_pos));
+	}
+	// Only the tile holding the middle of the road labels it
+	let ends = [west, east].map(|(lat, lon)| mapsforge::LatLon::from_degrees(lat, lon).to_coord());
+	let mid = mapsforge::Coord { x: (ends[0].x + ends[1].x) / 2, y: (ends[0].y + ends[1].y) / 2 };
+	assert_eq!(labels.len(), 1);
+	assert!((labels[0].x - mid.x).abs() <= 1 && (labels[0].y - mid.y).abs() <= 1, "{:?} isn't the middle {:?}", labels[0], mid);
+}
+
+#[test]
+fn test_closed_line_across_seam() {
+	// A loop of road that isn't an area, half in each of two tiles
+	let map = mapsforge::builder::MapBuilder::new((0.1, 0.1), (2.0, 2.0))
+		.interval(8, 5, 9)
+		.way(5, &[(0.5, 1.2), (0.5, 1.6), (0.9, 1.6), (0.9, 1.2), (0.5, 1.2)], &[("highway", "primary"), ("area", "no")])
+		.open();
+	let theme = theme::basic();
+	for x in [128, 129] {
+		let tile = RenderTile::new(map.stored_tile(8, x, 127).unwrap(), 8, x as i64, 127, &theme, None, None);
+		let (width, height) = TileGrid::MERCATOR.tile_size(8);
+		let (min, max) = TileGrid::MERCATOR.bounds(8, x as i64, 127).grow(width / 16, height / 16).corners().unwrap();
+		let pieces = tile.layers.values().flatten().flat_map(|obj| match &obj.geo {
+			Geometry::Path(polies) => polies.clone(),
+			_ => panic!("The loop was drawn as an area"),
+		}).collect::<Vec<_>>();
+		assert!(!pieces.is_empty());
+		for piece in pieces {
+			// Cut open where it leaves the tile, with nothing drawn along the edge to close it up
+			assert_ne!(piece.first(), piece.last());
+			assert!(!piece[1 .. piece.len() - 1].iter().any(|point| point.x == min.x || point.x == max.x), "{:?}", piece);
+		}
+	}
+}
+
+#[test]
+fn test_water_tile() {
+	let map = mapsforge::builder::MapBuilder::new((0.1, 0.1), (2.0, 2.0))
+		.interval(8, 5, 9)
+		.way(5, &[(0.3, 0.3), (0.4, 0.4)], &[("highway", "primary")])
+		.sea()
+		.open();
+	let theme = theme::basic();
+	let build = |x, y| RenderTile::new(map.stored_tile(8, x, y).unwrap(), 8, x as i64, y as i64, &theme, None, None);
+	assert!(!build(128, 127).layers.contains_key(&WATER_LAYER));
+	let sea = build(129, 127);
+	match &sea.layers[&WATER_LAYER][..] {
+		[Object { geo: Geometry::Area(rings), material, .. }] => {
+			assert_eq!(material.name(), "water");
+			assert_eq!(rings[0].len(), 5);
+			assert!(rings[0].iter().all(|point| sea.bounds().corners().map_or(false, |(min, max)| point.x == min.x || point.x == max.x)));
+		},
+		_ => panic!("No water in an empty sea tile"),
+	}
+}
+
+#[test]
+fn test_antimeridian() {
+	let map = mapsforge::builder::MapBuilder::new((-1.0, 178.0), (1.0, 179.9))
+		.interval(8, 5, 9)
+		.way(5, &[(0.5, 178.5), (0.5, 179.5)], &[("highway", "primary")])
+		.build();
+	let map = Arc::new(mapsforge::MapFile::from_bytes("antimeridian.map".into(), map).unwrap());
+	let mut render = RenderManager::new(vec![map.clone()]);
+	// The same stretch of the map, one lap to the west
+	let (min, max) = map.bounds();
+	let lap = Coord { x: -mapsforge::COORD_MAX, y: 0 };
+	let viewport = BoundingBox::from_corners((min.add(&lap), max.add(&lap)));
+	let tiles = render.viewport_tiles(&viewport, 256);
+	assert_eq!(render.loading(), 0);
+	assert!(!tiles.is_empty() && tiles.iter().all(|tile| tile.x < 0 && tile.zoom == 8));
+	let road = tiles.iter().flat_map(|tile| tile.layers.values().flatten()).find_map(|obj| match &obj.geo {
+		Geometry::Path(polies) => Some(polies[0][0]),
+		_ => None,
+	});
+	assert!(road.map_or(false, |point| (min.x + lap.x ..= max.x + lap.x).contains(&point.x)));
+}
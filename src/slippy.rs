@@ -0,0 +1,111 @@
+use mapviewer::mapsforge::{self, Coord, LatLon};
+use mapviewer::render::{BoundingBox, TileGrid};
+
+use crate::transform::Transform;
+
+pub const TILE_SIZE: u32 = 256;
+// Past this, a pixel is smaller than one unit of map coordinates
+pub const MAX_ZOOM: u8 = 24;
+
+// "A-B" for zoom levels A through B, or just "A" for one level
+pub fn parse_zoom_range(text: &str) -> Result<(u8, u8), String> {
+	let zoom = |text: &str| text.trim().parse::<u8>().ok().filter(|zoom| *zoom <= MAX_ZOOM).ok_or(format!("Bad zoom level \"{}\"", text));
+	let (min, max) = match text.split_once('-') {
+		Some((min, max)) => (zoom(min)?, zoom(max)?),
+		None => (zoom(text)?, zoom(text)?),
+	};
+	if min > max { return Err(format!("Zoom range {} is backwards", text)); }
+	Ok((min, max))
+}
+
+// Two opposite corners as "lat,lon,lat,lon"
+pub fn parse_bbox(text: &str) -> Result<(LatLon, LatLon), String> {
+	let fields = text.split(',').map(|field| field.trim().parse::<f64>().ok().filter(|x| x.is_finite())).collect::<Option<Vec<_>>>();
+	match fields.as_deref() {
+		Some([lat1, lon1, lat2, lon2]) if lat1.abs() <= 90.0 && lat2.abs() <= 90.0 && lon1.abs() <= 180.0 && lon2.abs() <= 180.0 =>
+			Ok((LatLon::from_degrees(*lat1, *lon1), LatLon::from_degrees(*lat2, *lon2))),
+		_ => Err(format!("Bad bounding box \"{}\": expected lat,lon,lat,lon", text)),
+	}
+}
+
+// Zoom for the coord command when none is given, about street level
+const DEFAULT_COORD_ZOOM: u8 = 14;
+
+// The coord command: where a position ends up in map coordinates, which tile it's in at a zoom
+// level, and how far it is from that tile's top left corner in degrees and pixels
+pub fn describe_position(args: &[String]) -> Result<String, String> {
+	let (lat, lon, zoom) = match args {
+		[lat, lon] => (lat, lon, None),
+		[lat, lon, zoom] => (lat, lon, Some(zoom)),
+		_ => return Err("Usage: coord LAT LON [ZOOM]".to_string()),
+	};
+	let degrees = |text: &str, max: f64| text.trim().parse::<f64>().ok().filter(|x| x.abs() <= max).ok_or(format!("Bad coordinate \"{}\"", text));
+	let point = LatLon::from_degrees(degrees(lat, 90.0)?, degrees(lon, 180.0)?);
+	let zoom = match zoom {
+		Some(zoom) => zoom.trim().parse::<u8>().ok().filter(|zoom| *zoom <= MAX_ZOOM).ok_or(format!("Bad zoom level \"{}\"", zoom))?,
+		None => DEFAULT_COORD_ZOOM,
+	};
+	let coord = point.to_coord();
+	let (x, y) = mapsforge::coord2tile(zoom, point);
+	let origin = mapsforge::tile_origin(zoom, x, y);
+	let (corner, offset) = (origin.to_coord(), (point.degrees().0 - origin.degrees().0, point.degrees().1 - origin.degrees().1));
+	let scale = ((TILE_SIZE as i64) << zoom) as f64 / mapsforge::COORD_MAX as f64;
+	let pixels = ((coord.x - corner.x) as f64 * scale, (coord.y - corner.y) as f64 * scale);
+	Ok(format!("coord {},{}\ntile {}/{}/{}\noffset {:.6},{:.6} degrees, {:.1},{:.1} px", coord.x, coord.y, zoom, x, y, offset.0, offset.1, pixels.0, pixels.1))
+}
+
+// The inclusive ranges of x and y for tiles at this zoom that touch the bounds.  Tiles that would
+// only share an edge with them are left out.
+pub fn tile_range(bounds: &BoundingBox, zoom: u8) -> ((i64, i64), (i64, i64)) {
+	let last = (1 << zoom) - 1;
+	let (min, max) = bounds.corners().unwrap();
+	let inner = BoundingBox::from_corners((min, Coord { x: (max.x - 1).max(min.x), y: (max.y - 1).max(min.y) }));
+	let ((xmin, xmax), (ymin, ymax)) = TileGrid::MERCATOR.visible_tiles(&inner, zoom);
+	((xmin.clamp(0, last), xmax.clamp(0, last)), (ymin.clamp(0, last), ymax.clamp(0, last)))
+}
+
+// The view that shows exactly one tile in a TILE_SIZE square
+pub fn tile_transform(zoom: u8, x: i64, y: i64) -> Transform {
+	let (topleft, _) = TileGrid::MERCATOR.bounds(zoom, x, y).corners().unwrap();
+	Transform { offset: topleft, scale: (mapsforge::COORD_MAX >> (zoom + 8)) as u32 }
+}
+
+#[test]
+fn test_parse() {
+	assert_eq!(parse_zoom_range("3-12"), Ok((3, 12)));
+	assert_eq!(parse_zoom_range("7"), Ok((7, 7)));
+	assert!(parse_zoom_range("12-3").is_err());
+	assert!(parse_zoom_range("3-30").is_err());
+	assert_eq!(parse_bbox("40.7, -74.1, 40.8,-73.9"), Ok((LatLon::from_degrees(40.7, -74.1), LatLon::from_degrees(40.8, -73.9))));
+	assert!(parse_bbox("40.7,-74.1,40.8").is_err());
+	assert!(parse_bbox("91,0,0,0").is_err());
+}
+
+#[test]
+fn test_tiles() {
+	// London is 10/511/340 on every web map
+	let london = LatLon::from_degrees(51.5074, -0.1278).to_coord();
+	assert_eq!(tile_range(&BoundingBox::from_corners((london, london)), 10), ((511, 511), (340, 340)));
+	let xform = tile_transform(10, 511, 340);
+	let screen = xform.to_screen(london);
+	assert!((0..TILE_SIZE as i64).contains(&screen.x) && (0..TILE_SIZE as i64).contains(&screen.y));
+	let (topleft, botright) = TileGrid::MERCATOR.bounds(10, 511, 340).corners().unwrap();
+	assert_eq!(xform.to_screen(topleft), Coord { x: 0, y: 0 });
+	assert_eq!(xform.to_screen(botright), Coord { x: TILE_SIZE as i64, y: TILE_SIZE as i64 });
+	// The whole world at zoom 2 is all sixteen tiles
+	let world = BoundingBox::from_corners((Coord { x: 0, y: 0 }, Coord { x: mapsforge::COORD_MAX, y: mapsforge::COORD_MAX }));
+	assert_eq!(tile_range(&world, 2), ((0, 3), (0, 3)));
+}
+
+#[test]
+fn test_describe_position() {
+	let describe = |args: &[&str]| describe_position(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+	let half = mapsforge::COORD_MAX / 2;
+	assert_eq!(describe(&["0", "0", "1"]), Ok(format!("coord {},{}\ntile 1/1/1\noffset 0.000000,0.000000 degrees, 0.0,0.0 px", half, half)));
+	let london = describe(&["51.5074", "-0.1278", "10"]).unwrap();
+	assert!(london.contains("tile 10/511/340"), "{}", london);
+	assert!(describe(&["51.5074", "-0.1278"]).unwrap().contains("tile 14/"));
+	assert!(describe(&["91", "0"]).is_err());
+	assert!(describe(&["0", "0", "30"]).is_err());
+	assert!(describe(&["0"]).is_err());
+}
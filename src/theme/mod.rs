@@ -0,0 +1,939 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use skulpin::skia_safe::{Color4f, Paint, PathEffect, Rect, paint};
+
+use super::mapsforge::{Poi, TagValue, Way};
+
+mod xml;
+
+// What shows where nothing is drawn, for themes that don't say.  Most of the world is sea, and
+// maps have land areas drawn over it more often than sea ones.
+const DEFAULT_BACKGROUND: Color4f = Color4f::new(0.6, 0.7, 0.9, 1.0);
+
+// Where a point's label is placed relative to the point itself
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "disk-cache", derive(serde::Serialize, serde::Deserialize))]
+pub enum LabelAnchor {
+	Center,
+	Above,
+	Below,
+	Left,
+	Right,
+}
+
+// How the ends of open lines are drawn, as in SVG's stroke-linecap
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "disk-cache", derive(serde::Serialize, serde::Deserialize))]
+pub enum LineCap {
+	Butt,
+	Round,
+	Square,
+}
+
+// How lines are drawn where they bend, as in SVG's stroke-linejoin
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "disk-cache", derive(serde::Serialize, serde::Deserialize))]
+pub enum LineJoin {
+	Miter,
+	Round,
+	Bevel,
+}
+
+// How the current view wants materials drawn
+#[derive(Clone, Copy, Debug)]
+pub struct PaintContext {
+	pub zoom: f32, // Display zoom level in the same sense as web map tiles, which needn't be whole
+	pub grayscale: bool, // For black and white printing
+	pub antialias: bool, // Off while the view is moving, since it's a lot slower
+}
+
+// Perceived brightness of a color, so that converting to gray keeps dark things dark
+fn luminance(color: Color4f) -> f32 {
+	0.2126 * color.r + 0.7152 * color.g + 0.0722 * color.b
+}
+
+fn context_color(color: Color4f, context: &PaintContext) -> Color4f {
+	if context.grayscale {
+		let gray = luminance(color);
+		Color4f::new(gray, gray, gray, color.a)
+	} else { color }
+}
+
+// Skia's colors don't know about serde, so they're stored as plain RGBA arrays
+#[cfg(feature = "disk-cache")]
+mod color_serde {
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+	use skulpin::skia_safe::Color4f;
+
+	pub fn serialize<S: Serializer>(color: &Option<Color4f>, serializer: S) -> Result<S::Ok, S::Error> {
+		color.map(|c| [c.r, c.g, c.b, c.a]).serialize(serializer)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Color4f>, D::Error> {
+		Ok(Option::<[f32; 4]>::deserialize(deserializer)?.map(|c| Color4f::new(c[0], c[1], c[2], c[3])))
+	}
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "disk-cache", derive(serde::Serialize, serde::Deserialize))]
+pub struct Material {
+	#[cfg_attr(feature = "disk-cache", serde(with = "color_serde"))]
+	fill: Option<Color4f>,
+	#[cfg_attr(feature = "disk-cache", serde(with = "color_serde"))]
+	stroke: Option<Color4f>,
+	label_anchor: LabelAnchor,
+	label_offset: f32, // Pixels between the point and the nearest edge of its label
+	description: Option<String>, // What this material is used for, as shown in the legend
+	label_priority: i32, // Labels with higher priority are kept when labels overlap
+	draw_rank: usize, // Position in the theme's draw order, filled in when the material is matched
+	name: String, // Also filled in when matched, for exports
+	// Lines are width pixels wide at width_ref_zoom and get width_scale times wider for each zoom
+	// level in from there, staying within width_range
+	width: f32,
+	width_scale: f32,
+	width_ref_zoom: f32,
+	width_range: (f32, f32),
+	min_size: f32, // Paths no more than this many pixels across on the screen aren't drawn
+	dash: Option<Vec<f32>>, // Pixels of line and gap along strokes, repeating, or None for solid lines
+	cap: LineCap,
+	join: LineJoin,
+}
+
+impl Default for Material {
+	fn default() -> Self {
+		Self { fill: None, stroke: None, label_anchor: LabelAnchor::Below, label_offset: 4.0, description: None, label_priority: 0, draw_rank: usize::MAX, name: String::new(), width: 1.0, width_scale: 1.0, width_ref_zoom: 0.0, width_range: (0.0, f32::INFINITY), min_size: 4.0, dash: None, cap: LineCap::Butt, join: LineJoin::Miter }
+	}
+}
+
+impl Material {
+	fn build_paint(color: Color4f, style: paint::Style, width: f32, context: &PaintContext) -> Paint {
+		let mut paint = Paint::new(context_color(color, context), None);
+		paint.set_anti_alias(context.antialias);
+		paint.set_style(style);
+		paint.set_stroke_width(width);
+		paint
+	}
+
+	pub fn label_priority(&self) -> i32 {
+		self.label_priority
+	}
+
+	pub fn draw_rank(&self) -> usize {
+		self.draw_rank
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn stroke_width(&self, zoom: f32) -> f32 {
+		let width = self.width * self.width_scale.powf(zoom - self.width_ref_zoom);
+		width.clamp(self.width_range.0, self.width_range.1)
+	}
+
+	// Whether a path this many pixels across is worth drawing
+	pub fn big_enough(&self, size: f32) -> bool {
+		size > self.min_size
+	}
+
+	pub fn paints(&self, context: &PaintContext) -> Vec<Paint> {
+		let mut ret = vec![];
+		if let Some(fill) = self.fill { ret.push(Self::build_paint(fill, paint::Style::Fill, 1.0, context)); }
+		if let Some(stroke) = self.stroke {
+			let mut paint = Self::build_paint(stroke, paint::Style::Stroke, self.stroke_width(context.zoom), context);
+			// Skia wants an even number of intervals, so odd patterns are doubled up like in SVG
+			if let Some(dash) = &self.dash {
+				let intervals = if dash.len() % 2 == 0 { dash.clone() } else { [&dash[..], &dash[..]].concat() };
+				if let Some(effect) = PathEffect::dash(&intervals, 0.0) { paint.set_path_effect(effect); }
+			}
+			// Butt caps and miter joins, the default as in SVG, suit railways and walls, but leave
+			// roads and rivers looking jagged where they bend, so those materials ask for round
+			// ones.  Caps go on the ends of every open contour, so a way split into several
+			// polylines gets them at each break too.
+			paint.set_stroke_cap(match self.cap {
+				LineCap::Butt => paint::Cap::Butt,
+				LineCap::Round => paint::Cap::Round,
+				LineCap::Square => paint::Cap::Square,
+			});
+			paint.set_stroke_join(match self.join {
+				LineJoin::Miter => paint::Join::Miter,
+				LineJoin::Round => paint::Join::Round,
+				LineJoin::Bevel => paint::Join::Bevel,
+			});
+			ret.push(paint);
+		}
+		ret
+	}
+
+	pub fn description(&self) -> Option<&str> {
+		self.description.as_deref()
+	}
+
+	// Given the screen location of a point and the bounds of its label as measured by
+	// Font::measure_str (relative to the text origin), return the origin at which to draw the
+	// label so that it sits at this material's anchor without covering the point
+	pub fn label_origin(&self, point: (f32, f32), text: &Rect) -> (f32, f32) {
+		let (x, y) = point;
+		let offset = self.label_offset;
+		let center_x = x - text.left - text.width() / 2.0;
+		let center_y = y - text.top - text.height() / 2.0;
+		match self.label_anchor {
+			LabelAnchor::Center => (center_x, center_y),
+			LabelAnchor::Above => (center_x, y - offset - text.bottom),
+			LabelAnchor::Below => (center_x, y + offset - text.top),
+			LabelAnchor::Left => (x - offset - text.right, center_y),
+			LabelAnchor::Right => (x + offset - text.left, center_y),
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EntityType {
+	Any,
+	Way, // Open or closed
+	Path, // Open way
+	Area, // Closed way
+	Point,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CompareOp {
+	Less,
+	LessEqual,
+	Equal,
+	GreaterEqual,
+	Greater,
+}
+
+#[derive(Clone)]
+enum TagMatch {
+	Present,
+	Literal(HashSet<String>),
+	Regex(Regex), // Matches the whole value, not just part of it
+	Range { min: Option<f64>, max: Option<f64> }, // Numeric values from min up to but not including max
+	Compare { op: CompareOp, value: f64 }, // Numeric values that compare to this one like so, such as maxspeed > 80
+}
+
+impl TagMatch {
+	fn from_values(values: &[&str]) -> Self {
+		Self::Literal(values.iter().map(|x| x.to_string()).collect())
+	}
+
+	fn regex(pattern: &str) -> Result<Self, regex::Error> {
+		Ok(Self::Regex(Regex::new(&format!("^(?:{})$", pattern))?))
+	}
+
+	// An operator and a number, like ">80" or "<= -5"
+	fn compare(text: &str) -> Option<Self> {
+		let ops = [("<=", CompareOp::LessEqual), (">=", CompareOp::GreaterEqual), ("==", CompareOp::Equal), ("<", CompareOp::Less), (">", CompareOp::Greater)];
+		let text = text.trim();
+		let (prefix, op) = ops.iter().find(|(prefix, _)| text.starts_with(prefix))?;
+		let value = text[prefix.len()..].trim().parse::<f64>().ok().filter(|x| !x.is_nan())?;
+		Some(Self::Compare { op: *op, value })
+	}
+
+	fn matches(&self, value: &TagValue) -> bool {
+		match self {
+			TagMatch::Present => true,
+			TagMatch::Literal(values) => match value {
+				TagValue::Literal(literal_value) => values.contains(literal_value),
+				_ => false,
+			},
+			TagMatch::Regex(regex) => match value {
+				TagValue::Literal(literal_value) => regex.is_match(literal_value),
+				_ => false,
+			},
+			TagMatch::Range { min, max } => {
+				let number = match value.number() {
+					Some(x) => x,
+					None => return false,
+				};
+				min.is_none_or(|min| number >= min) && max.is_none_or(|max| number < max)
+			},
+			// Values that aren't numbers don't match whatever the operator
+			TagMatch::Compare { op, value: than } => match value.number() {
+				Some(number) => match op {
+					CompareOp::Less => number < *than,
+					CompareOp::LessEqual => number <= *than,
+					CompareOp::Equal => number == *than,
+					CompareOp::GreaterEqual => number >= *than,
+					CompareOp::Greater => number > *than,
+				},
+				None => false,
+			},
+		}
+	}
+}
+
+// The value of one of a POI's tags, including the ones mapsforge stores separately
+fn poi_tag<'a>(poi: &'a Poi, key: &str) -> Option<Cow<'a, TagValue>> {
+	if let Some(value) = poi.tags.get(key) { return Some(Cow::Borrowed(value)); }
+	match key {
+		"name" => poi.name(None).map(|name| TagValue::Literal(name.to_string())),
+		"addr:housenumber" => poi.house_number.clone().map(TagValue::Literal),
+		"ele" => poi.elevation.map(|ele| TagValue::Int(ele as i32)),
+		_ => None,
+	}.map(Cow::Owned)
+}
+
+// The same for a way, whose name, reference, house number, and layer mapsforge also keeps apart
+fn way_tag<'a>(way: &'a Way, key: &str) -> Option<Cow<'a, TagValue>> {
+	if let Some(value) = way.tags.get(key) { return Some(Cow::Borrowed(value)); }
+	match key {
+		"name" => way.name(None).map(|name| TagValue::Literal(name.to_string())),
+		"ref" => way.reference.clone().map(TagValue::Literal),
+		"addr:housenumber" => way.house_number.clone().map(TagValue::Literal),
+		"layer" => Some(TagValue::Byte(way.layer)),
+		_ => None,
+	}.map(Cow::Owned)
+}
+
+// Matches when any one of the tags does, as long as every one of the requirements is met too.
+// Each requirement is met when any one of its tags matches.
+struct Matcher {
+	entity_type: EntityType,
+	requires: Vec<HashMap<String, TagMatch>>,
+	tags: HashMap<String, TagMatch>,
+	material: String,
+	min_zoom: Option<u8>, // Tiles below this zoom level don't use the matcher at all
+	max_zoom: Option<u8>, // Nor do tiles above this one
+}
+
+impl Matcher {
+	fn applies_at(&self, zoom: u8) -> bool {
+		self.min_zoom.is_none_or(|min| zoom >= min) && self.max_zoom.is_none_or(|max| zoom <= max)
+	}
+
+	fn matches<'a>(&self, get: impl Fn(&str) -> Option<Cow<'a, TagValue>>) -> bool {
+		let any = |tags: &HashMap<String, TagMatch>| tags.iter().any(|(tag, tagmatch)| get(tag).is_some_and(|value| tagmatch.matches(&value)));
+		any(&self.tags) && self.requires.iter().all(any)
+	}
+}
+
+#[derive(Debug)]
+pub enum ThemeError {
+	Read(std::io::Error),
+	Xml(roxmltree::Error),
+	NotATheme,
+	MissingAttribute { line: u32, element: String, name: &'static str },
+	BadAttribute { line: u32, name: String, value: String },
+}
+
+impl std::fmt::Display for ThemeError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			ThemeError::Read(error) => write!(f, "couldn't read: {}", error),
+			ThemeError::Xml(error) => write!(f, "{}", error),
+			ThemeError::NotATheme => write!(f, "not a render theme (the root element should be rendertheme)"),
+			ThemeError::MissingAttribute { line, element, name } => write!(f, "line {}: <{}> needs a {} attribute", line, element, name),
+			ThemeError::BadAttribute { line, name, value } => write!(f, "line {}: bad {} \"{}\"", line, name, value),
+		}
+	}
+}
+
+impl std::error::Error for ThemeError { }
+
+pub struct Theme {
+	name: String, // Tiles built with different themes are cached separately by this
+	materials: HashMap<String, Material>,
+	matchers: Vec<Matcher>,
+	// Material names from bottom to top.  Within each layer, objects are drawn in this order
+	// whatever order they come in, and materials that aren't listed go on top.
+	draw_order: Vec<String>,
+	background: Option<Color4f>,
+}
+
+impl Theme {
+	// Read a mapsforge render theme and any it includes.  Only part of the format is understood;
+	// see the xml module.
+	pub fn from_file(path: &Path) -> Result<Theme, String> {
+		// Tiles cached with an older version of any of the files shouldn't be reused
+		let newest = std::cell::Cell::new(0);
+		let parse = |file: &Path| {
+			// Errors in the file asked for get its name added by the caller, like any other error
+			let describe = |e: ThemeError| if file == path { e.to_string() } else { format!("{}: {}", file.display(), e) };
+			let text = std::fs::read_to_string(file).map_err(|e| describe(ThemeError::Read(e)))?;
+			let mtime = std::fs::metadata(file).and_then(|meta| meta.modified()).ok()
+				.and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok()).map_or(0, |time| time.as_secs());
+			newest.set(newest.get().max(mtime));
+			xml::parse(&text).map_err(describe)
+		};
+		let mut theme = load_with_includes(path, &parse)?;
+		theme.name = format!("{}@{}", path.display(), newest.get());
+		Ok(theme)
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	// All materials in the theme, sorted by name
+	pub fn materials(&self) -> Vec<(&str, &Material)> {
+		let mut ret = self.materials.iter().map(|(name, material)| (name.as_str(), material)).collect::<Vec<_>>();
+		ret.sort_by_key(|(name, _)| *name);
+		ret
+	}
+
+	// The material for a way in a tile at the given zoom level.  Its name, ref, addr:housenumber,
+	// and layer can be matched like any other tag.
+	pub fn match_way(&self, way: &Way, zoom: u8) -> Option<Material> {
+		for matcher in &self.matchers {
+			if matcher.entity_type == EntityType::Point || !matcher.applies_at(zoom) { continue; }
+			let area = way.is_area();
+			if (matcher.entity_type == EntityType::Area && !area) || (matcher.entity_type == EntityType::Path && area) { continue; }
+			if matcher.matches(|tag| way_tag(way, tag)) {
+				return self.drawn_material(&matcher.material);
+			}
+		}
+		None
+	}
+	
+	// Like match_way.  Mapsforge keeps a POI's name, house number, and elevation apart from its
+	// other tags, so they can be matched as name, addr:housenumber, and ele.
+	pub fn match_poi(&self, poi: &Poi, zoom: u8) -> Option<Material> {
+		for matcher in &self.matchers {
+			if matcher.entity_type != EntityType::Point && matcher.entity_type != EntityType::Any { continue; }
+			if !matcher.applies_at(zoom) { continue; }
+			if matcher.matches(|tag| poi_tag(poi, tag)) {
+				return self.drawn_material(&matcher.material);
+			}
+		}
+		None
+	}
+
+	// The color to clear to before drawing any tiles
+	pub fn background(&self, context: &PaintContext) -> Color4f {
+		context_color(self.background.unwrap_or(DEFAULT_BACKGROUND), context)
+	}
+
+	pub fn material(&self, name: &str) -> Option<&Material> {
+		self.materials.get(name)
+	}
+
+	// The tint for areas inside boundaries of an administrative level, such as 2 for countries,
+	// named like admin_2
+	pub fn admin_fill(&self, level: i64) -> Option<Material> {
+		self.drawn_material(&format!("admin_{}", level))
+	}
+
+	// The fill for tiles the map flags as all water, named water.  Themes without one, like most
+	// mapsforge themes, get whatever they'd use for an area tagged natural=sea.
+	pub fn water_fill(&self, zoom: u8) -> Option<Material> {
+		if self.materials.contains_key("water") { return self.drawn_material("water"); }
+		let sea = TagValue::Literal("sea".to_string());
+		self.matchers.iter()
+			.find(|matcher| matcher.entity_type != EntityType::Point && matcher.entity_type != EntityType::Path && matcher.applies_at(zoom) && matcher.matches(|tag| if tag == "natural" { Some(Cow::Borrowed(&sea)) } else { None }))
+			.and_then(|matcher| self.drawn_material(&matcher.material))
+	}
+
+	// The named material with its name and place in the draw order filled in
+	pub fn drawn_material(&self, name: &str) -> Option<Material> {
+		let rank = self.draw_order.iter().position(|x| x == name).unwrap_or(self.draw_order.len());
+		self.materials.get(name).map(|material| Material { draw_rank: rank, name: name.to_string(), ..material.clone() })
+	}
+
+	// Merge in a theme that this one includes.  Materials we already have take precedence over
+	// the included ones of the same name, and our matchers are tried before the included ones.
+	fn inherit(&mut self, base: Theme) {
+		for (name, material) in base.materials {
+			self.materials.entry(name).or_insert(material);
+		}
+		self.matchers.extend(base.matchers);
+		if self.draw_order.is_empty() { self.draw_order = base.draw_order; }
+		self.background = self.background.or(base.background);
+	}
+}
+
+// A theme as read from a single file, before anything it includes is merged in
+pub struct ThemeSource {
+	pub theme: Theme,
+	pub includes: Vec<PathBuf>, // Relative to the including file.  Earlier ones take precedence.
+}
+
+// Load a theme file along with everything it includes, using parse to read each file.  The
+// theme is named after the path.
+pub fn load_with_includes(path: &Path, parse: &dyn Fn(&Path) -> Result<ThemeSource, String>) -> Result<Theme, String> {
+	let mut theme = load_included(path, parse, &mut vec![])?;
+	theme.name = path.display().to_string();
+	Ok(theme)
+}
+
+fn load_included(path: &Path, parse: &dyn Fn(&Path) -> Result<ThemeSource, String>, stack: &mut Vec<PathBuf>) -> Result<Theme, String> {
+	let canonical = path.canonicalize().unwrap_or(path.to_path_buf());
+	if stack.contains(&canonical) {
+		let chain = stack.iter().chain(std::iter::once(&canonical)).map(|p| p.display().to_string()).collect::<Vec<_>>();
+		return Err(format!("Circular theme include: {}", chain.join(" -> ")));
+	}
+	let source = parse(path)?;
+	stack.push(canonical);
+	let mut theme = source.theme;
+	let dir = path.parent().unwrap_or(Path::new(""));
+	for include in source.includes {
+		theme.inherit(load_included(&dir.join(include), parse, stack)?);
+	}
+	stack.pop();
+	Ok(theme)
+}
+
+pub fn outline() -> Theme {
+	let materials = vec![
+		("outline".to_string(), Material { fill: None, stroke: Some(Color4f::new(1.0, 1.0, 1.0, 1.0)), ..Material::default() }),
+	].into_iter().collect::<HashMap<_, _>>();
+	let matchers = vec![Matcher { entity_type: EntityType::Any, requires: vec![], tags: HashMap::new(), material: "outline".to_string(), min_zoom: None, max_zoom: None }];
+	// White lines show up best on black
+	Theme { name: "outline".to_string(), materials, matchers, draw_order: vec![], background: Some(Color4f::new(0.0, 0.0, 0.0, 1.0)) }
+}
+
+pub fn basic() -> Theme {
+	let opacity = 0.8;
+	// Where labels overlap, lakes win over parks and parks over streets
+	let materials = vec![
+		("water_path".to_string(), Material { stroke: Some(Color4f::new(0.2, 0.2, 1.0, opacity)), fill: None, description: Some("Rivers, streams, and shorelines".to_string()), min_size: 0.0, cap: LineCap::Round, join: LineJoin::Round, ..Material::default() }),
+		("water_area".to_string(), Material { stroke: None, fill: Some(Color4f::new(0.5, 0.5, 1.0, opacity)), description: Some("Lakes and seas".to_string()), label_priority: 20, ..Material::default() }),
+		("water".to_string(), Material { stroke: None, fill: Some(Color4f::new(0.5, 0.5, 1.0, opacity)), description: Some("Open sea, where the map has no coastline".to_string()), ..Material::default() }),
+		// Bathymetry bands, shallow to deep
+		("depth_shallow".to_string(), Material { stroke: None, fill: Some(Color4f::new(0.65, 0.75, 1.0, opacity)), description: Some("Water up to 10 m deep".to_string()), ..Material::default() }),
+		("depth_medium".to_string(), Material { stroke: None, fill: Some(Color4f::new(0.4, 0.5, 0.9, opacity)), description: Some("Water 10 to 50 m deep".to_string()), ..Material::default() }),
+		("depth_deep".to_string(), Material { stroke: None, fill: Some(Color4f::new(0.2, 0.25, 0.7, opacity)), description: Some("Water over 50 m deep".to_string()), ..Material::default() }),
+		("land".to_string(), Material { stroke: None, fill: Some(Color4f::new(0.8, 0.8, 0.8, opacity)), description: Some("Land".to_string()), ..Material::default() }),
+		// Faint, since they're drawn under everything else and usually overlap
+		("admin_2".to_string(), Material { stroke: None, fill: Some(Color4f::new(1.0, 0.85, 0.4, 0.15)), description: Some("Countries, at low zoom".to_string()), ..Material::default() }),
+		("admin_4".to_string(), Material { stroke: None, fill: Some(Color4f::new(0.9, 0.5, 0.9, 0.1)), description: Some("States and provinces, at low zoom".to_string()), ..Material::default() }),
+		("road".to_string(), Material { stroke: Some(Color4f::new(0.2, 0.2, 0.2, opacity)), fill: None, description: Some("Roads and runways".to_string()), min_size: 0.0, label_priority: 10, cap: LineCap::Round, join: LineJoin::Round, ..Material::default() }),
+		("building".to_string(), Material { stroke: None, fill: Some(Color4f::new(0.6, 0.6, 0.6, opacity)), description: Some("Buildings".to_string()), min_size: 3.0, ..Material::default() }),
+		("barrier".to_string(), Material { stroke: Some(Color4f::new(0.4, 0.2, 0.2, opacity)), fill: None, description: Some("Walls and fences".to_string()), min_size: 2.0, ..Material::default() }),
+		("greenspace".to_string(), Material { stroke: None, fill: Some(Color4f::new(0.8, 1.0, 0.8, opacity)), description: Some("Parks, farmland, and natural areas".to_string()), label_priority: 15, ..Material::default() }),
+		("rail".to_string(), Material { stroke: Some(Color4f::new(0.2, 0.2, 0.8, opacity)), fill: None, description: Some("Railways".to_string()), min_size: 0.0, ..Material::default() }),
+	].into_iter().collect();
+	let matchers = vec![
+		// Depth is positive downwards, elevation negative underwater
+		Matcher {
+			entity_type: EntityType::Area,
+			requires: vec![],
+			tags: vec![
+				("depth".to_string(), TagMatch::Range { min: Some(0.0), max: Some(10.0) }),
+				("ele".to_string(), TagMatch::Range { min: Some(-10.0), max: Some(0.0) }),
+			].into_iter().collect(),
+			material: "depth_shallow".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+		Matcher {
+			entity_type: EntityType::Area,
+			requires: vec![],
+			tags: vec![
+				("depth".to_string(), TagMatch::Range { min: Some(10.0), max: Some(50.0) }),
+				("ele".to_string(), TagMatch::Range { min: Some(-50.0), max: Some(-10.0) }),
+			].into_iter().collect(),
+			material: "depth_medium".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+		Matcher {
+			entity_type: EntityType::Area,
+			requires: vec![],
+			tags: vec![
+				("depth".to_string(), TagMatch::Range { min: Some(50.0), max: None }),
+				("ele".to_string(), TagMatch::Range { min: None, max: Some(-50.0) }),
+			].into_iter().collect(),
+			material: "depth_deep".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+		Matcher {
+			entity_type: EntityType::Area,
+			requires: vec![],
+			tags: vec![
+				("natural".to_string(), TagMatch::from_values(&["sea", "water"])),
+				("waterway".to_string(), TagMatch::Present),
+			].into_iter().collect(),
+			material: "water_area".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+		Matcher {
+			entity_type: EntityType::Area,
+			requires: vec![],
+			tags: vec![
+				("natural".to_string(), TagMatch::from_values(&["nosea"])),
+			].into_iter().collect(),
+			material: "land".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+		Matcher {
+			entity_type: EntityType::Way,
+			requires: vec![],
+			tags: vec![
+				("natural".to_string(), TagMatch::from_values(&["sea", "water"])),
+				("waterway".to_string(), TagMatch::Present),
+			].into_iter().collect(),
+			material: "water_path".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+		Matcher {
+			entity_type: EntityType::Way,
+			requires: vec![],
+			tags: vec![
+				("highway".to_string(), TagMatch::Present),
+				("bridge".to_string(), TagMatch::Present),
+				("aeroway".to_string(), TagMatch::from_values(&["apron", "runway", "taxiway"])),
+			].into_iter().collect(),
+			material: "road".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+		Matcher {
+			entity_type: EntityType::Way,
+			requires: vec![],
+			tags: vec![
+				("barrier".to_string(), TagMatch::Present),
+			].into_iter().collect(),
+			material: "barrier".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+		Matcher {
+			entity_type: EntityType::Area,
+			requires: vec![],
+			tags: vec![
+				("building".to_string(), TagMatch::Present),
+			].into_iter().collect(),
+			material: "building".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+		Matcher {
+			entity_type: EntityType::Area,
+			requires: vec![],
+			tags: vec![
+				("landuse".to_string(), TagMatch::from_values(&["brownfield", "cemetery", "farm", "farmland", "farmyard", "forest", "grass", "meadow", "orchard", "recreation_ground", "village_green", "vineyard", "wood"])),
+				("leisure".to_string(), TagMatch::from_values(&["dog_park", "garden", "nature_reserve", "park", "pitch", "playground"])),
+				("natural".to_string(), TagMatch::from_values(&["grassland", "heath", "land", "marsh", "scrub", "wetland"])),
+			].into_iter().collect(),
+			material: "greenspace".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+		Matcher {
+			entity_type: EntityType::Way,
+			requires: vec![],
+			tags: vec![
+				("railway".to_string(), TagMatch::from_values(&["rail"])),
+			].into_iter().collect(),
+			material: "rail".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+	];
+	let draw_order = ["water", "land", "admin_2", "admin_4", "depth_deep", "depth_medium", "depth_shallow", "water_area", "water_path", "greenspace", "building", "barrier", "road", "rail"];
+	Theme { name: "basic".to_string(), materials, matchers, draw_order: draw_order.iter().map(|name| name.to_string()).collect(), background: None }
+}
+
+// Roads colored by who is allowed to use them, for checking routing data.  Everything else is
+// drawn faintly for context.
+pub fn access() -> Theme {
+	let opacity = 0.8;
+	let materials = vec![
+		("context".to_string(), Material { stroke: Some(Color4f::new(0.3, 0.3, 0.3, 0.5)), fill: None, description: Some("Buildings, land, and water".to_string()), ..Material::default() }),
+		("road_public".to_string(), Material { stroke: Some(Color4f::new(0.8, 0.8, 0.8, opacity)), fill: None, description: Some("Roads without restrictions".to_string()), cap: LineCap::Round, join: LineJoin::Round, ..Material::default() }),
+		("road_private".to_string(), Material { stroke: Some(Color4f::new(1.0, 0.2, 0.2, opacity)), fill: None, description: Some("No public access".to_string()), cap: LineCap::Round, join: LineJoin::Round, ..Material::default() }),
+		("road_destination".to_string(), Material { stroke: Some(Color4f::new(1.0, 0.6, 0.1, opacity)), fill: None, description: Some("Access only to reach a destination".to_string()), cap: LineCap::Round, join: LineJoin::Round, ..Material::default() }),
+		("road_no_motor".to_string(), Material { stroke: Some(Color4f::new(0.7, 0.3, 1.0, opacity)), fill: None, description: Some("Closed to motor vehicles".to_string()), cap: LineCap::Round, join: LineJoin::Round, ..Material::default() }),
+		("road_oneway".to_string(), Material { stroke: Some(Color4f::new(0.2, 0.6, 1.0, opacity)), fill: None, description: Some("One-way roads".to_string()), cap: LineCap::Round, join: LineJoin::Round, ..Material::default() }),
+	].into_iter().collect();
+	let road = |tags: &[(&str, &[&str])], material: &str| Matcher {
+		entity_type: EntityType::Way,
+		requires: vec![],
+		tags: tags.iter().map(|(tag, values)| (tag.to_string(), TagMatch::from_values(values))).collect(),
+		material: material.to_string(),
+		min_zoom: None,
+		max_zoom: None,
+	};
+	// Most restrictive first, since the first match wins
+	let matchers = vec![
+		road(&[("access", &["no", "private"])], "road_private"),
+		road(&[("access", &["destination", "delivery", "customers"]), ("motor_vehicle", &["destination", "delivery", "private"])], "road_destination"),
+		road(&[("motor_vehicle", &["no"]), ("motorcar", &["no"])], "road_no_motor"),
+		road(&[("oneway", &["yes", "-1"])], "road_oneway"),
+		Matcher {
+			entity_type: EntityType::Way,
+			requires: vec![],
+			tags: vec![("highway".to_string(), TagMatch::Present)].into_iter().collect(),
+			material: "road_public".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+		Matcher {
+			entity_type: EntityType::Any,
+			requires: vec![],
+			tags: ["building", "landuse", "natural", "railway", "waterway"].iter().map(|tag| (tag.to_string(), TagMatch::Present)).collect(),
+			material: "context".to_string(),
+			min_zoom: None,
+			max_zoom: None,
+		},
+	];
+	let draw_order = ["context", "road_public", "road_oneway", "road_no_motor", "road_destination", "road_private"];
+	Theme { name: "access".to_string(), materials, matchers, draw_order: draw_order.iter().map(|name| name.to_string()).collect(), background: Some(Color4f::new(0.0, 0.0, 0.0, 1.0)) }
+}
+
+// Materials for comparing two maps.  Features are assigned these directly rather than through
+// matchers, depending on which maps they appear in.
+pub fn diff() -> Theme {
+	let materials = vec![
+		("added".to_string(), Material { stroke: Some(Color4f::new(0.2, 1.0, 0.2, 1.0)), fill: None, description: Some("Only in the second map".to_string()), ..Material::default() }),
+		("removed".to_string(), Material { stroke: Some(Color4f::new(1.0, 0.2, 0.2, 1.0)), fill: None, description: Some("Only in the first map".to_string()), ..Material::default() }),
+		("unchanged".to_string(), Material { stroke: Some(Color4f::new(0.5, 0.5, 0.5, 0.3)), fill: None, description: Some("In both maps".to_string()), ..Material::default() }),
+	].into_iter().collect();
+	Theme { name: "diff".to_string(), materials, matchers: vec![], draw_order: vec![], background: Some(Color4f::new(0.0, 0.0, 0.0, 1.0)) }
+}
+
+#[test]
+fn test_includes() {
+	let mut files = HashMap::new();
+	files.insert("themes/main", (vec!["base"], vec![("road", 1.0), ("extra", 2.0)]));
+	files.insert("themes/base", (vec!["common/colors"], vec![("road", 3.0), ("water", 4.0)]));
+	files.insert("themes/common/colors", (vec![], vec![("water", 5.0), ("land", 6.0)]));
+	files.insert("loop/a", (vec!["b"], vec![]));
+	files.insert("loop/b", (vec!["a"], vec![]));
+	let parse = |path: &Path| {
+		let (includes, materials) = files.get(path.to_str().unwrap()).ok_or(format!("{}: not found", path.display()))?;
+		let materials = materials.iter().map(|(name, width)| (name.to_string(), Material { width: *width, ..Material::default() })).collect::<HashMap<_, _>>();
+		let matchers = materials.keys().map(|name| Matcher { entity_type: EntityType::Any, requires: vec![], tags: HashMap::new(), material: name.clone(), min_zoom: None, max_zoom: None }).collect();
+		Ok(ThemeSource { theme: Theme { name: String::new(), materials, matchers, draw_order: vec![], background: None }, includes: includes.iter().map(PathBuf::from).collect() })
+	};
+	let theme = load_with_includes(Path::new("themes/main"), &parse).unwrap();
+	let width = |name| theme.material(name).map(|material| material.width);
+	assert_eq!(width("road"), Some(1.0)); // Overridden
+	assert_eq!(width("extra"), Some(2.0));
+	assert_eq!(width("water"), Some(4.0)); // Included and overridden one level down
+	assert_eq!(width("land"), Some(6.0)); // Included two levels down
+	assert_eq!(theme.matchers.len(), 6);
+	assert!(theme.matchers[..2].iter().all(|matcher| matcher.material == "road" || matcher.material == "extra"));
+	let err = load_with_includes(Path::new("loop/a"), &parse).err().unwrap();
+	assert!(err.contains("Circular"), "{}", err);
+	assert!(load_with_includes(Path::new("themes/missing"), &parse).is_err());
+	// Theme files name what they include relative to themselves
+	let dir = std::env::temp_dir().join(format!("mapviewer-test-includes-{}", std::process::id()));
+	std::fs::create_dir_all(dir.join("common")).unwrap();
+	std::fs::write(dir.join("main.xml"), r##"<rendertheme><include src="common/base.xml" /><rule e="way" k="highway" v="*"><line stroke="#ff0000" /></rule></rendertheme>"##).unwrap();
+	std::fs::write(dir.join("common/base.xml"), r##"<rendertheme><rule e="way" k="natural" v="water"><area fill="#0000ff" /></rule></rendertheme>"##).unwrap();
+	let theme = Theme::from_file(&dir.join("main.xml")).unwrap();
+	assert!(theme.material("highway=*").is_some() && theme.material("natural=water").is_some());
+	std::fs::write(dir.join("common/base.xml"), "<rendertheme><rule>").unwrap();
+	assert!(Theme::from_file(&dir.join("main.xml")).err().unwrap().contains("base.xml"));
+	std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_luminance_order() {
+	// Contrast between the basic theme's materials should survive conversion to gray
+	let theme = basic();
+	let gray = |name: &str| {
+		let material = theme.material(name).unwrap();
+		luminance(material.fill.or(material.stroke).unwrap())
+	};
+	assert!(gray("road") < gray("building"));
+	assert!(gray("building") < gray("land"));
+	assert!(gray("water_path") < gray("water_area"));
+	assert!(gray("depth_deep") < gray("depth_medium"));
+	assert!(gray("depth_medium") < gray("depth_shallow"));
+}
+
+#[test]
+fn test_stroke_width() {
+	assert_eq!(Material::default().stroke_width(3.0), 1.0);
+	assert_eq!(Material::default().stroke_width(18.5), 1.0);
+	let material = Material { width: 2.0, width_scale: 2.0, width_ref_zoom: 10.0, width_range: (1.0, 6.0), ..Material::default() };
+	assert_eq!(material.stroke_width(10.0), 2.0);
+	assert_eq!(material.stroke_width(11.0), 4.0);
+	assert_eq!(material.stroke_width(9.0), 1.0);
+	assert_eq!(material.stroke_width(12.0), 6.0); // Clamped from 8
+	assert_eq!(material.stroke_width(5.0), 1.0); // Clamped from 1/16
+}
+
+#[test]
+fn test_dash_paint() {
+	let context = PaintContext { zoom: 14.0, grayscale: false, antialias: true };
+	let dash = |material: &Material| material.paints(&context).last().unwrap().path_effect().and_then(|effect| effect.as_a_dash()).map(|dash| dash.intervals);
+	let solid = Material { stroke: Some(Color4f::new(0.0, 0.0, 0.0, 1.0)), ..Material::default() };
+	assert_eq!(dash(&solid), None);
+	assert_eq!(dash(&Material { dash: Some(vec![6.0, 3.0]), ..solid.clone() }), Some(vec![6.0, 3.0]));
+	// An odd number of intervals is repeated to make it even
+	assert_eq!(dash(&Material { dash: Some(vec![5.0, 3.0, 2.0]), ..solid.clone() }), Some(vec![5.0, 3.0, 2.0, 5.0, 3.0, 2.0]));
+	// The fill isn't dashed, only the stroke
+	let filled = Material { fill: Some(Color4f::new(1.0, 1.0, 1.0, 1.0)), dash: Some(vec![6.0, 3.0]), ..solid };
+	assert!(filled.paints(&context).first().unwrap().path_effect().is_none());
+}
+
+#[test]
+fn test_line_style_paint() {
+	let context = PaintContext { zoom: 14.0, grayscale: false, antialias: true };
+	let style = |material: &Material| {
+		let paint = material.paints(&context).pop().unwrap();
+		(paint.stroke_cap(), paint.stroke_join())
+	};
+	let line = Material { stroke: Some(Color4f::new(0.0, 0.0, 0.0, 1.0)), ..Material::default() };
+	assert_eq!(style(&line), (paint::Cap::Butt, paint::Join::Miter));
+	assert_eq!(style(&Material { cap: LineCap::Square, join: LineJoin::Bevel, ..line.clone() }), (paint::Cap::Square, paint::Join::Bevel));
+	// Roads and rivers bend smoothly, but railways keep square ends
+	let theme = basic();
+	let style_of = |name| style(&theme.drawn_material(name).unwrap());
+	assert_eq!(style_of("road"), (paint::Cap::Round, paint::Join::Round));
+	assert_eq!(style_of("water_path"), (paint::Cap::Round, paint::Join::Round));
+	assert_eq!(style_of("rail"), (paint::Cap::Butt, paint::Join::Miter));
+}
+
+#[test]
+fn test_basic_widths() {
+	// Lines only get wider with zoom where a theme file asks for it
+	let theme = basic();
+	for (name, material) in theme.materials() {
+		let width = material.stroke_width(1.0);
+		assert!((2..=22).all(|zoom| material.stroke_width(zoom as f32) == width), "{} changes width", name);
+	}
+}
+
+#[test]
+fn test_label_origin() {
+	let text = Rect::new(0.0, -8.0, 30.0, 2.0); // As returned by measure_str: origin on the baseline
+	let point = (100.0, 50.0);
+	for anchor in &[LabelAnchor::Above, LabelAnchor::Below, LabelAnchor::Left, LabelAnchor::Right] {
+		let material = Material { label_anchor: *anchor, label_offset: 3.0, ..Material::default() };
+		let origin = material.label_origin(point, &text);
+		let label = Rect::new(origin.0 + text.left, origin.1 + text.top, origin.0 + text.right, origin.1 + text.bottom);
+		let symbol = Rect::new(point.0 - 1.0, point.1 - 1.0, point.0 + 1.0, point.1 + 1.0);
+		assert!(!label.intersects(symbol), "Label {:?} anchored {:?} overlaps symbol at {:?}", label, anchor, point);
+	}
+}
+
+#[test]
+fn test_range_match() {
+	let range = TagMatch::Range { min: Some(-10.0), max: Some(0.0) };
+	assert!(range.matches(&TagValue::Int(-10))); // Lower bound is inclusive
+	assert!(range.matches(&TagValue::Float(-0.5)));
+	assert!(!range.matches(&TagValue::Byte(0))); // Upper bound is exclusive
+	assert!(!range.matches(&TagValue::Short(-11)));
+	assert!(range.matches(&TagValue::Literal("-5".to_string())));
+	assert!(range.matches(&TagValue::String(" -2.5 ".to_string())));
+	assert!(!range.matches(&TagValue::Literal("deep".to_string())));
+	assert!(!range.matches(&TagValue::Literal("NaN".to_string())));
+	assert!(!range.matches(&TagValue::Literal("".to_string())));
+	let open = TagMatch::Range { min: Some(50.0), max: None };
+	assert!(open.matches(&TagValue::Int(i32::MAX)));
+	assert!(!open.matches(&TagValue::Float(49.9)));
+	assert!(TagMatch::Range { min: None, max: None }.matches(&TagValue::Float(f32::NEG_INFINITY)));
+}
+
+#[test]
+fn test_compare_match() {
+	let fast = TagMatch::compare(">80").unwrap();
+	assert!(fast.matches(&TagValue::Int(100)));
+	assert!(!fast.matches(&TagValue::Byte(80)));
+	assert!(fast.matches(&TagValue::Literal("90".to_string())));
+	assert!(!fast.matches(&TagValue::Literal("fast".to_string()))); // Not a number, so no match either way
+	assert!(!TagMatch::compare("<=80").unwrap().matches(&TagValue::Literal("none".to_string())));
+	let below = TagMatch::compare("< 0").unwrap();
+	assert!(below.matches(&TagValue::Short(-1)) && !below.matches(&TagValue::Float(0.0)));
+	assert!(TagMatch::compare("<=0").unwrap().matches(&TagValue::Float(0.0)));
+	assert!(TagMatch::compare(">= 2.5").unwrap().matches(&TagValue::String("2.5".to_string())));
+	assert!(TagMatch::compare("==3").unwrap().matches(&TagValue::Int(3)));
+	assert!(!TagMatch::compare("==3").unwrap().matches(&TagValue::Int(4)));
+	assert!(TagMatch::compare("80").is_none());
+	assert!(TagMatch::compare(">fast").is_none());
+	assert!(TagMatch::compare("=>5").is_none());
+}
+
+#[test]
+fn test_regex_match() {
+	let roads = TagMatch::regex("(primary|secondary)(_link)?").unwrap();
+	assert!(roads.matches(&TagValue::Literal("primary".to_string())));
+	assert!(roads.matches(&TagValue::Literal("secondary_link".to_string())));
+	assert!(!roads.matches(&TagValue::Literal("tertiary".to_string())));
+	assert!(!roads.matches(&TagValue::Literal("primary_road".to_string()))); // The whole value has to match
+	assert!(!roads.matches(&TagValue::String("primary".to_string())));
+	assert!(TagMatch::regex("(unclosed").is_err());
+}
+
+#[test]
+fn test_draw_order() {
+	let theme = basic();
+	let rank = |name| theme.drawn_material(name).unwrap().draw_rank();
+	assert!(rank("land") < rank("greenspace"));
+	assert!(rank("greenspace") < rank("road"));
+	assert!(rank("water_area") < rank("building"));
+	assert!(theme.drawn_material("nonexistent").is_none());
+	let unlisted = Theme { draw_order: vec!["road".to_string()], ..outline() };
+	assert_eq!(unlisted.drawn_material("outline").unwrap().draw_rank(), 1); // On top of everything listed
+	assert_eq!(unlisted.drawn_material("outline").unwrap().name(), "outline");
+}
+
+#[test]
+fn test_match_poi() {
+	let material = |name: &str| (name.to_string(), Material::default());
+	let matcher = |entity_type, tag: &str, tagmatch, material: &str| Matcher { entity_type, requires: vec![], tags: vec![(tag.to_string(), tagmatch)].into_iter().collect(), material: material.to_string(), min_zoom: None, max_zoom: None };
+	let theme = Theme {
+		name: "test".to_string(),
+		materials: vec![material("restaurant"), material("hospital"), material("summit"), material("named"), material("road")].into_iter().collect(),
+		matchers: vec![
+			matcher(EntityType::Path, "amenity", TagMatch::Present, "road"),
+			matcher(EntityType::Point, "amenity", TagMatch::from_values(&["restaurant"]), "restaurant"),
+			matcher(EntityType::Any, "amenity", TagMatch::from_values(&["hospital"]), "hospital"),
+			matcher(EntityType::Point, "ele", TagMatch::Range { min: Some(1000.0), max: None }, "summit"),
+			matcher(EntityType::Point, "name", TagMatch::from_values(&["Town Hall"]), "named"),
+		],
+		draw_order: vec!["hospital".to_string(), "restaurant".to_string()],
+		background: None,
+	};
+	let matched = |poi: &Poi| theme.match_poi(poi, 15).map(|material| material.draw_rank);
+	let amenity = |value: &str| ("amenity", TagValue::Literal(value.to_string()));
+	assert_eq!(matched(&Poi::with_tags(&[amenity("restaurant")], None, None, None)), Some(1));
+	assert_eq!(matched(&Poi::with_tags(&[amenity("hospital")], None, None, None)), Some(0));
+	assert_eq!(matched(&Poi::with_tags(&[amenity("bench")], None, None, None)), None); // Path matchers don't apply
+	assert!(matched(&Poi::with_tags(&[], None, None, Some(1200))).is_some());
+	assert_eq!(matched(&Poi::with_tags(&[], None, None, Some(800))), None);
+	assert!(matched(&Poi::with_tags(&[], Some("Town Hall"), None, None)).is_some());
+	assert_eq!(matched(&Poi::with_tags(&[], Some("Library"), None, None)), None);
+	// Only from zoom 14 up
+	let zoomed = Theme { matchers: vec![Matcher { min_zoom: Some(14), ..matcher(EntityType::Point, "amenity", TagMatch::Present, "restaurant") }], ..theme };
+	let bench = Poi::with_tags(&[amenity("bench")], None, None, None);
+	assert!(zoomed.match_poi(&bench, 13).is_none());
+	assert!(zoomed.match_poi(&bench, 14).is_some());
+}
+
+#[test]
+fn test_min_size() {
+	let theme = basic();
+	let big_enough = |name: &str, size: f32| theme.material(name).unwrap().big_enough(size);
+	// Roads stay visible at sizes where buildings are culled
+	assert!(big_enough("road", 2.0));
+	assert!(!big_enough("building", 2.0));
+	assert!(!big_enough("building", 3.0));
+	assert!(big_enough("building", 3.5));
+	assert!(!big_enough("greenspace", 4.0)); // The default
+	assert!(big_enough("greenspace", 5.0));
+}
+
+#[test]
+fn test_closed_ways() {
+	use super::mapsforge::{builder::MapBuilder, coord2tile, LatLon};
+	// Closed buildings, roundabouts, and fences are all areas that the theme matches, but a building
+	// whose ends don't meet isn't an area and isn't drawn at all
+	let square = [(51.5, -0.1), (51.5, -0.099), (51.501, -0.099), (51.501, -0.1), (51.5, -0.1)];
+	let map = MapBuilder::new((51.49, -0.11), (51.51, -0.09)).interval(14, 12, 16)
+		.way(14, &square, &[("building", "yes")])
+		.way(14, &square, &[("highway", "primary"), ("junction", "roundabout")])
+		.way(14, &square, &[("barrier", "fence")])
+		.way(14, &square[..3], &[("building", "yes"), ("name", "Unfinished")])
+		.open();
+	let (x, y) = coord2tile(14, LatLon::from_degrees(51.5005, -0.0995));
+	let tile = map.tile(14, x, y).unwrap();
+	let theme = basic();
+	let materials = tile.ways.iter().map(|way| (way.is_area(), theme.match_way(way, 14).map(|material| material.name().to_string()))).collect::<Vec<_>>();
+	let expect = [(true, Some("building")), (true, Some("road")), (true, Some("barrier")), (false, None)];
+	assert_eq!(materials, expect.iter().map(|(area, name)| (*area, name.map(str::to_string))).collect::<Vec<_>>());
+}
@@ -0,0 +1,340 @@
+// Mapsforge render themes.  Rules and their area, line, and caption instructions are read, along
+// with <include src="..."/> elements naming other themes to inherit from, and everything else is
+// ignored.  Rules with conditions that matchers can't express, a key that has
+// to be missing (~) or any key at all (*), are skipped along with the rules inside them.
+
+use std::collections::HashMap;
+
+use roxmltree::Node;
+use skulpin::skia_safe::Color4f;
+
+use super::{EntityType, LabelAnchor, LineCap, LineJoin, Material, Matcher, TagMatch, Theme, ThemeError, ThemeSource};
+
+fn line(node: Node) -> u32 {
+	node.document().text_pos_at(node.range().start).row
+}
+
+fn bad(node: Node, name: &str, value: &str) -> ThemeError {
+	ThemeError::BadAttribute { line: line(node), name: name.to_string(), value: value.to_string() }
+}
+
+fn required<'a>(node: Node<'a, '_>, name: &'static str) -> Result<&'a str, ThemeError> {
+	node.attribute(name).ok_or_else(|| ThemeError::MissingAttribute { line: line(node), element: node.tag_name().name().to_string(), name })
+}
+
+// Colors are #RRGGBB or #AARRGGBB
+fn color(node: Node, name: &str) -> Result<Option<Color4f>, ThemeError> {
+	let value = match node.attribute(name) {
+		Some(value) => value,
+		None => return Ok(None),
+	};
+	let hex = value.strip_prefix('#').filter(|hex| (hex.len() == 6 || hex.len() == 8) && hex.chars().all(|c| c.is_ascii_hexdigit()));
+	let hex = hex.ok_or_else(|| bad(node, name, value))?;
+	let n = u32::from_str_radix(hex, 16).unwrap();
+	let channel = |shift: u32| ((n >> shift) & 0xff) as f32 / 255.0;
+	let alpha = if hex.len() == 8 { channel(24) } else { 1.0 };
+	Ok(Some(Color4f::new(channel(16), channel(8), channel(0), alpha)))
+}
+
+fn number(node: Node, name: &str) -> Result<Option<f32>, ThemeError> {
+	node.attribute(name).map(|value| value.parse::<f32>().map_err(|_| bad(node, name, value))).transpose()
+}
+
+// Comma or space separated, as in SVG
+fn dash(node: Node, name: &str) -> Result<Option<Vec<f32>>, ThemeError> {
+	let value = match node.attribute(name) {
+		Some(value) => value,
+		None => return Ok(None),
+	};
+	let intervals = value.split(|c: char| c == ',' || c.is_whitespace()).filter(|x| !x.is_empty()).map(|x| x.parse::<f32>().ok().filter(|x| x.is_finite() && *x >= 0.0)).collect::<Option<Vec<_>>>();
+	match intervals {
+		Some(intervals) if intervals.iter().any(|x| *x > 0.0) => Ok(Some(intervals)),
+		_ => Err(bad(node, name, value)),
+	}
+}
+
+fn zoom(node: Node, name: &str) -> Result<Option<u8>, ThemeError> {
+	node.attribute(name).map(|value| value.parse::<u8>().map_err(|_| bad(node, name, value))).transpose()
+}
+
+// A rule's k and v attributes as matcher tags, or None if they can't be expressed that way.
+// Instead of v, a rule can have a v-regex attribute or a v-compare one like ">80", neither of
+// which mapsforge itself understands.
+fn condition(rule: Node) -> Result<Option<HashMap<String, TagMatch>>, ThemeError> {
+	let keys = required(rule, "k")?;
+	let tagmatch = match (rule.attribute("v-regex"), rule.attribute("v-compare")) {
+		(Some(pattern), _) => TagMatch::regex(pattern).map_err(|_| bad(rule, "v-regex", pattern))?,
+		(None, Some(comparison)) => TagMatch::compare(comparison).ok_or_else(|| bad(rule, "v-compare", comparison))?,
+		(None, None) => {
+			let values = required(rule, "v")?.split('|').collect::<Vec<_>>();
+			if values.contains(&"~") { return Ok(None); }
+			if values.contains(&"*") { TagMatch::Present } else { TagMatch::from_values(&values) }
+		},
+	};
+	if keys.split('|').any(|key| key == "*") { return Ok(None); }
+	Ok(Some(keys.split('|').map(|key| (key.to_string(), tagmatch.clone())).collect()))
+}
+
+// The e and closed attributes, which rules inherit from the rules around them unless they're "any"
+fn entity_type(element: &str, closed: &str) -> EntityType {
+	match (element, closed) {
+		("node", _) => EntityType::Point,
+		(_, "yes") => EntityType::Area,
+		(_, "no") => EntityType::Path,
+		("way", _) => EntityType::Way,
+		_ => EntityType::Any,
+	}
+}
+
+fn label_anchor(node: Node) -> Result<LabelAnchor, ThemeError> {
+	match node.attribute("position").unwrap_or("auto") {
+		"auto" | "below" => Ok(LabelAnchor::Below),
+		"above" => Ok(LabelAnchor::Above),
+		"left" => Ok(LabelAnchor::Left),
+		"right" => Ok(LabelAnchor::Right),
+		"center" => Ok(LabelAnchor::Center),
+		value => Err(bad(node, "position", value)),
+	}
+}
+
+fn line_cap(node: Node) -> Result<Option<LineCap>, ThemeError> {
+	match node.attribute("stroke-linecap") {
+		None => Ok(None),
+		Some("butt") => Ok(Some(LineCap::Butt)),
+		Some("round") => Ok(Some(LineCap::Round)),
+		Some("square") => Ok(Some(LineCap::Square)),
+		Some(value) => Err(bad(node, "stroke-linecap", value)),
+	}
+}
+
+fn line_join(node: Node) -> Result<Option<LineJoin>, ThemeError> {
+	match node.attribute("stroke-linejoin") {
+		None => Ok(None),
+		Some("miter") => Ok(Some(LineJoin::Miter)),
+		Some("round") => Ok(Some(LineJoin::Round)),
+		Some("bevel") => Ok(Some(LineJoin::Bevel)),
+		Some(value) => Err(bad(node, "stroke-linejoin", value)),
+	}
+}
+
+#[derive(Default)]
+struct Parser {
+	materials: HashMap<String, Material>,
+	matchers: Vec<Matcher>,
+	draw_order: Vec<String>,
+}
+
+impl Parser {
+	// Materials are named after the rule's condition, numbered if that's been used already
+	fn material_name(&self, rule: Node) -> String {
+		let value = rule.attribute("v").or(rule.attribute("v-regex")).or(rule.attribute("v-compare")).unwrap_or("");
+		let base = format!("{}={}", rule.attribute("k").unwrap_or(""), value);
+		let mut name = base.clone();
+		for i in 2.. {
+			if !self.draw_order.contains(&name) { break; }
+			name = format!("{} #{}", base, i);
+		}
+		name
+	}
+
+	// Rules inside others only apply at zoom levels where the outer ones do too, so zooms is the
+	// range the rules around this one leave, as from zoom-min and zoom-max
+	fn rule<'a>(&mut self, rule: Node<'a, '_>, element: &'a str, closed: &'a str, requires: &[HashMap<String, TagMatch>], zooms: (Option<u8>, Option<u8>)) -> Result<(), ThemeError> {
+		let element = match rule.attribute("e").unwrap_or("any") {
+			"any" => element,
+			value if value == "node" || value == "way" => value,
+			value => return Err(bad(rule, "e", value)),
+		};
+		let closed = match rule.attribute("closed").unwrap_or("any") {
+			"any" => closed,
+			value if value == "yes" || value == "no" => value,
+			value => return Err(bad(rule, "closed", value)),
+		};
+		let tags = match condition(rule)? {
+			Some(tags) => tags,
+			None => return Ok(()),
+		};
+		let zooms = (
+			zoom(rule, "zoom-min")?.into_iter().chain(zooms.0).max(),
+			zoom(rule, "zoom-max")?.into_iter().chain(zooms.1).min(),
+		);
+		let inner_requires = requires.iter().cloned().chain(std::iter::once(tags.clone())).collect::<Vec<_>>();
+		// The material takes its place in the draw order from its first instruction, but its
+		// matcher goes after those of the rules inside, since they're more specific
+		let mut material: Option<(String, Material)> = None;
+		for child in rule.children().filter(|node| node.is_element()) {
+			let name = child.tag_name().name();
+			if name == "rule" {
+				self.rule(child, element, closed, &inner_requires, zooms)?;
+				continue;
+			}
+			if !["area", "line", "caption"].contains(&name) { continue; }
+			if material.is_none() {
+				let material_name = self.material_name(rule);
+				self.draw_order.push(material_name.clone());
+				material = Some((material_name, Material::default()));
+			}
+			let (_, current) = material.as_mut().unwrap();
+			match name {
+				"area" => {
+					current.fill = color(child, "fill")?.or(current.fill);
+					current.stroke = color(child, "stroke")?.or(current.stroke);
+				},
+				"line" => {
+					current.stroke = Some(color(child, "stroke")?.unwrap_or(Color4f::new(0.0, 0.0, 0.0, 1.0)));
+					current.dash = dash(child, "stroke-dasharray")?;
+					current.cap = line_cap(child)?.unwrap_or(current.cap);
+					current.join = line_join(child)?.unwrap_or(current.join);
+					// Not part of the format: how much wider the line gets each zoom level in from
+					// stroke-width-zoom, and the narrowest and widest it can be
+					if let Some(scale) = number(child, "stroke-width-scale")? { current.width_scale = scale; }
+					if let Some(zoom) = number(child, "stroke-width-zoom")? { current.width_ref_zoom = zoom; }
+					if let Some(min) = number(child, "stroke-width-min")? { current.width_range.0 = min; }
+					if let Some(max) = number(child, "stroke-width-max")? { current.width_range.1 = max; }
+				},
+				_ => {
+					current.label_anchor = label_anchor(child)?;
+					if let Some(priority) = child.attribute("priority") {
+						current.label_priority = priority.parse::<i32>().map_err(|_| bad(child, "priority", priority))?;
+					}
+				},
+			}
+			if let Some(width) = number(child, "stroke-width")? { current.width = width; }
+		}
+		if let Some((name, material)) = material {
+			self.materials.insert(name.clone(), material);
+			self.matchers.push(Matcher { entity_type: entity_type(element, closed), requires: requires.to_vec(), tags, material: name, min_zoom: zooms.0, max_zoom: zooms.1 });
+		}
+		Ok(())
+	}
+}
+
+pub fn parse(text: &str) -> Result<ThemeSource, ThemeError> {
+	let doc = roxmltree::Document::parse(text).map_err(ThemeError::Xml)?;
+	let root = doc.root_element();
+	if root.tag_name().name() != "rendertheme" { return Err(ThemeError::NotATheme); }
+	let mut parser = Parser::default();
+	for rule in root.children().filter(|node| node.has_tag_name("rule")) {
+		parser.rule(rule, "any", "any", &[], (None, None))?;
+	}
+	let background = color(root, "map-background")?;
+	let includes = root.children().filter(|node| node.has_tag_name("include")).map(|node| required(node, "src").map(std::path::PathBuf::from)).collect::<Result<_, _>>()?;
+	let theme = Theme { name: String::new(), materials: parser.materials, matchers: parser.matchers, draw_order: parser.draw_order, background };
+	Ok(ThemeSource { theme, includes })
+}
+
+#[test]
+fn test_parse() {
+	use std::borrow::Cow;
+	use crate::mapsforge::{Poi, TagValue};
+	let text = r##"<?xml version="1.0" encoding="UTF-8"?>
+		<rendertheme xmlns="http://mapsforge.org/renderTheme" version="5" map-background="#f8f8f8">
+			<rule e="way" k="highway" v="*" zoom-max="18">
+				<rule e="any" k="highway" v="primary|secondary" zoom-min="8" zoom-max="20">
+					<line stroke="#ff8000" stroke-width="2.5" stroke-width-scale="1.5" stroke-width-zoom="14" stroke-width-max="5" />
+				</rule>
+				<rule e="way" k="bridge" v="yes">
+					<line stroke="#80000000" stroke-dasharray="6, 3" stroke-linecap="butt" />
+				</rule>
+				<line stroke="#808080" />
+			</rule>
+			<rule e="way" k="natural" v="water" closed="yes">
+				<area fill="#0000ff" />
+			</rule>
+			<rule e="way" k="area" v="~|no">
+				<area fill="#ff0000" />
+			</rule>
+			<rule e="way" k="maxspeed" v-compare="&gt;=100">
+				<line stroke="#ff0000" stroke-width="3" />
+			</rule>
+			<rule e="node" k="amenity" v-regex="restaurant|cafe" zoom-min="16">
+				<caption k="name" position="above" priority="5" />
+				<symbol src="assets/food.svg" />
+			</rule>
+		</rendertheme>"##;
+	let theme = parse(text).unwrap().theme;
+	assert_eq!(theme.draw_order, vec!["highway=primary|secondary", "bridge=yes", "highway=*", "natural=water", "maxspeed=>=100", "amenity=restaurant|cafe"]);
+	assert_eq!(theme.background, Some(Color4f::new(248.0 / 255.0, 248.0 / 255.0, 248.0 / 255.0, 1.0)));
+	let road = &theme.materials["highway=primary|secondary"];
+	assert_eq!((road.stroke, road.width), (Some(Color4f::new(1.0, 128.0 / 255.0, 0.0, 1.0)), 2.5));
+	assert_eq!(theme.materials["bridge=yes"].stroke.map(|color| color.a), Some(128.0 / 255.0));
+	assert_eq!(theme.materials["bridge=yes"].dash, Some(vec![6.0, 3.0]));
+	assert_eq!(road.dash, None);
+	assert_eq!((road.stroke_width(14.0), road.stroke_width(15.0), road.stroke_width(18.0)), (2.5, 3.75, 5.0));
+	assert_eq!(theme.materials["highway=*"].stroke_width(18.0), 1.0);
+	assert_eq!((road.cap, road.join), (LineCap::Butt, LineJoin::Miter));
+	assert_eq!(theme.materials["bridge=yes"].cap, LineCap::Butt);
+	assert!(theme.materials["natural=water"].fill.is_some());
+	// Inner rules come first and need the outer rules' conditions as well as their own
+	let materials = theme.matchers.iter().map(|matcher| matcher.material.as_str()).collect::<Vec<_>>();
+	assert_eq!(materials, vec!["highway=primary|secondary", "bridge=yes", "highway=*", "natural=water", "maxspeed=>=100", "amenity=restaurant|cafe"]);
+	let way = |tags: &[(&str, &str)]| {
+		let tags = tags.iter().map(|(key, value)| (key.to_string(), TagValue::Literal(value.to_string()))).collect::<HashMap<_, _>>();
+		theme.matchers.iter().find(|matcher| matcher.entity_type != EntityType::Point && matcher.matches(|tag| tags.get(tag).cloned().map(Cow::Owned))).map(|matcher| matcher.material.clone())
+	};
+	assert_eq!(way(&[("highway", "primary")]).as_deref(), Some("highway=primary|secondary"));
+	assert_eq!(way(&[("highway", "track"), ("bridge", "yes")]).as_deref(), Some("bridge=yes"));
+	assert_eq!(way(&[("bridge", "yes")]), None);
+	assert_eq!(way(&[("highway", "track")]).as_deref(), Some("highway=*"));
+	assert_eq!(way(&[("maxspeed", "120")]).as_deref(), Some("maxspeed=>=100"));
+	assert_eq!(way(&[("maxspeed", "50")]), None);
+	assert_eq!(way(&[("maxspeed", "signals")]), None);
+	assert!(theme.matchers.iter().find(|matcher| matcher.material == "natural=water").unwrap().entity_type == EntityType::Area);
+	// Zoom ranges narrow down from the rules outside
+	let zooms = |material: &str| theme.matchers.iter().find(|matcher| matcher.material == material).map(|matcher| (matcher.min_zoom, matcher.max_zoom)).unwrap();
+	assert_eq!(zooms("highway=primary|secondary"), (Some(8), Some(18)));
+	assert_eq!(zooms("highway=*"), (None, Some(18)));
+	assert_eq!(zooms("natural=water"), (None, None));
+	let cafe = Poi::with_tags(&[("amenity", TagValue::Literal("cafe".to_string()))], Some("Corner Cafe"), None, None);
+	assert_eq!(theme.match_poi(&cafe, 17).map(|material| material.label_anchor), Some(LabelAnchor::Above));
+	assert_eq!(theme.match_poi(&cafe, 17).map(|material| material.label_priority), Some(5));
+	assert!(theme.match_poi(&Poi::with_tags(&[("amenity", TagValue::Literal("bank".to_string()))], None, None, None), 17).is_none());
+	assert!(theme.match_poi(&cafe, 15).is_none()); // Too far out
+	assert!(matches!(parse("<rendertheme><rule e=\"way\" k=\"highway\"><line /></rule></rendertheme>"), Err(ThemeError::MissingAttribute { line: 1, name: "v", .. })));
+	assert!(matches!(parse("<rendertheme>\n<rule e=\"way\" k=\"a\" v=\"b\"><area fill=\"blue\" /></rule></rendertheme>"), Err(ThemeError::BadAttribute { line: 2, .. })));
+	assert!(matches!(parse("<rendertheme><rule e=\"way\" k=\"lanes\" v-compare=\"many\"><line /></rule></rendertheme>"), Err(ThemeError::BadAttribute { line: 1, .. })));
+	assert!(matches!(parse("<rendertheme><rule e=\"way\" k=\"a\" v=\"b\" zoom-min=\"-1\"><line /></rule></rendertheme>"), Err(ThemeError::BadAttribute { line: 1, .. })));
+	assert!(matches!(parse("<rendertheme><rule e=\"way\" k=\"a\" v=\"b\"><line stroke-dasharray=\"0,0\" /></rule></rendertheme>"), Err(ThemeError::BadAttribute { line: 1, .. })));
+	assert!(matches!(parse("<rendertheme><rule e=\"way\" k=\"a\" v=\"b\"><line stroke-linejoin=\"sharp\" /></rule></rendertheme>"), Err(ThemeError::BadAttribute { line: 1, .. })));
+	assert!(matches!(parse("<osm />"), Err(ThemeError::NotATheme)));
+	let source = parse("<rendertheme><include src=\"base.xml\" /><include src=\"../common/water.xml\" /></rendertheme>").unwrap();
+	assert_eq!(source.includes, vec![std::path::PathBuf::from("base.xml"), std::path::PathBuf::from("../common/water.xml")]);
+	assert!(matches!(parse("<rendertheme><include /></rendertheme>"), Err(ThemeError::MissingAttribute { name: "src", .. })));
+	assert!(matches!(parse("<rendertheme><rule></rendertheme>"), Err(ThemeError::Xml(_))));
+}
+
+#[test]
+fn test_way_fields() {
+	use crate::mapsforge::{builder::MapBuilder, coord2tile, LatLon};
+	// Mapsforge keeps these out of the way's tags, but rules can match them all the same
+	let theme = parse(r##"
+		<rendertheme>
+			<rule e="way" k="layer" v-compare="&lt;0">
+				<line stroke="#808080" />
+			</rule>
+			<rule e="way" k="name" v="High Street">
+				<line stroke="#ff0000" />
+			</rule>
+		</rendertheme>"##).unwrap().theme;
+	let line = [(51.5, -0.1), (51.501, -0.099)];
+	let map = MapBuilder::new((51.49, -0.11), (51.51, -0.09)).interval(14, 12, 16)
+		.way(14, &line, &[("highway", "primary"), ("layer", "-1")])
+		.way(14, &line, &[("highway", "primary"), ("layer", "1")])
+		.way(14, &line, &[("highway", "primary"), ("name", "High Street")])
+		.open();
+	let (x, y) = coord2tile(14, LatLon::from_degrees(51.5005, -0.0995));
+	let tile = map.tile(14, x, y).unwrap();
+	let materials = tile.ways.iter().map(|way| theme.match_way(way, 14).map(|material| material.name().to_string())).collect::<Vec<_>>();
+	assert_eq!(materials, vec![Some("layer=<0".to_string()), None, Some("name=High Street".to_string())]);
+}
+
+#[test]
+fn test_background() {
+	use super::{basic, PaintContext, DEFAULT_BACKGROUND};
+	let context = PaintContext { zoom: 14.0, grayscale: false, antialias: true };
+	assert_eq!(basic().background(&context), DEFAULT_BACKGROUND);
+	// Without map-background a theme file gets the default too
+	assert_eq!(parse("<rendertheme />").unwrap().theme.background(&context), DEFAULT_BACKGROUND);
+	let theme = parse(r##"<rendertheme map-background="#f8f8f8" />"##).unwrap().theme;
+	assert_eq!(theme.background(&context), Color4f::new(248.0 / 255.0, 248.0 / 255.0, 248.0 / 255.0, 1.0));
+}
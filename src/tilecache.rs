@@ -0,0 +1,138 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+use super::render::RenderTile;
+
+// Bump this whenever cached tiles would come out differently, because RenderTile or the way tiles
+// are built has changed, or one of the built-in themes has.  Their names stay the same, so nothing
+// else would tell old tiles apart.
+const FORMAT_VERSION: u32 = 1;
+
+// FNV-1a, which unlike DefaultHasher is guaranteed to give the same directory names in every
+// build, so caches outlive upgrades
+fn stable_hash(data: &[u8]) -> u64 {
+	data.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// Keeps built tiles on disk between sessions.  Each map and style, which is the theme along with
+// anything else that changes how tiles are built, gets its own directory,
+// containing one directory named for the map's modification time, under which tiles are stored
+// by zoom and position.  When the map changes, the old tiles are thrown away the next time one
+// is looked for and isn't there.
+pub struct DiskCache {
+	dir: PathBuf,
+	hits: AtomicUsize,
+	misses: AtomicUsize,
+}
+
+impl DiskCache {
+	pub fn new(dir: &Path) -> io::Result<Self> {
+		fs::create_dir_all(dir)?;
+		Ok(Self { dir: dir.to_path_buf(), hits: AtomicUsize::new(0), misses: AtomicUsize::new(0) })
+	}
+
+	// The directory for a map and style, and the subdirectory for the current version of the map
+	fn map_dir(&self, map: &Path, theme: &str) -> io::Result<(PathBuf, PathBuf)> {
+		let mtime = fs::metadata(map)?.modified()?.duration_since(UNIX_EPOCH).map_err(io::Error::other)?;
+		let map = map.canonicalize().unwrap_or(map.to_path_buf());
+		let key = format!("{}\0{}\0{}\0{}", FORMAT_VERSION, env!("CARGO_PKG_VERSION"), map.display(), theme);
+		let base = self.dir.join(format!("{:016x}", stable_hash(key.as_bytes())));
+		let version = base.join(format!("{}.{:09}", mtime.as_secs(), mtime.subsec_nanos()));
+		Ok((base, version))
+	}
+
+	fn tile_path(version: &Path, zoom: u8, x: u32, y: u32) -> PathBuf {
+		version.join(zoom.to_string()).join(format!("{}_{}.bin", x, y))
+	}
+
+	pub fn load(&self, map: &Path, theme: &str, zoom: u8, x: u32, y: u32) -> Option<RenderTile> {
+		let (base, version) = self.map_dir(map, theme).ok()?;
+		if !version.exists() {
+			// Anything else here is for an older version of the map.  Another thread may be
+			// storing the first tile of this version as we look, so its directory is left alone.
+			for entry in fs::read_dir(&base).into_iter().flatten().flatten() {
+				if entry.path() != version { let _ = fs::remove_dir_all(entry.path()); }
+			}
+			return None;
+		}
+		let data = fs::read(Self::tile_path(&version, zoom, x, y)).ok()?;
+		bincode::deserialize(&data).ok()
+	}
+
+	pub fn store(&self, map: &Path, theme: &str, zoom: u8, x: u32, y: u32, tile: &RenderTile) -> io::Result<()> {
+		let (_, version) = self.map_dir(map, theme)?;
+		let path = Self::tile_path(&version, zoom, x, y);
+		fs::create_dir_all(path.parent().unwrap())?;
+		let data = bincode::serialize(tile).map_err(io::Error::other)?;
+		// Write to the side and rename so that nobody reads a half-written tile
+		let temp = path.with_extension(format!("tmp{:?}", std::thread::current().id()).replace(|c: char| !c.is_ascii_alphanumeric(), ""));
+		fs::write(&temp, data)?;
+		fs::rename(&temp, &path)
+	}
+
+	// Load the tile if it's cached, otherwise build it and save it for next time
+	pub fn load_or_build(&self, map: &Path, theme: &str, zoom: u8, x: u32, y: u32, build: impl FnOnce() -> RenderTile) -> RenderTile {
+		if let Some(tile) = self.load(map, theme, zoom, x, y) {
+			self.hits.fetch_add(1, Ordering::Relaxed);
+			return tile;
+		}
+		self.misses.fetch_add(1, Ordering::Relaxed);
+		let tile = build();
+		if let Err(e) = self.store(map, theme, zoom, x, y, &tile) {
+			eprintln!("Couldn't cache zoom {} tile {:?} of {}: {}", zoom, (x, y), map.display(), e);
+		}
+		tile
+	}
+
+	// How many tiles have been loaded from the cache and how many had to be built
+	pub fn stats(&self) -> (usize, usize) {
+		(self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+	}
+
+	// Like stats, but starts counting again from zero
+	pub fn take_stats(&self) -> (usize, usize) {
+		(self.hits.swap(0, Ordering::Relaxed), self.misses.swap(0, Ordering::Relaxed))
+	}
+}
+
+#[test]
+fn test_disk_cache() {
+	use std::collections::BTreeMap;
+	use super::render::{Geometry, Object, TileGrid};
+	use super::mapsforge::Coord;
+	let dir = std::env::temp_dir().join(format!("mapviewer-test-cache-{}", std::process::id()));
+	let map = dir.join("test.map");
+	fs::create_dir_all(&dir).unwrap();
+	fs::write(&map, b"map").unwrap();
+	let cache = DiskCache::new(&dir.join("cache")).unwrap();
+	let mut layers = BTreeMap::new();
+	layers.insert(-1, vec![Object { geo: Geometry::Point(Coord { x: 5, y: -7 }), name: Some("Here".to_string()), tags: Default::default(), material: Default::default(), subtiles: 0, label_pos: None }]);
+	let tile = RenderTile { zoom: 12, x: 34, y: 56, layers, truncated: false, pois: vec![], heights: vec![], covered: true, grid: TileGrid::MERCATOR, map: 0 };
+	let loaded = |cache: &DiskCache| cache.load_or_build(&map, "basic", 12, 34, 56, || RenderTile { zoom: 0, x: 0, y: 0, layers: BTreeMap::new(), truncated: true, pois: vec![], heights: vec![], covered: true, grid: TileGrid::MERCATOR, map: 0 });
+	cache.store(&map, "basic", 12, 34, 56, &tile).unwrap();
+	let warm = loaded(&cache);
+	assert_eq!((warm.zoom, warm.x, warm.y, warm.truncated), (12, 34, 56, false));
+	assert!(matches!(&warm.layers[&-1][..], [Object { geo: Geometry::Point(Coord { x: 5, y: -7 }), .. }]));
+	assert_eq!(cache.stats(), (1, 0));
+	assert!(cache.load(&map, "access", 12, 34, 56).is_none()); // Other themes are separate
+	// Directory names mustn't depend on the build
+	assert_eq!(stable_hash(b""), 0xcbf29ce484222325);
+	assert_eq!(stable_hash(b"a"), 0xaf63dc4c8601ec8c);
+	// Touching the map invalidates everything cached for it
+	fs::File::options().write(true).open(&map).unwrap().set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(10)).unwrap();
+	assert!(loaded(&cache).truncated);
+	assert_eq!(cache.stats(), (1, 1));
+	assert_eq!(cache.take_stats(), (1, 1));
+	assert_eq!(cache.stats(), (0, 0));
+	// Tiles of a new version of the map being looked for and stored at once all make it in
+	fs::File::options().write(true).open(&map).unwrap().set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(20)).unwrap();
+	let (cache, map, tile) = (&cache, &map, &tile);
+	std::thread::scope(|scope| {
+		for x in 0 .. 8 { scope.spawn(move || cache.load_or_build(map, "basic", 12, x, 56, || RenderTile { x: x as i64, ..tile.clone() })); }
+	});
+	assert!((0 .. 8).all(|x| cache.load(map, "basic", 12, x, 56).is_some()));
+	fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,227 @@
+use mapviewer::mapsforge::{self, Coord};
+use mapviewer::projection::EARTH_RADIUS;
+use mapviewer::render::BoundingBox;
+
+// Converts between map coordinates and screen pixels
+// TODO The view can't be rotated yet, so north is always up.  Once it can, Shift with the rotation
+// keys should snap to 15°, 45°, and 90° steps, and another key should snap back to the nearest
+// cardinal direction, keeping the center of the screen where it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+	pub offset: Coord, // Map coordinates of the top left corner of the screen
+	pub scale: u32, // Coord units per pixel -- larger is zooming out
+}
+
+impl Transform {
+	// Center a point in a window of the given size at a zoom level as given by zoom_level
+	pub fn centered(center: Coord, zoom: f64, tile_size: f64, size: (u32, u32)) -> Self {
+		let scale = (mapsforge::COORD_MAX as f64 / (tile_size * zoom.exp2())).round().clamp(1.0, u32::MAX as f64) as u32;
+		Self::around(center, scale, size)
+	}
+
+	// Center a point in a window of the given size at the given scale
+	fn around(center: Coord, scale: u32, size: (u32, u32)) -> Self {
+		Self { offset: Coord { x: center.x - (size.0 / 2) as i64 * scale as i64, y: center.y - (size.1 / 2) as i64 * scale as i64 }, scale }
+	}
+
+	// Partway from this view to another, with t from 0 to 1.  The center moves in a straight line
+	// while the scale changes by the same factor each step, so zooming looks steady.
+	pub fn interpolate(&self, to: &Transform, t: f64, size: (u32, u32)) -> Self {
+		let (from_center, to_center) = (self.center(size), to.center(size));
+		let center = Coord {
+			x: from_center.x + ((to_center.x - from_center.x) as f64 * t).round() as i64,
+			y: from_center.y + ((to_center.y - from_center.y) as f64 * t).round() as i64,
+		};
+		let scale = (self.scale as f64).powf(1.0 - t) * (to.scale as f64).powf(t);
+		Self::around(center, (scale.round() as u32).max(1), size)
+	}
+
+	// Like interpolate, but for zooming about a pixel instead of the center.  The map position under
+	// that pixel moves in a straight line, so if it's the same at both ends it stays put.
+	pub fn interpolate_about(&self, to: &Transform, t: f64, pixel: (u32, u32)) -> Self {
+		let pixel = (pixel.0 as i32, pixel.1 as i32);
+		let (from_anchor, to_anchor) = (self.to_world(pixel), to.to_world(pixel));
+		let anchor = Coord {
+			x: from_anchor.x + ((to_anchor.x - from_anchor.x) as f64 * t).round() as i64,
+			y: from_anchor.y + ((to_anchor.y - from_anchor.y) as f64 * t).round() as i64,
+		};
+		let scale = ((self.scale as f64).powf(1.0 - t) * (to.scale as f64).powf(t)).round().max(1.0) as u32;
+		Self { offset: Coord { x: anchor.x - pixel.0 as i64 * scale as i64, y: anchor.y - pixel.1 as i64 * scale as i64 }, scale }
+	}
+
+	// Center the bounds in a window of the given size, as large as they will fit
+	pub fn fit(bounds: &BoundingBox, size: (u32, u32)) -> Self {
+		let scale = (bounds.width() as u32 / size.0).max(bounds.height() as u32 / size.1).max(1);
+		let viewport_adj = Coord { x: -(scale as i64 * size.0 as i64) / 2, y: -(scale as i64 * size.1 as i64) / 2 };
+		Self { offset: bounds.midpoint().unwrap().add(&viewport_adj), scale }
+	}
+
+	pub fn to_screen(self, point: Coord) -> Coord {
+		// Round towards negative infinity so that points just off the top or left of the screen
+		// don't land on the first row or column
+		Coord { x: (point.x - self.offset.x).div_euclid(self.scale as i64), y: (point.y - self.offset.y).div_euclid(self.scale as i64) }
+	}
+
+	// The map coordinates of the top left corner of a pixel
+	pub fn to_world(self, pixel: (i32, i32)) -> Coord {
+		Coord { x: self.offset.x + pixel.0 as i64 * self.scale as i64, y: self.offset.y + pixel.1 as i64 * self.scale as i64 }
+	}
+
+	// The zoom level at which web map tiles of the given size in pixels would show about the same
+	// amount of detail
+	pub fn zoom_level(&self, tile_size: f64) -> f64 {
+		(mapsforge::COORD_MAX as f64 / (tile_size * self.scale as f64)).log2()
+	}
+
+	pub fn center(&self, size: (u32, u32)) -> Coord {
+		self.to_world((size.0 as i32 / 2, size.1 as i32 / 2))
+	}
+
+	// Meters on the ground per pixel at the center of the screen.  Mercator stretches things by
+	// more the further they are from the equator, so this shrinks with the cosine of the latitude.
+	pub fn meters_per_pixel(&self, size: (u32, u32)) -> f64 {
+		let (lat, _) = self.center(size).to_latlon().degrees();
+		self.scale as f64 * 2.0 * std::f64::consts::PI * EARTH_RADIUS * lat.to_radians().cos() / mapsforge::COORD_MAX as f64
+	}
+
+	pub fn viewport(&self, size: (u32, u32)) -> BoundingBox {
+		BoundingBox::from_corners((self.offset, self.to_world((size.0 as i32, size.1 as i32))))
+	}
+
+	// Zoom in by the given factor, keeping the point under the center pixel where it is
+	pub fn zoom(&mut self, factor: f64, center: (u32, u32)) {
+		let anchor = self.to_world((center.0 as i32, center.1 as i32));
+		self.scale = ((self.scale as f64 / factor).round() as u32).max(1);
+		self.offset = Coord { x: anchor.x - center.0 as i64 * self.scale as i64, y: anchor.y - center.1 as i64 * self.scale as i64 };
+	}
+
+	// Move the map by the given number of pixels
+	pub fn pan(&mut self, delta: (i32, i32)) {
+		self.offset = Coord {
+			x: self.offset.x - delta.0 as i64 * self.scale as i64,
+			y: self.offset.y - delta.1 as i64 * self.scale as i64,
+		};
+	}
+
+	// The same view shifted a whole number of times around the world so that the center of a
+	// window of the given size is on the map.  East and west of the map is the map again, so this
+	// looks no different, but it keeps panning from running off the coordinate space.
+	pub fn wrapped(&self, size: (u32, u32)) -> Self {
+		let laps = self.center(size).x.div_euclid(mapsforge::COORD_MAX);
+		Self { offset: Coord { x: self.offset.x - laps * mapsforge::COORD_MAX, y: self.offset.y }, ..*self }
+	}
+
+	// The same view shifted a whole number of times around the world so that the point is as close
+	// as it can be to the center of a window of the given size.  Things kept in map coordinates are
+	// drawn through this so that they show up on the copy of the map the view is looking at.
+	pub fn nearest_lap(&self, point: Coord, size: (u32, u32)) -> Self {
+		let laps = (point.x - self.center(size).x + mapsforge::COORD_MAX / 2).div_euclid(mapsforge::COORD_MAX);
+		Self { offset: Coord { x: self.offset.x + laps * mapsforge::COORD_MAX, y: self.offset.y }, ..*self }
+	}
+}
+
+#[test]
+fn test_tile_corners() {
+	let xform = Transform { offset: Coord { x: 1000, y: -500 }, scale: 10 };
+	let tile = BoundingBox::from_corners((Coord { x: 1000, y: -500 }, Coord { x: 3560, y: 2060 }));
+	let (topleft, botright) = tile.corners().unwrap();
+	assert_eq!(xform.to_screen(topleft), Coord { x: 0, y: 0 });
+	assert_eq!(xform.to_screen(botright), Coord { x: 256, y: 256 });
+	assert_eq!(xform.to_screen(Coord { x: 999, y: -501 }), Coord { x: -1, y: -1 }); // Not 0
+}
+
+#[test]
+fn test_pan() {
+	let mut xform = Transform { offset: Coord { x: -12345, y: 67890 }, scale: 7 };
+	let point = Coord { x: 5000, y: 80000 };
+	let before = xform.to_screen(point);
+	xform.pan((30, -45));
+	let after = xform.to_screen(point);
+	assert_eq!((after.x - before.x, after.y - before.y), (30, -45));
+}
+
+#[test]
+fn test_zoom_anchor() {
+	let mut xform = Transform { offset: Coord { x: 100000, y: 200000 }, scale: 1000 };
+	let center = (317, 211);
+	let anchor = xform.to_world((center.0 as i32, center.1 as i32));
+	for factor in &[1.2, 1.2, 1.0 / 1.2, 3.0, 0.1] {
+		xform.zoom(*factor, center);
+		assert_eq!(xform.to_world((center.0 as i32, center.1 as i32)), anchor);
+	}
+	// Zooming in as far as possible still leaves a usable transform
+	xform.zoom(1e9, center);
+	assert_eq!(xform.scale, 1);
+	assert_eq!(xform.to_world((center.0 as i32, center.1 as i32)), anchor);
+}
+
+#[test]
+fn test_centered() {
+	let center = Coord { x: 1 << 31, y: 3 << 29 };
+	let xform = Transform::centered(center, 12.5, 256.0, (800, 601));
+	assert!((xform.zoom_level(256.0) - 12.5).abs() < 0.01);
+	assert_eq!(xform.center((800, 601)), center);
+}
+
+#[test]
+fn test_interpolate() {
+	let size = (800, 600);
+	let from = Transform::around(Coord { x: 1000, y: 2000 }, 100, size);
+	let to = Transform::around(Coord { x: 5000, y: -2000 }, 400, size);
+	assert_eq!(from.interpolate(&to, 0.0, size), from);
+	assert_eq!(from.interpolate(&to, 1.0, size), to);
+	let half = from.interpolate(&to, 0.5, size);
+	assert_eq!(half.scale, 200);
+	assert_eq!(half.center(size), Coord { x: 3000, y: 0 });
+}
+
+#[test]
+fn test_meters_per_pixel() {
+	let size = (800, 600);
+	// Zoom 0 web map tiles are 256 pixels across the whole equator
+	let equator = Transform::centered(Coord { x: 1 << 31, y: 1 << 31 }, 0.0, 256.0, size);
+	assert!((equator.meters_per_pixel(size) - 156543.03).abs() < 0.1);
+	let north = Transform::centered(mapsforge::LatLon::from_degrees(60.0, 10.0).to_coord(), 0.0, 256.0, size);
+	assert!((north.meters_per_pixel(size) / equator.meters_per_pixel(size) - 0.5).abs() < 1e-4);
+}
+
+#[test]
+fn test_interpolate_about() {
+	let pixel = (317, 211);
+	let from = Transform { offset: Coord { x: 100000, y: 200000 }, scale: 1000 };
+	let mut to = from;
+	to.zoom(4.0, pixel);
+	assert_eq!(from.interpolate_about(&to, 0.0, pixel), from);
+	assert_eq!(from.interpolate_about(&to, 1.0, pixel), to);
+	let half = from.interpolate_about(&to, 0.5, pixel);
+	assert_eq!(half.scale, 500);
+	assert_eq!(half.to_world((pixel.0 as i32, pixel.1 as i32)), from.to_world((pixel.0 as i32, pixel.1 as i32)));
+}
+
+#[test]
+fn test_round_trip() {
+	let xform = Transform { offset: Coord { x: -7777, y: 3 }, scale: 13 };
+	for pixel in &[(0, 0), (1, 1), (-20, 45), (799, 599)] {
+		let screen = xform.to_screen(xform.to_world(*pixel));
+		assert_eq!((screen.x as i32, screen.y as i32), *pixel);
+	}
+}
+
+#[test]
+fn test_wrapped() {
+	let size = (800, 600);
+	let xform = Transform { offset: Coord { x: mapsforge::COORD_MAX - 100 * 1000, y: 5000 }, scale: 1000 };
+	// The center is past the antimeridian, so it comes round to the west end of the map
+	let wrapped = xform.wrapped(size);
+	assert_eq!(wrapped.offset, Coord { x: -100 * 1000, y: 5000 });
+	assert_eq!(wrapped.center(size).x, 300 * 1000);
+	assert_eq!(wrapped.wrapped(size), wrapped);
+	let west = Transform { offset: Coord { x: -3 * mapsforge::COORD_MAX, y: 0 }, scale: 1 };
+	assert_eq!(west.wrapped(size).offset.x, 0);
+	// A point just west of the antimeridian shows up by the west edge of the wrapped view, which is
+	// east of it
+	let point = Coord { x: mapsforge::COORD_MAX - 50 * 1000, y: 5000 };
+	assert_eq!(wrapped.nearest_lap(point, size).to_screen(point), Coord { x: 50, y: 0 });
+	assert_eq!(xform.nearest_lap(point, size), xform);
+	assert_eq!(wrapped.nearest_lap(Coord { x: 300 * 1000, y: 0 }, size), wrapped);
+}
@@ -0,0 +1,104 @@
+use mapviewer::mapsforge::{LatLon, LAT_MAX};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+	pub center: LatLon,
+	pub zoom: Option<f64>, // Left as it is if not given
+}
+
+// Views are shared the same way as on openstreetmap.org, as "#zoom/lat/lon".  Zoom levels in
+// between the usual whole ones are kept to two decimal places so the view comes back the same.
+pub fn fragment(center: LatLon, zoom: f64) -> String {
+	let (lat, lon) = center.degrees();
+	let zoom = format!("{:.2}", zoom);
+	let zoom = zoom.trim_end_matches('0').trim_end_matches('.');
+	format!("#{}/{:.5}/{:.5}", zoom, lat, lon)
+}
+
+fn number(text: &str, what: &str) -> Result<f64, String> {
+	text.trim().parse::<f64>().ok().filter(|x| x.is_finite()).ok_or(format!("Bad {} \"{}\"", what, text))
+}
+
+fn zoom_level(text: &str) -> Result<f64, String> {
+	let zoom = number(text, "zoom")?;
+	if !(0.0..=30.0).contains(&zoom) { return Err(format!("Zoom {} is out of range", zoom)); }
+	Ok(zoom)
+}
+
+fn view(lat: &str, lon: &str, zoom: Option<&str>) -> Result<View, String> {
+	let (lat, lon) = (number(lat, "latitude")?, number(lon, "longitude")?);
+	if lat.abs() > 90.0 || lon.abs() > 180.0 { return Err(format!("{},{} is not on the earth", lat, lon)); }
+	let zoom = zoom.map(zoom_level).transpose()?;
+	Ok(View { center: LatLon::from_degrees(lat, lon), zoom })
+}
+
+// A plain "lat,lon" for --center.  Unlike links, which can point anywhere, this has to be within
+// the latitudes the map can show.
+pub fn parse_center(text: &str) -> Result<LatLon, String> {
+	let err = |e: String| format!("--center {}: {}", text, e);
+	let (lat, lon) = text.split_once(',').ok_or_else(|| err("expected lat,lon".to_string()))?;
+	let (lat, lon) = (number(lat, "latitude").map_err(err)?, number(lon, "longitude").map_err(err)?);
+	if lat.abs() > LAT_MAX || lon.abs() > 180.0 { return Err(err(format!("must be within ±{} latitude and ±180 longitude", LAT_MAX))); }
+	Ok(LatLon::from_degrees(lat, lon))
+}
+
+pub fn parse_zoom(text: &str) -> Result<f64, String> {
+	zoom_level(text).map_err(|e| format!("--zoom: {}", e))
+}
+
+// Accepts fragments as made by fragment(), optionally with the rest of an openstreetmap.org URL
+// in front, and geo: URIs with an optional z parameter for the zoom
+pub fn parse(url: &str) -> Result<View, String> {
+	let url = url.trim();
+	let err = |e: String| format!("{}: {}", url, e);
+	if let Some(geo) = url.strip_prefix("geo:") {
+		let (coords, params) = geo.split_once('?').unwrap_or((geo, ""));
+		// There may be an altitude and parameters like ;crs=... after the coordinates
+		let coords = coords.split(';').next().unwrap().split(',').collect::<Vec<_>>();
+		let zoom = params.split('&').find_map(|param| param.strip_prefix("z="));
+		match coords.as_slice() {
+			[lat, lon] | [lat, lon, _] => view(lat, lon, zoom).map_err(err),
+			_ => Err(err("expected geo:lat,lon".to_string())),
+		}
+	}
+	else if let Some((_, fragment)) = url.split_once('#') {
+		let fragment = fragment.strip_prefix("map=").unwrap_or(fragment);
+		match fragment.split('/').collect::<Vec<_>>().as_slice() {
+			[zoom, lat, lon] => view(lat, lon, Some(zoom)).map_err(err),
+			_ => Err(err("expected #zoom/lat/lon".to_string())),
+		}
+	}
+	else { Err(err("expected #zoom/lat/lon or geo:lat,lon".to_string())) }
+}
+
+#[test]
+fn test_parse() {
+	let nyc = LatLon::from_degrees(40.7128, -74.006);
+	assert_eq!(parse("#14/40.7128/-74.0060"), Ok(View { center: nyc, zoom: Some(14.0) }));
+	assert_eq!(parse("https://www.openstreetmap.org/#map=14/40.7128/-74.0060"), Ok(View { center: nyc, zoom: Some(14.0) }));
+	assert_eq!(parse("geo:40.7128,-74.0060?z=14"), Ok(View { center: nyc, zoom: Some(14.0) }));
+	assert_eq!(parse("geo:40.7128,-74.0060,12;u=35"), Ok(View { center: nyc, zoom: None }));
+	let copied = parse(&fragment(nyc, 9.25)).unwrap();
+	assert_eq!((copied.center, copied.zoom), (nyc, Some(9.25)));
+	for bad in &["", "40.7,-74.0", "#14/40.7", "#x/40.7/-74", "geo:40.7", "geo:95,0", "#99/0/0", "#14/NaN/0"] {
+		assert!(parse(bad).is_err(), "{}", bad);
+	}
+}
+
+#[test]
+fn test_parse_center() {
+	assert_eq!(parse_center("40.7128,-74.006"), Ok(LatLon::from_degrees(40.7128, -74.006)));
+	assert_eq!(parse_center(" -85, 180 "), Ok(LatLon::from_degrees(-85.0, 180.0)));
+	for bad in &["", "40.7", "40.7,", "86,0", "0,180.5", "x,1", "1,2,3"] {
+		assert!(parse_center(bad).is_err(), "{}", bad);
+	}
+	assert_eq!(parse_zoom("12.5"), Ok(12.5));
+	assert!(parse_zoom("31").is_err() && parse_zoom("-1").is_err() && parse_zoom("z").is_err());
+}
+
+#[test]
+fn test_fragment() {
+	assert_eq!(fragment(LatLon::from_degrees(40.7128, -74.006), 14.0), "#14/40.71280/-74.00600");
+	assert_eq!(fragment(LatLon::from_degrees(-33.8688, 151.2093), 9.5), "#9.5/-33.86880/151.20930");
+	assert_eq!(fragment(LatLon::from_degrees(0.0, 0.0), 2.34567), "#2.35/0.00000/0.00000");
+}